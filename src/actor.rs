@@ -0,0 +1,351 @@
+use std::future::{poll_fn, Future};
+use std::hash::Hash;
+use std::pin::{pin, Pin};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_core::{FusedStream, Stream};
+use futures_sink::Sink;
+use tokio::sync::mpsc::error::SendError;
+use tokio::sync::mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender};
+
+use crate::DelayHandler;
+
+/// A cheap, clonable handle to a [`DelayHandler`] that is driven by a background task, created with
+/// [`DelayHandler::spawn()`], or by the [`DelayReceiver`] it was [split](DelayHandler::split) into.
+/// Every operation is passed to the driver as a message, so the client can be moved across task
+/// boundaries freely. It also implements [`Sink`], so that a stream of
+/// `(item, period)` pairs can be forwarded into it.
+///
+/// ### Examples
+/// ```no_run
+/// # use delay_handler::DelayHandler;
+/// # use std::time::Duration;
+/// # async fn run() {
+/// let (client, mut expired) = DelayHandler::default().spawn(16);
+/// client.insert(1, Duration::from_secs(5));
+/// client.insert(2, Duration::from_secs(10));
+/// client.remove(2);
+///
+/// // Prints 1 after ~5s, then ends as the client is dropped and the handler is empty.
+/// drop(client);
+/// while let Some(item) = expired.recv().await {
+///     println!("{}", item);
+/// }
+/// # }
+/// ```
+pub struct DelayClient<T> {
+    commands: UnboundedSender<Command<T>>,
+}
+
+enum Command<T> {
+    Insert(T, Duration),
+    Remove(T),
+    Reset(T, Duration),
+    Clear,
+}
+
+impl<T> DelayClient<T> {
+    /// Insert new timeout if it doesn't already exist. Returns false if the driver has stopped.
+    pub fn insert(&self, item: T, period: Duration) -> bool {
+        self.send(Command::Insert(item, period))
+    }
+
+    /// Prematurely removes timeout from delay-map. Returns false if the driver has stopped.
+    pub fn remove(&self, item: T) -> bool {
+        self.send(Command::Remove(item))
+    }
+
+    /// Updates the deadline of an existing timeout in place, to timeout after `period` from now.
+    /// Returns false if the driver has stopped.
+    pub fn reset(&self, item: T, period: Duration) -> bool {
+        self.send(Command::Reset(item, period))
+    }
+
+    /// Remove all pending timeouts from the delay-map. Returns false if the driver has stopped.
+    pub fn clear(&self) -> bool {
+        self.send(Command::Clear)
+    }
+
+    /// Check if the driver has stopped, after the receiver of expirations was dropped.
+    pub fn is_closed(&self) -> bool {
+        self.commands.is_closed()
+    }
+
+    fn send(&self, command: Command<T>) -> bool {
+        self.commands.send(command).is_ok()
+    }
+}
+
+/// Schedules timeouts forwarded from a producer pipeline. Fails with the timeout that couldn't be
+/// scheduled, if the driver has stopped.
+impl<T> Sink<(T, Duration)> for DelayClient<T> {
+    type Error = SendError<(T, Duration)>;
+
+    fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, (item, period): (T, Duration)) -> Result<(), Self::Error> {
+        match self.commands.send(Command::Insert(item, period)) {
+            Err(SendError(Command::Insert(item, period))) => Err(SendError((item, period))),
+            _ => Ok(()),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T> Clone for DelayClient<T> {
+    fn clone(&self) -> Self {
+        Self {
+            commands: self.commands.clone(),
+        }
+    }
+}
+
+impl<T> DelayHandler<T>
+where
+    T: Eq + Hash + Send + 'static,
+{
+    /// Moves the handler into a background task, returning a [`DelayClient`] for scheduling timeouts
+    /// and a receiver of the items as they timeout, buffering up to `buffer` of them. The task stops
+    /// once the receiver is dropped, or once every client is dropped and all timeouts have expired.
+    ///
+    /// # Panics
+    /// If called outside the context of a tokio runtime, or if `buffer` is zero.
+    pub fn spawn(self, buffer: usize) -> (DelayClient<T>, Receiver<T>) {
+        let (commands, rx) = mpsc::unbounded_channel();
+        let (tx, expired) = mpsc::channel(buffer);
+        tokio::spawn(drive(self, rx, tx));
+
+        (DelayClient { commands }, expired)
+    }
+}
+
+/// The expiry-consuming half of a [`DelayHandler`], that owns it and applies the operations of
+/// the [`DelayClient`] it was [split](DelayHandler::split) from as it is polled, so that timeouts
+/// can be scheduled from one task and consumed in another, without a background task or a lock.
+///
+/// ### Examples
+/// ```no_run
+/// # use delay_handler::DelayHandler;
+/// # use std::time::Duration;
+/// # async fn run() {
+/// let (client, mut expired) = DelayHandler::default().split();
+/// tokio::spawn(async move {
+///     client.insert(1, Duration::from_secs(5));
+///     client.insert(2, Duration::from_secs(10));
+///     client.remove(2);
+/// });
+///
+/// // Prints 1 after ~5s, then ends as the client is dropped and the handler is empty.
+/// while let Some(item) = expired.next().await {
+///     println!("{}", item);
+/// }
+/// # }
+/// ```
+pub struct DelayReceiver<T> {
+    handler: DelayHandler<T>,
+    commands: UnboundedReceiver<Command<T>>,
+    /// Cleared once every client is dropped and all of their operations have been applied.
+    open: bool,
+}
+
+impl<T> DelayReceiver<T>
+where
+    T: Eq + Hash,
+{
+    /// Remove an item once it has timedout and return it, after applying the operations sent so
+    /// far. Waits for clients to insert while the handler is empty, returning `None` only once
+    /// every client has been dropped and all timeouts have expired.
+    pub async fn next(&mut self) -> Option<T> {
+        poll_fn(|cx| self.poll_expired(cx)).await
+    }
+
+    /// Polls for an item that has timedout, after applying the operations sent so far, registering
+    /// the current task for wakeup on the next expiry or operation.
+    pub fn poll_expired(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        while self.open {
+            match self.commands.poll_recv(cx) {
+                Poll::Ready(Some(command)) => apply(&mut self.handler, command),
+                Poll::Ready(None) => self.open = false,
+                Poll::Pending => break,
+            }
+        }
+
+        match self.handler.poll_expired(cx) {
+            Poll::Ready(None) if self.open => Poll::Pending,
+            expired => expired,
+        }
+    }
+
+    /// Remove an item if it has already timedout and return it, after applying the operations sent
+    /// so far, without waiting.
+    pub fn try_next(&mut self) -> Option<T> {
+        while let Ok(command) = self.commands.try_recv() {
+            apply(&mut self.handler, command);
+        }
+
+        self.handler.try_next()
+    }
+
+    /// Returns the handler, as of the operations applied so far.
+    pub fn get_ref(&self) -> &DelayHandler<T> {
+        &self.handler
+    }
+
+    /// Returns the handler for modification, such as to insert timeouts directly.
+    pub fn get_mut(&mut self) -> &mut DelayHandler<T> {
+        &mut self.handler
+    }
+}
+
+impl<T> Stream for DelayReceiver<T>
+where
+    T: Eq + Hash,
+{
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_expired(cx)
+    }
+}
+
+impl<T> FusedStream for DelayReceiver<T>
+where
+    T: Eq + Hash,
+{
+    /// Terminated for good, once every client has been dropped and all timeouts have expired.
+    fn is_terminated(&self) -> bool {
+        !self.open && self.handler.is_empty()
+    }
+}
+
+impl<T> DelayHandler<T>
+where
+    T: Eq + Hash,
+{
+    /// Splits the handler into a [`DelayClient`] for inserting and removing timeouts, that can be
+    /// cloned and moved across tasks, and a [`DelayReceiver`] that consumes them as they expire,
+    /// applying the operations of clients as it is polled.
+    pub fn split(self) -> (DelayClient<T>, DelayReceiver<T>) {
+        let (commands, receiver) = mpsc::unbounded_channel();
+        let receiver = DelayReceiver {
+            handler: self,
+            commands: receiver,
+            open: true,
+        };
+
+        (DelayClient { commands }, receiver)
+    }
+}
+
+/// Applies an operation sent by a client to the handler.
+fn apply<T>(handler: &mut DelayHandler<T>, command: Command<T>)
+where
+    T: Eq + Hash,
+{
+    match command {
+        Command::Insert(item, period) => {
+            handler.insert(item, period);
+        }
+        Command::Remove(item) => {
+            handler.remove(&item);
+        }
+        Command::Reset(item, period) => {
+            handler.reset(&item, period);
+        }
+        Command::Clear => handler.clear(),
+    }
+}
+
+enum Event<T> {
+    Command(Command<T>),
+    Expired(T),
+    Closed,
+}
+
+async fn drive<T>(
+    mut handler: DelayHandler<T>,
+    mut commands: UnboundedReceiver<Command<T>>,
+    expired: Sender<T>,
+) where
+    T: Eq + Hash,
+{
+    let mut closed = pin!(expired.closed());
+    loop {
+        let event = poll_fn(|cx| {
+            if closed.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(Event::Closed);
+            }
+
+            match commands.poll_recv(cx) {
+                Poll::Ready(Some(command)) => return Poll::Ready(Event::Command(command)),
+                Poll::Ready(None) if handler.is_empty() => return Poll::Ready(Event::Closed),
+                _ => {}
+            }
+
+            match handler.poll_expired(cx) {
+                Poll::Ready(Some(item)) => Poll::Ready(Event::Expired(item)),
+                // Nothing to expire until the next command
+                _ => Poll::Pending,
+            }
+        })
+        .await;
+
+        match event {
+            Event::Command(command) => apply(&mut handler, command),
+            Event::Expired(item) => {
+                if expired.send(item).await.is_err() {
+                    break;
+                }
+            }
+            Event::Closed => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+
+    use futures::task::noop_waker_ref;
+    use futures_core::FusedStream;
+
+    use crate::{DelayHandler, MockClock};
+
+    #[test]
+    fn applies_operations_of_split_clients() {
+        let clock = MockClock::new();
+        let handler = DelayHandler::builder().clock(clock.clone()).build();
+        let (client, mut expired) = handler.split();
+        let other = client.clone();
+        client.insert(1, Duration::from_secs(1));
+        client.insert(2, Duration::from_secs(2));
+        other.insert(3, Duration::from_secs(1));
+        other.remove(2);
+        assert_eq!(expired.try_next(), None);
+        assert_eq!(expired.get_ref().len(), 2);
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(expired.try_next(), Some(1));
+        assert_eq!(expired.try_next(), Some(3));
+
+        // Waits for clients to insert while empty, until every one of them is dropped
+        let mut cx = Context::from_waker(noop_waker_ref());
+        assert_eq!(expired.poll_expired(&mut cx), Poll::Pending);
+        drop(client);
+        assert_eq!(expired.poll_expired(&mut cx), Poll::Pending);
+        drop(other);
+        assert_eq!(expired.poll_expired(&mut cx), Poll::Ready(None));
+        assert!(expired.is_terminated());
+    }
+}
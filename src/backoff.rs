@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+/// Delay beyond which it stops growing by default, so that timeouts that keep being re-armed
+/// without a limit on attempts still expire every so often.
+const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// A policy by which the delay of a timeout grows after each expiry, see
+/// [`insert_with_backoff()`](crate::DelayHandler::insert_with_backoff).
+///
+/// ### Examples
+/// Start with a delay of 1s, doubling upto a maximum of 1 minute, giving up after 10 attempts
+/// ```
+/// # use delay_handler::Backoff;
+/// # use std::time::Duration;
+/// let backoff = Backoff::new(Duration::from_secs(1))
+///     .multiplier(2.0)
+///     .max_delay(Duration::from_secs(60))
+///     .max_attempts(10);
+///
+/// assert_eq!(backoff.delay(1), Duration::from_secs(1));
+/// assert_eq!(backoff.delay(3), Duration::from_secs(4));
+/// assert_eq!(backoff.delay(8), Duration::from_secs(60));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Backoff {
+    initial: Duration,
+    multiplier: f64,
+    max_delay: Duration,
+    max_attempts: Option<u32>,
+}
+
+impl Backoff {
+    /// Creates a new policy, with `initial` as the delay before the first attempt, that doubles
+    /// after every attempt upto an hour, without limiting the number of attempts.
+    pub fn new(initial: Duration) -> Self {
+        Self {
+            initial,
+            multiplier: 2.0,
+            max_delay: DEFAULT_MAX_DELAY,
+            max_attempts: None,
+        }
+    }
+
+    /// Sets the factor by which the delay grows after every attempt.
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Sets the delay beyond which it stops growing, an hour by default.
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Sets the number of attempts after which the timeout is no longer re-armed.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+
+    /// Returns the delay before the given `attempt`, counting from 1.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let delay = self.initial.as_secs_f64() * self.multiplier.powi(exponent);
+
+        Duration::try_from_secs_f64(delay)
+            .unwrap_or(Duration::MAX)
+            .min(self.max_delay)
+    }
+
+    /// Returns true if there should be another attempt, after the given `attempt`.
+    pub fn should_retry(&self, attempt: u32) -> bool {
+        self.max_attempts.is_none_or(|max| attempt < max)
+    }
+}
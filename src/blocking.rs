@@ -0,0 +1,296 @@
+use std::borrow::Borrow;
+use std::collections::VecDeque;
+use std::hash::Hash;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::clock::{Clock, Sleep};
+use crate::DelayHandler;
+
+/// A [`DelayHandler`] for code that isn't async, driven by a background thread instead of a runtime,
+/// with blocking [`recv()`](SyncDelayHandler::recv) in place of `next().await`.
+///
+/// Every method takes `&self`, so that the handler can be shared between threads in an [`Arc`],
+/// to insert and remove timeouts from many threads while receiving from one or more others. The
+/// background thread moves timeouts to the receiving end as they expire and stops once the handler
+/// is dropped.
+///
+/// ### Examples
+/// ```no_run
+/// # use delay_handler::SyncDelayHandler;
+/// # use std::time::Duration;
+/// let handler = SyncDelayHandler::default();
+/// handler.insert(1, Duration::from_secs(5));
+/// handler.insert(2, Duration::from_secs(10));
+/// handler.remove(&2);
+///
+/// // Blocks for ~5s before printing 1, then ends as the handler is empty.
+/// while let Some(expired) = handler.recv() {
+///     println!("{}", expired);
+/// }
+/// ```
+pub struct SyncDelayHandler<T> {
+    shared: Arc<Shared<T>>,
+    driver: Option<JoinHandle<()>>,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    /// Notified on every change, both to the driver and to threads waiting to receive.
+    changed: Condvar,
+}
+
+struct State<T> {
+    handler: DelayHandler<T>,
+    /// Items that have timedout, yet to be received.
+    expired: VecDeque<T>,
+    /// Set once the handler is dropped, for the driver to stop.
+    closed: bool,
+}
+
+/// Tells time with the standard library, even on threads of a tokio runtime with paused time. The
+/// driver only ever takes timeouts that are already due and waits for the next deadline on a
+/// condvar, so that the handler is never polled and its sleeps are never awaited.
+#[derive(Debug)]
+struct ThreadClock;
+
+impl Clock for ThreadClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep_until(&self, _: Instant) -> Sleep {
+        Box::pin(std::future::pending())
+    }
+}
+
+impl<T> SyncDelayHandler<T>
+where
+    T: Eq + Hash + Send + 'static,
+{
+    /// Creates an empty handler, with space for at least `capacity` timeouts before reallocating,
+    /// spawning the thread that drives it.
+    ///
+    /// # Panics
+    /// If the thread can't be spawned.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let handler = DelayHandler::builder()
+            .capacity(capacity)
+            .clock(ThreadClock)
+            .build();
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                handler,
+                expired: VecDeque::new(),
+                closed: false,
+            }),
+            changed: Condvar::new(),
+        });
+
+        let driver = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("delay-handler".to_owned())
+                .spawn(move || drive(&shared))
+                .unwrap()
+        };
+
+        Self {
+            shared,
+            driver: Some(driver),
+        }
+    }
+}
+
+impl<T> SyncDelayHandler<T>
+where
+    T: Eq + Hash,
+{
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.shared.state.lock().unwrap()
+    }
+
+    /// Runs `f` with the state locked, notifying the driver and waiting threads of the change.
+    fn update<R>(&self, f: impl FnOnce(&mut State<T>) -> R) -> R {
+        let result = f(&mut self.lock());
+        self.shared.changed.notify_all();
+
+        result
+    }
+
+    /// Insert new timeout if it doesn't already exist. If one already exists, don't insert and
+    /// return false.
+    pub fn insert(&self, item: T, period: Duration) -> bool {
+        self.update(|state| state.handler.insert(item, period))
+    }
+
+    /// Prematurely removes timeout from the handler. If it didn't exist returns false.
+    pub fn remove<Q>(&self, item: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.update(|state| state.handler.remove(item))
+    }
+
+    /// Updates the deadline of an existing timeout in place, to timeout after `period` from now.
+    /// If it didn't exist returns false.
+    pub fn reset<Q>(&self, item: &Q, period: Duration) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.update(|state| state.handler.reset(item, period))
+    }
+
+    /// Remove all pending timeouts, along with items that have timedout but are yet to be received.
+    pub fn clear(&self) {
+        self.update(|state| {
+            state.handler.clear();
+            state.expired.clear();
+        })
+    }
+
+    /// Blocks until an item times out, then returns it. Returns `None` once there are no timeouts
+    /// left to wait for, as with [`DelayHandler::next()`].
+    pub fn recv(&self) -> Option<T> {
+        let mut state = self.lock();
+        loop {
+            if let Some(item) = state.expired.pop_front() {
+                return Some(item);
+            }
+            if state.handler.is_empty() {
+                return None;
+            }
+
+            state = self.shared.changed.wait(state).unwrap();
+        }
+    }
+
+    /// Blocks for upto `timeout` for an item to timeout, then returns it. Returns `None` if none
+    /// has by then, or if there are no timeouts left to wait for.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<T> {
+        let Some(until) = Instant::now().checked_add(timeout) else {
+            return self.recv();
+        };
+        let mut state = self.lock();
+        loop {
+            if let Some(item) = state.expired.pop_front() {
+                return Some(item);
+            }
+            let now = Instant::now();
+            if state.handler.is_empty() || now >= until {
+                return None;
+            }
+
+            state = self
+                .shared
+                .changed
+                .wait_timeout(state, until - now)
+                .unwrap()
+                .0;
+        }
+    }
+
+    /// Returns an item if it has already timedout, without blocking.
+    pub fn try_recv(&self) -> Option<T> {
+        self.lock().expired.pop_front()
+    }
+
+    /// Check if a timeout is currently scheduled for `item`.
+    pub fn contains<Q>(&self, item: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.lock().handler.contains(item)
+    }
+
+    /// Check if no timeouts are pending, nor have any timedout without having been received.
+    pub fn is_empty(&self) -> bool {
+        let state = self.lock();
+
+        state.handler.is_empty() && state.expired.is_empty()
+    }
+
+    /// Number of timeouts that are yet to timeout.
+    pub fn len(&self) -> usize {
+        self.lock().handler.len()
+    }
+}
+
+impl<T> Default for SyncDelayHandler<T>
+where
+    T: Eq + Hash + Send + 'static,
+{
+    fn default() -> Self {
+        Self::with_capacity(0)
+    }
+}
+
+impl<T> Drop for SyncDelayHandler<T> {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.closed = true;
+        }
+        self.shared.changed.notify_all();
+
+        if let Some(driver) = self.driver.take() {
+            let _ = driver.join();
+        }
+    }
+}
+
+/// Moves items to the receiving end as they timeout, sleeping until the next deadline or change,
+/// until the handler is dropped.
+fn drive<T>(shared: &Shared<T>)
+where
+    T: Eq + Hash,
+{
+    let mut state = shared.state.lock().unwrap();
+    while !state.closed {
+        let mut expired = false;
+        while let Some(item) = state.handler.try_next() {
+            state.expired.push_back(item);
+            expired = true;
+        }
+        if expired {
+            shared.changed.notify_all();
+        }
+
+        state = match state.handler.next_deadline() {
+            Some(deadline) => {
+                let timeout = deadline.saturating_duration_since(ThreadClock.now());
+                shared.changed.wait_timeout(state, timeout).unwrap().0
+            }
+            _ => shared.changed.wait(state).unwrap(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::SyncDelayHandler;
+
+    #[test]
+    fn receives_within_the_timeout() {
+        let handler = SyncDelayHandler::default();
+        handler.insert(1, Duration::from_millis(20));
+        handler.insert(2, Duration::from_secs(60));
+        assert_eq!(handler.recv_timeout(Duration::from_millis(1)), None);
+
+        let start = Instant::now();
+        assert_eq!(handler.recv_timeout(Duration::from_secs(10)), Some(1));
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        // Returns right away once there are no timeouts left to wait for
+        handler.remove(&2);
+        let start = Instant::now();
+        assert_eq!(handler.recv_timeout(Duration::from_secs(60)), None);
+        assert_eq!(handler.recv_timeout(Duration::MAX), None);
+        assert!(start.elapsed() < Duration::from_secs(60));
+    }
+}
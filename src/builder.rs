@@ -0,0 +1,248 @@
+use std::hash::{BuildHasher, Hash};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{Clock, DefaultHashBuilder, DelayHandler, DelayMap};
+
+/// Describes what happens on inserting a timeout that already exists.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Duplicates {
+    /// Leave the existing timeout as is, dropping the new one.
+    #[default]
+    Ignore,
+    /// Reschedule the existing timeout to the new deadline, replacing its payload. Inserts still
+    /// return false, as no timeout was newly inserted.
+    Reset,
+}
+
+/// Describes what happens on inserting a new timeout into a delay-map that is already full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Drop the new timeout.
+    Reject,
+    /// Make room by dropping the pending timeout that is to expire next.
+    EvictSoonest,
+    /// Make room by dropping the pending timeout that is to expire last.
+    EvictLatest,
+}
+
+/// The data structure that timeouts are scheduled on, see [`DelayHandlerBuilder::backend()`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// Tokio's [`DelayQueue`](tokio_util::time::DelayQueue), or a sorted queue that waits on the
+    /// sleeps of the [configured clock](DelayHandlerBuilder::clock) or the enabled backend.
+    #[default]
+    Queue,
+    /// A hierarchical timing wheel with 1ms ticks, unless configured with a
+    /// [resolution](DelayHandlerBuilder::resolution), that inserts into and removes from its slots in
+    /// constant time, no matter how many timeouts are pending. Deadlines are rounded up to the next
+    /// tick, as with the [`DelayQueue`](tokio_util::time::DelayQueue).
+    TimingWheel,
+}
+
+/// The clock that the periods of timeouts are measured against, see
+/// [`DelayHandlerBuilder::clock_mode()`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ClockMode {
+    /// Measure periods on the monotonic clock, which never jumps when the wall clock is adjusted.
+    /// Depending on the platform, it doesn't advance while the machine is suspended, e.g. on Linux,
+    /// pushing every timeout back by the time spent suspended.
+    #[default]
+    Monotonic,
+    /// Fix the deadline of every timeout on the wall clock as it is scheduled, as with
+    /// [`insert_at_system_time()`](DelayHandler::insert_at_system_time). Timeouts that fell due
+    /// while the machine was suspended expire right after it resumes, while adjusting the time
+    /// moves them along with it. Jumps between the clocks are checked for every second while the
+    /// delay-map is polled, so expiries may be that late.
+    Wall,
+}
+
+/// Describes how timeouts are rescheduled on detecting a gap in time, such as the machine having
+/// been suspended, see [`DelayHandlerBuilder::on_clock_gap()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockGap {
+    /// Count the gap as having passed, expiring every timeout that would have expired within it
+    /// right away, in the order of their deadlines. Those still remaining are moved up by the gap,
+    /// to expire on time by the wall clock.
+    Fire,
+    /// Discount the gap, so that every timeout has the same time remaining as it did before it,
+    /// including those with a wall-clock deadline, which are pushed back by the gap.
+    Postpone,
+}
+
+/// Configures a [`DelayHandler`] or [`DelayMap`] before constructing it, with
+/// [`build()`](DelayHandlerBuilder::build) or [`build_map()`](DelayHandlerBuilder::build_map)
+/// respectively. [`DelayHandler::builder()`] and [`DelayMap::builder()`] are shorthands for
+/// [`DelayHandlerBuilder::new()`].
+///
+/// ### Examples
+/// ```
+/// # use delay_handler::{DelayHandler, DelayHandlerBuilder, Duplicates};
+/// # use std::time::Duration;
+/// let handler: DelayHandler<u64> = DelayHandlerBuilder::new()
+///     .capacity(10_000)
+///     .jitter(Duration::from_millis(500))
+///     .duplicates(Duplicates::Reset)
+///     .build();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct DelayHandlerBuilder {
+    pub(crate) capacity: usize,
+    pub(crate) jitter: Duration,
+    pub(crate) default_delay: Option<Duration>,
+    pub(crate) duplicates: Duplicates,
+    pub(crate) max_entries: Option<(usize, Overflow)>,
+    pub(crate) auto_shrink: Option<usize>,
+    pub(crate) clock: Option<Arc<dyn Clock>>,
+    pub(crate) backend: Backend,
+    pub(crate) resolution: Option<Duration>,
+    pub(crate) clock_mode: ClockMode,
+    pub(crate) clock_gap: Option<(Duration, ClockGap)>,
+    #[cfg(feature = "metrics")]
+    pub(crate) metrics: Option<Vec<(String, String)>>,
+}
+
+impl DelayHandlerBuilder {
+    /// Creates a builder with the default configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Space for at least `capacity` timeouts to be reserved upfront.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Period used by [`insert_default()`](DelayHandler::insert_default), for when every timeout
+    /// uses the same one.
+    pub fn default_delay(mut self, period: Duration) -> Self {
+        self.default_delay = Some(period);
+        self
+    }
+
+    /// Randomize the delay of every timeout inserted with a period, to somewhere between `period`
+    /// and `period + jitter`. Timeouts inserted with an absolute deadline are not affected.
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// What to do on inserting a timeout that already exists, defaults to [`Duplicates::Ignore`].
+    pub fn duplicates(mut self, duplicates: Duplicates) -> Self {
+        self.duplicates = duplicates;
+        self
+    }
+
+    /// Bound the number of pending timeouts to `max`, with `overflow` deciding what happens on
+    /// inserting into a full delay-map. Unbounded by default.
+    pub fn max_entries(mut self, max: usize, overflow: Overflow) -> Self {
+        self.max_entries = Some((max, overflow));
+        self
+    }
+
+    /// Automatically shrink, see [`DelayHandler::set_auto_shrink()`].
+    pub fn auto_shrink(mut self, factor: usize) -> Self {
+        self.auto_shrink = Some(factor);
+        self
+    }
+
+    /// Tell time with `clock` instead of [`TokioClock`](crate::TokioClock), such as a [`MockClock`]
+    /// that tests can advance by hand.
+    ///
+    /// [`MockClock`]: crate::MockClock
+    pub fn clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// Schedule timeouts on `backend`, defaults to [`Backend::Queue`]. Switch to a
+    /// [`Backend::TimingWheel`] when millions of timeouts are pending at once.
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Bucket deadlines by rounding them up to multiples of `resolution`, so that timeouts due
+    /// around the same time share a single wakeup, trading precision for far less timer work.
+    /// Timeouts are then returned by [`next()`](DelayHandler::next) up to `resolution` past their
+    /// deadline, [`try_next()`](DelayHandler::try_next) still returns them as soon as it passes.
+    /// With a [`Backend::TimingWheel`], it is also the length of its ticks.
+    pub fn resolution(mut self, resolution: Duration) -> Self {
+        self.resolution = Some(resolution);
+        self
+    }
+
+    /// Measure the periods of timeouts against the clock of `mode`, defaults to
+    /// [`ClockMode::Monotonic`]. Use [`ClockMode::Wall`] for timeouts that have to expire at a
+    /// given time of day, even across the machine being suspended.
+    pub fn clock_mode(mut self, mode: ClockMode) -> Self {
+        self.clock_mode = mode;
+        self
+    }
+
+    /// Watch for gaps in time longer than `threshold`, handling the timeouts that are pending
+    /// across one as per `policy`. A gap is detected as the wall clock getting ahead of the monotonic
+    /// clock, which doesn't advance while the machine is suspended on Linux and macOS, though on
+    /// Windows it does and suspends go unnoticed. Setting the time forward by more than the threshold
+    /// is mistaken for a gap. The clocks are compared every second while the delay-map is polled,
+    /// or right away with [`resync()`](DelayMap::resync).
+    pub fn on_clock_gap(mut self, threshold: Duration, policy: ClockGap) -> Self {
+        self.clock_gap = Some((threshold, policy));
+        self
+    }
+
+    /// Report activity to the recorder installed with the [`metrics`](::metrics) crate, such as a
+    /// prometheus exporter, with every metric carrying `labels`. Handlers built with the same labels
+    /// are reported together. Counts the timeouts that were inserted, rejected as duplicates, removed
+    /// and expired in `delay_handler_{inserted,duplicates,removed,expired}_total`, along with the
+    /// number pending in `delay_handler_depth` and how late they expire in
+    /// `delay_handler_expiry_lag_seconds`.
+    #[cfg(feature = "metrics")]
+    pub fn metrics<I, K, V>(mut self, labels: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let labels = labels
+            .into_iter()
+            .map(|(key, value)| (key.into(), value.into()));
+        self.metrics = Some(labels.collect());
+        self
+    }
+
+    /// Constructs a [`DelayHandler`] with the configuration.
+    pub fn build<T>(self) -> DelayHandler<T>
+    where
+        T: Eq + Hash,
+    {
+        DelayHandler::from_builder(self, DefaultHashBuilder::default())
+    }
+
+    /// Constructs a [`DelayHandler`] with the configuration, that hashes items with `hasher`.
+    pub fn build_with_hasher<T, S>(self, hasher: S) -> DelayHandler<T, S>
+    where
+        T: Eq + Hash,
+        S: BuildHasher,
+    {
+        DelayHandler::from_builder(self, hasher)
+    }
+
+    /// Constructs a [`DelayMap`] with the configuration.
+    pub fn build_map<K, V>(self) -> DelayMap<K, V>
+    where
+        K: Eq + Hash,
+    {
+        DelayMap::from_builder(self, DefaultHashBuilder::default())
+    }
+
+    /// Constructs a [`DelayMap`] with the configuration, that hashes keys with `hasher`.
+    pub fn build_map_with_hasher<K, V, S>(self, hasher: S) -> DelayMap<K, V, S>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        DelayMap::from_builder(self, hasher)
+    }
+}
@@ -0,0 +1,254 @@
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant, SystemTime};
+
+/// A future that completes once a deadline has passed, as returned by [`Clock::sleep_until()`].
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send + Sync>>;
+
+/// The source of time that a delay-map schedules its timeouts against, configured with
+/// [`DelayHandlerBuilder::clock()`](crate::DelayHandlerBuilder::clock). By default timeouts are
+/// scheduled on a [`DelayQueue`](tokio_util::time::DelayQueue), following the same time as
+/// [`TokioClock`], while with a custom clock they wait on its sleeps instead.
+pub trait Clock: Debug + Send + Sync {
+    /// Returns the current instant.
+    fn now(&self) -> Instant;
+
+    /// Returns the current wall-clock time, that wall-clock deadlines are scheduled against and
+    /// that the monotonic clock is compared with to detect gaps in time. Defaults to the system time.
+    fn system_now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    /// Returns a future that completes once `deadline` has passed, waking the task polling it.
+    fn sleep_until(&self, deadline: Instant) -> Sleep;
+}
+
+/// About 30 years, as with sleeps that tokio never completes, for deadlines that are too far out
+/// to be represented.
+const FAR_FUTURE: Duration = Duration::from_secs(86400 * 365 * 30);
+
+/// Returns the instant `delay` after `instant`, saturating to a far-future instant for delays such
+/// as [`Duration::MAX`] that can't be represented, instead of panicking with an overflow.
+pub(crate) fn saturating_add(instant: Instant, delay: Duration) -> Instant {
+    instant
+        .checked_add(delay)
+        .unwrap_or_else(|| instant + FAR_FUTURE)
+}
+
+/// Tells time with [`tokio::time`], so that timeouts follow the tokio runtime's clock, including
+/// when it is paused with `tokio::time::pause()`.
+///
+/// This is the default clock with the `tokio` feature, with which timeouts can only be inserted,
+/// restored and deserialized from within the context of a tokio runtime. The other clocks have no
+/// such requirement.
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioClock;
+
+#[cfg(feature = "tokio")]
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        tokio::time::Instant::now().into_std()
+    }
+
+    /// The system time, offset by how far the tokio clock has been paused or advanced, so that
+    /// wall-clock deadlines follow it too.
+    fn system_now(&self) -> SystemTime {
+        let (now, real) = (self.now(), Instant::now());
+        let wall = SystemTime::now();
+        match now.checked_duration_since(real) {
+            Some(ahead) => wall.checked_add(ahead),
+            _ => wall.checked_sub(real - now),
+        }
+        .unwrap_or(wall)
+    }
+
+    /// # Panics
+    /// If polled outside the context of a tokio runtime.
+    fn sleep_until(&self, deadline: Instant) -> Sleep {
+        Box::pin(tokio::time::sleep_until(deadline.into()))
+    }
+}
+
+/// Tells time with [`async-io`](async_io) timers, for use with executors such as smol or
+/// async-std. The default when built without tokio, by disabling default features.
+#[cfg(feature = "async-io")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AsyncIoClock;
+
+#[cfg(feature = "async-io")]
+impl Clock for AsyncIoClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Sleep {
+        let timer = async_io::Timer::at(deadline);
+
+        Box::pin(async move {
+            timer.await;
+        })
+    }
+}
+
+/// Tells time with [`futures-timer`](futures_timer) delays, which run on a helper thread of their
+/// own rather than on any executor's reactor, for use in libraries that can't pick a runtime for
+/// their users.
+#[cfg(feature = "futures-timer")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FuturesTimerClock;
+
+#[cfg(feature = "futures-timer")]
+impl Clock for FuturesTimerClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Sleep {
+        let delay = futures_timer::Delay::new(deadline.saturating_duration_since(Instant::now()));
+
+        Box::pin(delay)
+    }
+}
+
+/// Clock of the enabled backend, for delay-maps that haven't been configured with one.
+#[cfg(all(not(feature = "tokio"), feature = "async-io"))]
+pub(crate) fn fallback() -> Arc<dyn Clock> {
+    Arc::new(AsyncIoClock)
+}
+
+/// Clock of the enabled backend, for delay-maps that haven't been configured with one.
+#[cfg(all(
+    not(any(feature = "tokio", feature = "async-io")),
+    feature = "futures-timer"
+))]
+pub(crate) fn fallback() -> Arc<dyn Clock> {
+    Arc::new(FuturesTimerClock)
+}
+
+#[cfg(not(any(feature = "tokio", feature = "async-io", feature = "futures-timer")))]
+compile_error!("a timer backend is required, enable one of the `tokio`, `async-io` or `futures-timer` features");
+
+/// A clock that only moves forward when [advanced](MockClock::advance), so that tests can control
+/// exactly when timeouts expire, without having to sleep for real or pause the tokio runtime.
+/// Its wall-clock time moves along with it, unless [set](MockClock::set_system_time) apart.
+/// Clones share the same time.
+///
+/// ### Examples
+/// ```
+/// # use delay_handler::{DelayHandler, MockClock};
+/// # use std::time::Duration;
+/// # futures::executor::block_on(async {
+/// let clock = MockClock::new();
+/// let mut handler = DelayHandler::builder().clock(clock.clone()).build();
+/// handler.insert(1, Duration::from_secs(60));
+/// assert_eq!(handler.try_next(), None);
+///
+/// clock.advance(Duration::from_secs(60));
+/// assert_eq!(handler.next().await, Some(1));
+/// # });
+/// ```
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    inner: Arc<Mutex<Mock>>,
+}
+
+#[derive(Debug)]
+struct Mock {
+    now: Instant,
+    wall: SystemTime,
+    /// Tasks waiting on a sleep, along with the deadline they are waiting for.
+    sleepers: Vec<(Instant, Waker)>,
+}
+
+impl MockClock {
+    /// Creates a clock that starts at the current instant and system time.
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Mock {
+                now: Instant::now(),
+                wall: SystemTime::now(),
+                sleepers: vec![],
+            })),
+        }
+    }
+
+    /// Moves the clock and its wall-clock time forward by `duration`, waking up every sleep that
+    /// it skips past.
+    pub fn advance(&self, duration: Duration) {
+        let mut mock = self.inner.lock().unwrap();
+        mock.now += duration;
+        mock.wall += duration;
+
+        let now = mock.now;
+        let (ready, pending) = std::mem::take(&mut mock.sleepers)
+            .into_iter()
+            .partition(|&(deadline, _)| deadline <= now);
+        mock.sleepers = pending;
+        drop(mock);
+
+        for (_, waker) in ready {
+            waker.wake();
+        }
+    }
+
+    /// Sets the wall-clock time without moving the monotonic clock, as happens when the machine is
+    /// suspended or the time is adjusted.
+    pub fn set_system_time(&self, wall: SystemTime) {
+        self.inner.lock().unwrap().wall = wall;
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.inner.lock().unwrap().now
+    }
+
+    fn system_now(&self) -> SystemTime {
+        self.inner.lock().unwrap().wall
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Sleep {
+        Box::pin(MockSleep {
+            clock: self.clone(),
+            deadline,
+        })
+    }
+}
+
+struct MockSleep {
+    clock: MockClock,
+    deadline: Instant,
+}
+
+impl Future for MockSleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut mock = self.clock.inner.lock().unwrap();
+        if mock.now >= self.deadline {
+            return Poll::Ready(());
+        }
+
+        let waker = cx.waker();
+        let deadline = self.deadline;
+        if !mock
+            .sleepers
+            .iter()
+            .any(|(d, w)| *d == deadline && w.will_wake(waker))
+        {
+            mock.sleepers.push((deadline, waker.clone()));
+        }
+
+        Poll::Pending
+    }
+}
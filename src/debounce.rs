@@ -0,0 +1,183 @@
+use std::borrow::Borrow;
+use std::future::poll_fn;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_core::{FusedStream, Stream};
+
+use crate::{DelayHandler, Scheduled};
+
+/// Coalesces bursts of repeated items, emitting each only once it has gone quiet.
+///
+/// Every insert of an item that is already pending pushes its deadline out to a full quiet period
+/// from now, so that the item is only yielded by [`next()`](Debouncer::next) once it hasn't been
+/// inserted again for that long. Useful to react once to a flurry of filesystem or configuration
+/// change events.
+///
+/// ### Examples
+/// ```
+/// # use delay_handler::Debouncer;
+/// # use std::time::Duration;
+/// # #[tokio::main(flavor = "current_thread", start_paused = true)]
+/// # async fn main() {
+/// let mut debouncer = Debouncer::new(Duration::from_millis(500));
+/// debouncer.insert("config.toml");
+/// debouncer.insert("config.toml");
+/// debouncer.insert("config.toml");
+///
+/// // Expect a delay of ~500ms, after which "config.toml" is yielded just once.
+/// while let Some(path) = debouncer.next().await {
+///     println!("{path} changed");
+/// }
+/// # }
+/// ```
+pub struct Debouncer<T> {
+    handler: DelayHandler<T>,
+    quiet: Duration,
+}
+
+impl<T> Debouncer<T>
+where
+    T: Eq + Hash,
+{
+    /// Creates an empty debouncer, that emits items once they haven't been inserted for `quiet`.
+    pub fn new(quiet: Duration) -> Self {
+        Self {
+            handler: DelayHandler::default(),
+            quiet,
+        }
+    }
+
+    /// Returns the period for which an item has to go quiet before it is emitted.
+    pub fn quiet_period(&self) -> Duration {
+        self.quiet
+    }
+
+    /// Insert an item to be emitted after the quiet period, pushing its deadline out if it was
+    /// already pending, in which case the pending item is kept and the one provided is dropped.
+    pub fn insert(&mut self, item: T) -> Scheduled {
+        self.handler.insert_or_reset(item, self.quiet)
+    }
+
+    /// Prematurely removes a pending item, so that it isn't emitted. If it didn't exist returns false.
+    pub fn remove<Q>(&mut self, item: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.handler.remove(item)
+    }
+
+    /// Remove an item once it has gone quiet and return it.
+    pub async fn next(&mut self) -> Option<T> {
+        poll_fn(|cx| self.poll_expired(cx)).await
+    }
+
+    /// Polls for an item that has gone quiet, registering the current task for wakeup if none has yet.
+    pub fn poll_expired(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.handler.poll_expired(cx)
+    }
+
+    /// Remove an item if it has already gone quiet and return it, without waiting.
+    pub fn try_next(&mut self) -> Option<T> {
+        self.handler.try_next()
+    }
+
+    /// Remove all pending items, so that none of them are emitted.
+    pub fn clear(&mut self) {
+        self.handler.clear()
+    }
+
+    /// Check if no items are pending.
+    pub fn is_empty(&self) -> bool {
+        self.handler.is_empty()
+    }
+
+    /// Check if an item is pending, yet to go quiet.
+    pub fn contains<Q>(&self, item: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.handler.contains(item)
+    }
+
+    /// Number of items currently pending.
+    pub fn len(&self) -> usize {
+        self.handler.len()
+    }
+}
+
+impl<T> Stream for Debouncer<T>
+where
+    T: Eq + Hash,
+{
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_expired(cx)
+    }
+}
+
+impl<T> FusedStream for Debouncer<T>
+where
+    T: Eq + Hash,
+{
+    fn is_terminated(&self) -> bool {
+        self.handler.is_terminated()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Debouncer;
+    use crate::{DelayHandler, MockClock, Scheduled};
+
+    fn debouncer(clock: &MockClock) -> Debouncer<&'static str> {
+        let mut debouncer = Debouncer::new(Duration::from_millis(500));
+        debouncer.handler = DelayHandler::builder().clock(clock.clone()).build();
+
+        debouncer
+    }
+
+    #[test]
+    fn emits_once_items_go_quiet() {
+        let clock = MockClock::new();
+        let mut debouncer = debouncer(&clock);
+        assert_eq!(debouncer.insert("a"), Scheduled::Inserted);
+        for _ in 0..3 {
+            clock.advance(Duration::from_millis(400));
+            assert_eq!(debouncer.insert("a"), Scheduled::Reset);
+            assert_eq!(debouncer.try_next(), None);
+        }
+
+        clock.advance(Duration::from_millis(499));
+        assert_eq!(debouncer.try_next(), None);
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(debouncer.try_next(), Some("a"));
+        assert_eq!(debouncer.try_next(), None);
+        assert!(debouncer.is_empty());
+    }
+
+    #[test]
+    fn debounces_items_independently() {
+        let clock = MockClock::new();
+        let mut debouncer = debouncer(&clock);
+        debouncer.insert("a");
+        clock.advance(Duration::from_millis(300));
+        debouncer.insert("b");
+        debouncer.insert("c");
+        assert!(debouncer.remove("c"));
+
+        clock.advance(Duration::from_millis(200));
+        assert_eq!(debouncer.try_next(), Some("a"));
+        assert_eq!(debouncer.try_next(), None);
+        clock.advance(Duration::from_millis(300));
+        assert_eq!(debouncer.try_next(), Some("b"));
+        assert_eq!(debouncer.try_next(), None);
+    }
+}
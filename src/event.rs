@@ -0,0 +1,78 @@
+use std::time::Instant;
+
+/// A change in the lifecycle of a timeout, as seen by subscribers of
+/// [`events()`](crate::DelayMap::events), each stamped with the instant it happened at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event<K> {
+    /// A new timeout was inserted, to expire at `deadline`.
+    Inserted {
+        key: K,
+        deadline: Instant,
+        at: Instant,
+    },
+    /// A pending timeout was rescheduled to expire at `deadline`.
+    Reset {
+        key: K,
+        deadline: Instant,
+        at: Instant,
+    },
+    /// A timeout was removed before it could expire, such as by removal, eviction, cancellation,
+    /// clearing or draining.
+    Removed { key: K, at: Instant },
+    /// A timeout that was due at `deadline` expired.
+    Expired {
+        key: K,
+        deadline: Instant,
+        at: Instant,
+    },
+}
+
+impl<K> Event<K> {
+    /// Returns the key of the timeout the event is about.
+    pub fn key(&self) -> &K {
+        match self {
+            Event::Inserted { key, .. }
+            | Event::Reset { key, .. }
+            | Event::Removed { key, .. }
+            | Event::Expired { key, .. } => key,
+        }
+    }
+
+    /// Returns the instant at which the event happened.
+    pub fn at(&self) -> Instant {
+        match self {
+            Event::Inserted { at, .. }
+            | Event::Reset { at, .. }
+            | Event::Removed { at, .. }
+            | Event::Expired { at, .. } => *at,
+        }
+    }
+}
+
+#[cfg(feature = "channels")]
+impl<K: Clone> Event<&K> {
+    /// Maps the event to one that owns a clone of the key.
+    pub(crate) fn cloned(&self) -> Event<K> {
+        match *self {
+            Event::Inserted { key, deadline, at } => Event::Inserted {
+                key: key.clone(),
+                deadline,
+                at,
+            },
+            Event::Reset { key, deadline, at } => Event::Reset {
+                key: key.clone(),
+                deadline,
+                at,
+            },
+            Event::Removed { key, at } => Event::Removed {
+                key: key.clone(),
+                at,
+            },
+            Event::Expired { key, deadline, at } => Event::Expired {
+                key: key.clone(),
+                deadline,
+                at,
+            },
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use std::time::{Duration, Instant};
+
+/// An item that has timedout, along with the deadline it was scheduled for and the instant it was
+/// actually delivered at. Useful to monitor how far behind the consumer of a delay-map is lagging.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Expired<T> {
+    item: T,
+    deadline: Instant,
+    delivered: Instant,
+    attempt: u32,
+}
+
+impl<T> Expired<T> {
+    pub(crate) fn new(item: T, deadline: Instant, delivered: Instant, attempt: u32) -> Self {
+        Self {
+            item,
+            deadline,
+            delivered,
+            attempt,
+        }
+    }
+
+    /// Returns a reference to the timedout item.
+    pub fn get_ref(&self) -> &T {
+        &self.item
+    }
+
+    /// Consumes `self`, returning the timedout item.
+    pub fn into_inner(self) -> T {
+        self.item
+    }
+
+    /// Returns the deadline the item was scheduled to timeout at.
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Returns the instant the item was delivered at.
+    pub fn delivered(&self) -> Instant {
+        self.delivered
+    }
+
+    /// Returns how late the item was delivered, past its deadline.
+    pub fn lateness(&self) -> Duration {
+        self.delivered.saturating_duration_since(self.deadline)
+    }
+
+    /// Returns the number of times the item has timedout, counting from 1. Only ever more than
+    /// one for periodic timeouts and ones with a backoff policy.
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    pub(crate) fn map<U, F: FnOnce(T) -> U>(self, f: F) -> Expired<U> {
+        Expired {
+            item: f(self.item),
+            deadline: self.deadline,
+            delivered: self.delivered,
+            attempt: self.attempt,
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use std::future::poll_fn;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures_core::{FusedStream, Stream};
+
+use crate::clock::saturating_add;
+use crate::{DelayHandlerBuilder, DelayMap};
+
+/// An opaque handle to a delay inserted into a [`HandleQueue`], used to cancel or reschedule it.
+///
+/// Handles are never reused, so a stale handle will not refer to a different delay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DelayHandle(u64);
+
+/// A variant of [`DelayHandler`](crate::DelayHandler) for items that have no meaningful key, such as
+/// boxed trait objects or futures. Items don't need to be `Eq + Hash`, instead inserting returns
+/// a [`DelayHandle`] that can later be used to refer to the delay.
+///
+/// ### Examples
+/// ```no_run
+/// # use delay_handler::HandleQueue;
+/// # use std::time::Duration;
+/// # async fn run() {
+/// let mut queue: HandleQueue<Box<dyn Fn()>> = HandleQueue::default();
+/// queue.insert(Box::new(|| println!("hello")), Duration::from_secs(5));
+/// let handle = queue.insert(Box::new(|| println!("world")), Duration::from_secs(10));
+///
+/// // Cancel the second delay, only "hello" should print after ~5s.
+/// queue.remove(&handle);
+/// while let Some(action) = queue.next().await {
+///     action();
+/// }
+/// # }
+/// ```
+pub struct HandleQueue<T> {
+    map: DelayMap<DelayHandle, T>,
+    next_handle: u64,
+}
+
+impl<T> HandleQueue<T> {
+    /// Creates an empty queue, with space for at least `capacity` delays before reallocating.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::from_builder(DelayHandlerBuilder::new().capacity(capacity))
+    }
+
+    pub(crate) fn from_builder(builder: DelayHandlerBuilder) -> Self {
+        Self {
+            map: builder.build_map(),
+            next_handle: 0,
+        }
+    }
+
+    /// Insert new delay for `item` into the queue, returning a handle that refers to it.
+    pub fn insert(&mut self, item: T, period: Duration) -> DelayHandle {
+        self.insert_at(item, saturating_add(self.map.now(), period))
+    }
+
+    /// Insert new delay for `item` into the queue, to timeout at the given `deadline`, returning
+    /// a handle that refers to it.
+    pub fn insert_at(&mut self, item: T, deadline: Instant) -> DelayHandle {
+        let handle = DelayHandle(self.next_handle);
+        self.next_handle += 1;
+        self.map.insert_at(handle, item, deadline);
+
+        handle
+    }
+
+    /// Prematurely removes delay from the queue, returning the item if it hadn't already timedout.
+    pub fn remove(&mut self, handle: &DelayHandle) -> Option<T> {
+        self.map.remove(handle)
+    }
+
+    /// Prematurely removes delay from the queue, returning the item and time that was remaining,
+    /// if it hadn't already timedout.
+    pub fn take(&mut self, handle: &DelayHandle) -> Option<(T, Duration)> {
+        let (_, item, remaining) = self.map.take(handle)?;
+
+        Some((item, remaining))
+    }
+
+    /// Updates the deadline of an existing delay in place, to timeout after `period` from now.
+    /// If it didn't already exist returns false.
+    pub fn reset(&mut self, handle: &DelayHandle, period: Duration) -> bool {
+        self.map.reset(handle, period)
+    }
+
+    /// Pushes back the deadline of an existing delay, to timeout after the same period it was last
+    /// scheduled with, from now. If it didn't already exist returns false.
+    pub fn touch(&mut self, handle: &DelayHandle) -> bool {
+        self.map.touch(handle)
+    }
+
+    /// Returns the instant at which the delay referred to by `handle` is scheduled to expire.
+    pub fn deadline(&self, handle: &DelayHandle) -> Option<Instant> {
+        self.map.deadline(handle)
+    }
+
+    /// Returns how long until the delay referred to by `handle` expires, zero if it is already due.
+    pub fn time_remaining(&self, handle: &DelayHandle) -> Option<Duration> {
+        self.map.time_remaining(handle)
+    }
+
+    /// Remove an item from the queue if it has timedout and return it.
+    pub async fn next(&mut self) -> Option<T> {
+        poll_fn(|cx| self.poll_expired(cx)).await
+    }
+
+    /// Polls for an item that has timedout, registering the current task for wakeup if none has yet.
+    pub fn poll_expired(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.map
+            .poll_expired(cx)
+            .map(|expired| expired.map(|(_, item)| item))
+    }
+
+    /// Remove an item from the queue if it has already timedout and return it, without waiting.
+    pub fn try_next(&mut self) -> Option<T> {
+        let (_, item) = self.map.try_next()?;
+
+        Some(item)
+    }
+
+    /// Remove all pending delays from the queue, dropping their items.
+    pub fn clear(&mut self) {
+        self.map.clear()
+    }
+
+    /// Check if queue is empty.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Check if the delay referred to by `handle` is still pending.
+    pub fn contains(&self, handle: &DelayHandle) -> bool {
+        self.map.contains(handle)
+    }
+
+    /// Number of delays currently pending in the queue.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+}
+
+impl<T> Default for HandleQueue<T> {
+    fn default() -> Self {
+        Self::with_capacity(0)
+    }
+}
+
+impl<T> Stream for HandleQueue<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_expired(cx)
+    }
+}
+
+impl<T> FusedStream for HandleQueue<T> {
+    fn is_terminated(&self) -> bool {
+        self.map.is_terminated()
+    }
+}
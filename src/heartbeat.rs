@@ -0,0 +1,221 @@
+use std::borrow::Borrow;
+use std::future::poll_fn;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_core::{FusedStream, Stream};
+
+use crate::{DelayHandler, Scheduled};
+
+/// Detects peers that have gone silent, such as dead connections, by the heartbeats they send.
+///
+/// Each peer is [watched](HeartbeatMonitor::watch) with its own interval, within which it has to
+/// [beat](HeartbeatMonitor::beat) to be considered alive. Peers that miss their window are yielded
+/// by [`next()`](HeartbeatMonitor::next) and are no longer watched, until watched again.
+///
+/// ### Examples
+/// ```no_run
+/// # use delay_handler::HeartbeatMonitor;
+/// # use std::time::Duration;
+/// # async fn run() {
+/// let mut monitor = HeartbeatMonitor::default();
+/// monitor.watch("device-1", Duration::from_secs(30));
+/// monitor.watch("device-2", Duration::from_secs(60));
+///
+/// // Heartbeats keep a peer alive for another interval.
+/// monitor.beat("device-1");
+///
+/// while let Some(peer) = monitor.next().await {
+///     println!("{peer} is dead");
+/// }
+/// # }
+/// ```
+pub struct HeartbeatMonitor<K> {
+    handler: DelayHandler<K>,
+}
+
+impl<K> HeartbeatMonitor<K>
+where
+    K: Eq + Hash,
+{
+    /// Creates an empty monitor, with space for at least `capacity` peers before reallocating.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            handler: DelayHandler::with_capacity(capacity),
+        }
+    }
+
+    /// Start watching a peer, that has to beat within every `interval` to be considered alive.
+    /// If the peer is already watched, its interval is updated and its window starts over, in
+    /// which case the provided key is dropped.
+    pub fn watch(&mut self, peer: K, interval: Duration) -> Scheduled {
+        self.handler.insert_or_reset(peer, interval)
+    }
+
+    /// Records a heartbeat from a peer, starting its window over. If the peer isn't watched,
+    /// possibly as it was already declared dead, returns false.
+    pub fn beat<Q>(&mut self, peer: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.handler.touch(peer)
+    }
+
+    /// Stops watching a peer, such as when its connection is closed gracefully. If it wasn't
+    /// watched returns false.
+    pub fn unwatch<Q>(&mut self, peer: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.handler.remove(peer)
+    }
+
+    /// Returns how long a peer has left to beat before it is declared dead, zero if it is already due.
+    pub fn time_remaining<Q>(&self, peer: &Q) -> Option<Duration>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.handler.time_remaining(peer)
+    }
+
+    /// Remove a peer once it has missed its window and return it.
+    pub async fn next(&mut self) -> Option<K> {
+        poll_fn(|cx| self.poll_expired(cx)).await
+    }
+
+    /// Polls for a peer that has missed its window, registering the current task for wakeup if none
+    /// has yet.
+    pub fn poll_expired(&mut self, cx: &mut Context<'_>) -> Poll<Option<K>> {
+        self.handler.poll_expired(cx)
+    }
+
+    /// Remove a peer if it has already missed its window and return it, without waiting.
+    pub fn try_next(&mut self) -> Option<K> {
+        self.handler.try_next()
+    }
+
+    /// Stop watching all peers.
+    pub fn clear(&mut self) {
+        self.handler.clear()
+    }
+
+    /// Check if no peers are watched.
+    pub fn is_empty(&self) -> bool {
+        self.handler.is_empty()
+    }
+
+    /// Check if a peer is watched, i.e. hasn't been declared dead.
+    pub fn contains<Q>(&self, peer: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.handler.contains(peer)
+    }
+
+    /// Number of peers currently watched.
+    pub fn len(&self) -> usize {
+        self.handler.len()
+    }
+}
+
+impl<K> Default for HeartbeatMonitor<K>
+where
+    K: Eq + Hash,
+{
+    fn default() -> Self {
+        Self::with_capacity(0)
+    }
+}
+
+impl<K> Stream for HeartbeatMonitor<K>
+where
+    K: Eq + Hash,
+{
+    type Item = K;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_expired(cx)
+    }
+}
+
+impl<K> FusedStream for HeartbeatMonitor<K>
+where
+    K: Eq + Hash,
+{
+    fn is_terminated(&self) -> bool {
+        self.handler.is_terminated()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::HeartbeatMonitor;
+    use crate::{DelayHandler, MockClock, Scheduled};
+
+    fn monitor(clock: &MockClock) -> HeartbeatMonitor<&'static str> {
+        HeartbeatMonitor {
+            handler: DelayHandler::builder().clock(clock.clone()).build(),
+        }
+    }
+
+    #[test]
+    fn declares_peers_dead_once_they_miss_a_beat() {
+        let clock = MockClock::new();
+        let mut monitor = monitor(&clock);
+        monitor.watch("a", Duration::from_secs(10));
+        monitor.watch("b", Duration::from_secs(30));
+
+        clock.advance(Duration::from_secs(8));
+        assert!(monitor.beat("a"));
+        clock.advance(Duration::from_secs(8));
+        assert_eq!(monitor.try_next(), None);
+        assert_eq!(monitor.time_remaining("a"), Some(Duration::from_secs(2)));
+
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(monitor.try_next(), Some("a"));
+        assert_eq!(monitor.try_next(), None);
+        assert!(!monitor.beat("a"));
+        assert!(monitor.contains("b"));
+    }
+
+    #[test]
+    fn rewatching_starts_the_window_over() {
+        let clock = MockClock::new();
+        let mut monitor = monitor(&clock);
+        assert_eq!(
+            monitor.watch("a", Duration::from_secs(10)),
+            Scheduled::Inserted
+        );
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(
+            monitor.watch("a", Duration::from_secs(20)),
+            Scheduled::Reset
+        );
+
+        clock.advance(Duration::from_secs(19));
+        assert_eq!(monitor.try_next(), None);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(monitor.try_next(), Some("a"));
+    }
+
+    #[test]
+    fn unwatched_peers_are_never_declared_dead() {
+        let clock = MockClock::new();
+        let mut monitor = monitor(&clock);
+        monitor.watch("a", Duration::from_secs(10));
+        assert!(monitor.unwatch("a"));
+        assert!(!monitor.unwatch("a"));
+
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(monitor.try_next(), None);
+        assert!(monitor.is_empty());
+    }
+}
@@ -1,17 +1,163 @@
-use std::collections::{hash_map::Entry, HashMap};
-use std::fmt::Display;
-use std::hash::Hash;
-use std::time::Duration;
+use std::borrow::Borrow;
+use std::fmt::{self, Debug, Formatter};
+use std::hash::{BuildHasher, Hash};
+use std::pin::Pin;
+#[cfg(feature = "persistent")]
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
 
-use tokio_stream::StreamExt;
-use tokio_util::time::{delay_queue::Key, DelayQueue};
+use futures_core::{FusedStream, Stream};
+#[cfg(feature = "channels")]
+use tokio::sync::{broadcast, watch};
+#[cfg(feature = "tokio")]
+use tokio_util::sync::CancellationToken;
 
-/// An abstration over [`DelayQueue`] that allows you to create a delay, with associated data.
+use crate::map::Listed;
+#[cfg(feature = "channels")]
+use crate::map::BROADCAST_CAPACITY;
+#[cfg(feature = "persistent")]
+use crate::persistent::Wal;
+
+#[cfg(feature = "tokio")]
+mod actor;
+mod backoff;
+mod blocking;
+mod builder;
+mod clock;
+mod debounce;
+mod event;
+mod expired;
+mod handle;
+mod heartbeat;
+mod map;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod observer;
+#[cfg(feature = "channels")]
+mod pending;
+#[cfg(feature = "persistent")]
+mod persistent;
+mod queue;
+mod rand;
+mod rate;
+mod retry;
+#[cfg(feature = "serde")]
+mod serialize;
+mod session;
+mod shared;
+mod stats;
+mod stream;
+#[cfg(feature = "test-util")]
+pub mod testing;
+mod throttle;
+mod timer;
+#[cfg(feature = "tracing")]
+mod trace;
+#[cfg(feature = "channels")]
+mod wait;
+mod wheel;
+
+#[cfg(feature = "tokio")]
+pub use actor::{DelayClient, DelayReceiver};
+pub use backoff::Backoff;
+pub use blocking::SyncDelayHandler;
+pub use builder::{Backend, ClockGap, ClockMode, DelayHandlerBuilder, Duplicates, Overflow};
+#[cfg(feature = "async-io")]
+pub use clock::AsyncIoClock;
+#[cfg(feature = "futures-timer")]
+pub use clock::FuturesTimerClock;
+#[cfg(feature = "tokio")]
+pub use clock::TokioClock;
+pub use clock::{Clock, MockClock, Sleep};
+pub use debounce::Debouncer;
+pub use event::Event;
+pub use expired::Expired;
+pub use handle::{DelayHandle, HandleQueue};
+pub use heartbeat::HeartbeatMonitor;
+pub use map::{DefaultHashBuilder, DelayMap, Entry, OccupiedEntry, VacantEntry};
+pub use observer::Observer;
+#[cfg(feature = "channels")]
+pub use pending::PendingRequests;
+#[cfg(feature = "persistent")]
+pub use persistent::{Fsync, PersistentDelayHandler};
+pub use rate::{Acquire, RateLimiter};
+pub use retry::{RetryPolicy, RetryQueue};
+pub use session::SessionExpiry;
+pub use shared::SharedDelayHandler;
+pub use stats::Stats;
+pub use stream::IntoStream;
+pub use throttle::{Edge, Throttle};
+#[cfg(feature = "channels")]
+pub use wait::{Fate, WaitFor};
+
+/// Describes what happened on calling [`insert_or_reset()`](DelayHandler::insert_or_reset).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheduled {
+    /// A new timeout was added to the delay-map.
+    Inserted,
+    /// A timeout already existed and has been rescheduled.
+    Reset,
+    /// The delay-map is full, so the timeout couldn't be added.
+    Rejected,
+}
+
+/// Describes how a periodic timeout is re-armed after each expiry, see
+/// [`insert_periodic()`](DelayHandler::insert_periodic).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Periodic {
+    /// Re-arm relative to the deadline that just expired, so that a slow consumer receives
+    /// every tick, albeit late.
+    FixedRate,
+    /// Re-arm relative to the instant the expiry was delivered at.
+    FixedDelay,
+}
+
+/// Level of pending timeouts to be notified about, see
+/// [`watch_watermark()`](DelayHandler::watch_watermark).
+#[cfg(feature = "channels")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watermark {
+    /// An absolute number of pending timeouts.
+    Count(usize),
+    /// A percentage of the [bound](DelayHandler::set_max_entries) on pending timeouts, rounded up,
+    /// that is never reached while unbounded.
+    Percent(u8),
+}
+
+/// Describes how the remaining timeouts are handed out on shutting down, see
+/// [`shutdown()`](DelayHandler::shutdown).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shutdown {
+    /// Hand out all remaining timeouts right away, without waiting for them to expire.
+    Immediate,
+    /// Wait for every remaining timeout to expire, resuming time and thawing frozen timeouts first,
+    /// so that none are left waiting indefinitely.
+    Graceful,
+}
+
+/// An abstration over [`DelayQueue`](tokio_util::time::DelayQueue) that allows you to create a delay, with associated data.
 ///
 /// Users can add data to the delay-map with [`insert()`](DelayHandler::insert). The associated data
 /// is removed and returned when delay is timedout by `.await`ing on [`next()`](DelayHandler::next).
 /// Users can also prematurely remove the delay from the delay-map with [`remove()`](DelayHandler::remove).
 ///
+/// Items are stored only once, no matter how large. The queue only refers to them by the index of
+/// the slot holding them, so there is no need to wrap long keys such as topic strings in an `Arc`
+/// to avoid keeping a copy for the map and another for the queue.
+///
+/// Timeouts are yielded in the order of their deadlines and those that share a deadline, such as
+/// when inserted with the same [`insert_at()`](DelayHandler::insert_at) instant, in the order
+/// they were inserted, no matter which backend they are scheduled on. Only a higher
+/// [priority](DelayHandler::insert_with_priority) gets a timeout yielded ahead of others that are
+/// already due.
+///
+/// The handler also implements [`Stream`], so that it can be used with `StreamExt` combinators and
+/// `StreamMap`. NOTE: the stream yields `None` whenever the delay-map is empty, but will yield items
+/// again after new inserts. As a [`FusedStream`] it only reports itself as terminated once it can
+/// never yield again, when [closed](DelayHandler::close) and empty. Use
+/// [`into_stream()`](DelayHandler::into_stream) for an owned stream that ends for good.
+///
 /// ### Examples
 /// 1. Insert 3 numbers into delay-map with 10s delays, print them as they timeout
 /// ```no_run
@@ -57,7 +203,7 @@ use tokio_util::time::{delay_queue::Key, DelayQueue};
 /// handler.insert(1, Duration::from_secs(15));
 /// handler.insert(2, Duration::from_secs(5));
 /// handler.insert(3, Duration::from_secs(10));
-/// 
+///
 /// // Remove 3 from the delay-map
 /// handler.remove(&3);
 ///
@@ -67,49 +213,844 @@ use tokio_util::time::{delay_queue::Key, DelayQueue};
 /// }
 /// # }
 /// ```
-pub struct DelayHandler<T> {
-    queue: DelayQueue<T>,
-    map: HashMap<T, Key>,
+pub struct DelayHandler<T, S = DefaultHashBuilder> {
+    map: DelayMap<T, (), S>,
+    /// Journal that mutations are appended to, if built with one.
+    #[cfg(feature = "persistent")]
+    journal: Option<Arc<Mutex<Wal>>>,
 }
 
 impl<T> DelayHandler<T>
 where
-    T: Eq + Hash + Clone + Display,
+    T: Eq + Hash,
+{
+    /// Creates an empty handler, with space for at least `capacity` timeouts before reallocating.
+    pub fn with_capacity(capacity: usize) -> Self {
+        DelayHandlerBuilder::new().capacity(capacity).build()
+    }
+}
+
+impl<T, S> DelayHandler<T, S>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
 {
+    /// Creates an empty handler, that hashes items with `hasher`, such as a faster one than the
+    /// default for items that can't be chosen by an attacker.
+    ///
+    /// ### Examples
+    /// ```
+    /// # use delay_handler::DelayHandler;
+    /// # use std::collections::hash_map::RandomState;
+    /// # use std::time::Duration;
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let mut handler = DelayHandler::with_hasher(RandomState::new());
+    /// handler.insert(1, Duration::from_secs(1));
+    /// # }
+    /// ```
+    pub fn with_hasher(hasher: S) -> Self {
+        Self::with_capacity_and_hasher(0, hasher)
+    }
+
+    /// Creates an empty handler, with space for at least `capacity` timeouts before reallocating,
+    /// that hashes items with `hasher`.
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        DelayHandlerBuilder::new()
+            .capacity(capacity)
+            .build_with_hasher(hasher)
+    }
+
+    /// Returns a reference to the hasher that items are hashed with.
+    pub fn hasher(&self) -> &S {
+        self.map.hasher()
+    }
+
+    pub(crate) fn from_builder(builder: DelayHandlerBuilder, hasher: S) -> Self {
+        Self {
+            map: DelayMap::from_builder(builder, hasher),
+            #[cfg(feature = "persistent")]
+            journal: None,
+        }
+    }
+
+    /// Reserves space for at least `additional` more timeouts to be inserted without reallocating.
+    /// Useful ahead of inserting a large burst of timeouts.
+    pub fn reserve(&mut self, additional: usize) {
+        self.map.reserve(additional)
+    }
+
     /// Insert new timeout into the map and queue if it doesn't already exist.
-    /// If one already exists, don't .
+    /// If one already exists, don't insert and return false.
+    /// A timeout with a zero `period` is due right away, to be yielded on the next poll without
+    /// waiting on a timer.
+    ///
+    /// Returns true only if the timeout was newly inserted. With [`Duplicates::Reset`] an existing
+    /// timeout is rescheduled instead, which also returns false, as it does for every other method
+    /// that inserts.
     pub fn insert(&mut self, item: T, period: Duration) -> bool {
-        match self.map.entry(item.clone()) {
-            Entry::Vacant(v) => {
-                let key = self.queue.insert(item, period);
-                v.insert(key);
-
-                true
-            }
-            _ => false,
-        }
+        self.map.insert(item, (), period)
+    }
+
+    /// Insert new timeout into the map and queue if it doesn't already exist, with the
+    /// [default delay](DelayHandler::set_default_delay). Avoids threading the same period through
+    /// every call site, when all timeouts use it.
+    ///
+    /// # Panics
+    /// If no default delay has been set.
+    pub fn insert_default(&mut self, item: T) -> bool {
+        self.map.insert_default(item, ())
+    }
+
+    /// Sets the period used by [`insert_default()`](DelayHandler::insert_default).
+    pub fn set_default_delay(&mut self, period: Duration) {
+        self.map.set_default_delay(period)
+    }
+
+    /// Returns the period used by [`insert_default()`](DelayHandler::insert_default), if set.
+    pub fn default_delay(&self) -> Option<Duration> {
+        self.map.default_delay()
+    }
+
+    /// Insert new timeout into the map and queue if it doesn't already exist, with the delay randomized
+    /// to somewhere between `period` and `period + jitter`. Useful to avoid thundering-herd expirations,
+    /// when scheduling a lot of retries together.
+    pub fn insert_with_jitter(&mut self, item: T, period: Duration, jitter: Duration) -> bool {
+        self.map.insert_with_jitter(item, (), period, jitter)
+    }
+
+    /// Insert new timeout into the map and queue, to timeout at the given `deadline`, if it doesn't
+    /// already exist. Useful when deadlines are computed elsewhere, as it avoids the drift that comes
+    /// with converting them back into a [`Duration`] relative to now.
+    pub fn insert_at(&mut self, item: T, deadline: Instant) -> bool {
+        self.map.insert_at(item, (), deadline)
+    }
+
+    /// Insert new timeout into the map and queue, to timeout once the wall clock reaches `wall`, if
+    /// it doesn't already exist. Useful for deadlines such as "expire this token at 14:00 UTC", which
+    /// are re-anchored whenever the wall clock jumps against the monotonic clock, such as after the
+    /// machine was suspended or the time was adjusted, rather than drifting along with it.
+    ///
+    /// ```
+    /// # use delay_handler::{Clock, DelayHandler, MockClock};
+    /// # use std::time::Duration;
+    /// # futures::executor::block_on(async {
+    /// let clock = MockClock::new();
+    /// let mut handler = DelayHandler::builder().clock(clock.clone()).build();
+    /// let expiry = clock.system_now() + Duration::from_secs(3600);
+    /// handler.insert_at_system_time("token", expiry);
+    ///
+    /// // Yields once the wall clock reaches the expiry, even if the machine slept in between.
+    /// clock.advance(Duration::from_secs(3600));
+    /// assert_eq!(handler.next().await, Some("token"));
+    /// # });
+    /// ```
+    pub fn insert_at_system_time(&mut self, item: T, wall: SystemTime) -> bool {
+        self.map.insert_at_system_time(item, (), wall)
+    }
+
+    /// Insert a batch of new timeouts, reserving space for all of them upfront, which is faster
+    /// than inserting them one at a time. As with [`insert()`](DelayHandler::insert) returning false,
+    /// returns the items that weren't newly inserted: those rejected as duplicates, or because the
+    /// handler is full or closed, and with [`Duplicates::Reset`] those that were rescheduled instead.
+    ///
+    /// ### Examples
+    /// ```
+    /// # use delay_handler::DelayHandler;
+    /// # use std::time::Duration;
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let mut handler = DelayHandler::default();
+    /// handler.insert(0, Duration::from_secs(1));
+    ///
+    /// let retries = (0..100_000).map(|id| (id, Duration::from_secs(30)));
+    /// assert_eq!(handler.insert_many(retries), vec![0]);
+    /// # }
+    /// ```
+    pub fn insert_many<I>(&mut self, timeouts: I) -> Vec<T>
+    where
+        I: IntoIterator<Item = (T, Duration)>,
+    {
+        let timeouts = timeouts
+            .into_iter()
+            .map(|(item, period)| (item, (), period));
+
+        self.map
+            .insert_many(timeouts)
+            .into_iter()
+            .map(|(item, _)| item)
+            .collect()
+    }
+
+    /// Insert new periodic timeout if it doesn't already exist, which first times out after `interval`
+    /// from now and is then automatically re-armed after each expiry, until it is removed.
+    ///
+    /// # Panics
+    /// If `interval` is zero.
+    pub fn insert_periodic(&mut self, item: T, interval: Duration, mode: Periodic) -> bool
+    where
+        T: Clone,
+    {
+        self.map.insert_periodic(item, (), interval, mode)
+    }
+
+    /// Insert new timeout if it doesn't already exist, that expires at every wall-clock time matching
+    /// the cron `expression`, with seconds. The next occurrence is recomputed after each expiry,
+    /// skipping any that were missed. Returns false if the timeout already exists or the expression
+    /// has no upcoming occurrences.
+    ///
+    /// ```no_run
+    /// # use delay_handler::DelayHandler;
+    /// # async fn run() {
+    /// let mut handler = DelayHandler::default();
+    /// // Every 5 minutes, on the minute
+    /// handler.insert_cron("compact", "0 */5 * * * *").unwrap();
+    ///
+    /// while let Some(job) = handler.next().await {
+    ///     println!("running {}", job);
+    /// }
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    /// If `expression` isn't a valid cron expression.
+    #[cfg(feature = "cron")]
+    pub fn insert_cron(&mut self, item: T, expression: &str) -> Result<bool, cron::error::Error>
+    where
+        T: Clone,
+    {
+        self.map.insert_cron(item, (), expression)
+    }
+
+    /// Insert new timeout if it doesn't already exist, which is re-armed after each expiry with a delay
+    /// that grows as per the `backoff` policy, until it runs out of attempts or is removed.
+    /// Use [`next_expired()`](DelayHandler::next_expired) to learn the attempt count.
+    ///
+    /// ```no_run
+    /// # use delay_handler::{Backoff, DelayHandler};
+    /// # use std::time::Duration;
+    /// # async fn run() {
+    /// let mut handler = DelayHandler::default();
+    /// let backoff = Backoff::new(Duration::from_secs(1)).max_attempts(5);
+    /// handler.insert_with_backoff("retry", backoff);
+    ///
+    /// // Prints after ~1s, ~2s, ~4s, ~8s and then ~16s from the previous print.
+    /// while let Some(expired) = handler.next_expired().await {
+    ///     println!("{}: attempt {}", expired.get_ref(), expired.attempt());
+    /// }
+    /// # }
+    /// ```
+    pub fn insert_with_backoff(&mut self, item: T, backoff: Backoff) -> bool
+    where
+        T: Clone,
+    {
+        self.map.insert_with_backoff(item, (), backoff)
+    }
+
+    /// Insert new timeout under the `group` label if it doesn't already exist, so that it can later
+    /// be removed along with every other timeout in the group, with
+    /// [`remove_group()`](DelayHandler::remove_group). If one already exists, don't insert, returning false.
+    pub fn insert_in_group(&mut self, group: &str, item: T, period: Duration) -> bool {
+        self.map.insert_in_group(group, item, (), period)
+    }
+
+    /// Insert new timeout if it doesn't already exist, that runs `f` with the item on expiry, instead
+    /// of it being returned from [`next()`](DelayHandler::next). Turns the handler into a lightweight
+    /// queue of deferred actions, that run while it is being polled. If one already exists,
+    /// don't insert, returning false.
+    ///
+    /// ### Examples
+    /// ```
+    /// # use delay_handler::{DelayHandler, MockClock};
+    /// # use std::time::Duration;
+    /// # futures::executor::block_on(async {
+    /// let clock = MockClock::new();
+    /// let mut handler = DelayHandler::builder().clock(clock.clone()).build();
+    /// handler.insert_with("session-1", Duration::from_secs(30), |id| println!("{} expired", id));
+    ///
+    /// // Runs the action once 30s have passed, without ever returning the item.
+    /// clock.advance(Duration::from_secs(30));
+    /// assert_eq!(handler.next().await, None);
+    /// # });
+    /// ```
+    pub fn insert_with<F>(&mut self, item: T, period: Duration, f: F) -> bool
+    where
+        F: FnOnce(T) + Send + Sync + 'static,
+    {
+        self.map
+            .insert_with(item, (), period, move |item, _| f(item))
+    }
+
+    /// Insert new timeout if it doesn't already exist, that is automatically removed when `token` is
+    /// cancelled, the next time the handler is polled. Ties the lifetime of the timeout to that of
+    /// a task, without cleanup code in every cancellation path. If one already exists or the token
+    /// is already cancelled, don't insert, returning false.
+    #[cfg(feature = "tokio")]
+    pub fn insert_with_token(
+        &mut self,
+        item: T,
+        period: Duration,
+        token: CancellationToken,
+    ) -> bool {
+        self.map.insert_with_token(item, (), period, token)
+    }
+
+    /// Insert new timeout if it doesn't already exist, with a `priority` that decides the order of
+    /// delivery among timeouts that have already expired, highest first. Useful to deliver
+    /// control-plane timeouts ahead of a backlog of bulk retries. Timeouts inserted otherwise have the
+    /// lowest priority of zero. If one already exists, don't insert, returning false.
+    pub fn insert_with_priority(&mut self, item: T, period: Duration, priority: u8) -> bool {
+        self.map.insert_with_priority(item, (), period, priority)
+    }
+
+    /// Insert new timeout carrying one or more `tags` if it doesn't already exist, e.g. the tenant,
+    /// priority class or origin of the item, so that it can later be inspected or removed by any of them.
+    /// If one already exists, don't insert, returning false.
+    pub fn insert_with_tags(&mut self, item: T, period: Duration, tags: &[&str]) -> bool {
+        self.map.insert_with_tags(item, (), period, tags)
+    }
+
+    /// Insert new timeout if it doesn't already exist, otherwise reschedule the existing one to
+    /// timeout after `period` from now. Useful for keepalive-style usage, where every new
+    /// activity should push the timeout further.
+    pub fn insert_or_reset(&mut self, item: T, period: Duration) -> Scheduled {
+        self.map.insert_or_reset(item, (), period)
     }
 
     /// Prematurely removes timeout from delay-map, if it didn't already exist returns false.
-    pub fn remove(&mut self, item: &T) -> bool {
-        match self.map.remove(item) {
-            Some(key) => {
-                self.queue.remove(&key).into_inner();
-
-                true
-            }
-            _ => false,
-        }
+    pub fn remove<Q>(&mut self, item: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.remove(item).is_some()
+    }
+
+    /// Prematurely removes a batch of timeouts from delay-map, returning how many of them existed.
+    /// Useful to acknowledge a batch of messages at once.
+    pub fn remove_many<'a, Q, I>(&mut self, items: I) -> usize
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized + 'a,
+        I: IntoIterator<Item = &'a Q>,
+    {
+        self.map.remove_many(items).len()
+    }
+
+    /// Prematurely removes timeout from delay-map, returning the owned item and time that was
+    /// remaining, if it existed. Useful to re-schedule the timeout elsewhere.
+    pub fn take<Q>(&mut self, item: &Q) -> Option<(T, Duration)>
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (item, _, remaining) = self.map.take(item)?;
+
+        Some((item, remaining))
+    }
+
+    /// Prematurely removes every timeout inserted under the `group` label, returning their items.
+    /// Useful to cancel everything related to a connection on teardown, in one call.
+    pub fn remove_group(&mut self, group: &str) -> Vec<T> {
+        self.map
+            .remove_group(group)
+            .into_iter()
+            .map(|(item, _)| item)
+            .collect()
+    }
+
+    /// Number of timeouts currently pending under the `group` label.
+    pub fn group_len(&self, group: &str) -> usize {
+        self.map.group_len(group)
+    }
+
+    /// Prematurely removes every timeout carrying `tag`, returning their items.
+    pub fn remove_by_tag(&mut self, tag: &str) -> Vec<T> {
+        self.map
+            .remove_by_tag(tag)
+            .into_iter()
+            .map(|(item, _)| item)
+            .collect()
+    }
+
+    /// Iterate over all pending items carrying `tag`.
+    pub fn tagged<'a>(&'a self, tag: &str) -> impl Iterator<Item = &'a T> + 'a {
+        self.map.tagged(tag).map(|(item, _)| item)
+    }
+
+    /// Updates the deadline of an existing timeout in place, to timeout after `period` from now.
+    /// If it didn't already exist returns false.
+    pub fn reset<Q>(&mut self, item: &Q, period: Duration) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.reset(item, period)
+    }
+
+    /// Reschedules an existing timeout to expire immediately, so that it is delivered by
+    /// [`next()`](DelayHandler::next) like any other. Useful to keep a single code path for
+    /// processing items, whether they timeout naturally or are triggered early.
+    /// If it didn't already exist returns false.
+    pub fn expire_now<Q>(&mut self, item: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.expire_now(item)
+    }
+
+    /// Marks every pending timeout as due, so that all of them are delivered by
+    /// [`next()`](DelayHandler::next) right away, in the order they would have expired in. Useful on graceful shutdown, where queued retries should
+    /// be attempted one last time instead of being dropped.
+    pub fn expire_all(&mut self) {
+        self.map.expire_all()
+    }
+
+    /// Pushes back the deadline of an existing timeout by `extra`, without having to know how much
+    /// time it has remaining. Useful for leases that are renewed by a fixed increment.
+    /// If it didn't already exist returns false.
+    pub fn extend_delay<Q>(&mut self, item: &Q, extra: Duration) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.extend_delay(item, extra)
+    }
+
+    /// Pushes back the deadline of an existing timeout, to timeout after the same period it was last
+    /// scheduled with, from now. Useful for idle-timeouts, e.g. of sessions that are kept alive by
+    /// activity. If it didn't already exist returns false.
+    pub fn touch<Q>(&mut self, item: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.touch(item)
+    }
+
+    /// Suspends the timeout associated with `item`, so that it can't expire until
+    /// [thawed](DelayHandler::thaw), preserving the time it has remaining. Useful when the resource
+    /// the timeout guards is temporarily busy. Returns false if it didn't exist or is already frozen.
+    pub fn freeze<Q>(&mut self, item: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.freeze(item)
+    }
+
+    /// Resumes a [frozen](DelayHandler::freeze) timeout, to expire after the time it had remaining
+    /// when it was frozen, from now. Returns false if it didn't exist or isn't frozen.
+    pub fn thaw<Q>(&mut self, item: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.thaw(item)
+    }
+
+    /// Check if the timeout associated with `item` is [frozen](DelayHandler::freeze).
+    pub fn is_frozen<Q>(&self, item: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.is_frozen(item)
+    }
+
+    /// Returns the instant at which the timeout associated with `item` is scheduled to expire.
+    pub fn deadline<Q>(&self, item: &Q) -> Option<Instant>
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.deadline(item)
+    }
+
+    /// Returns how long until the timeout associated with `item` expires, zero if it is already due.
+    /// Useful for reporting something like "retry in Ns" to users.
+    pub fn time_remaining<Q>(&self, item: &Q) -> Option<Duration>
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.time_remaining(item)
+    }
+
+    /// Returns the item that is to timeout next along with its deadline, without removing it.
+    /// Useful to decide whether to wait on [`next()`](DelayHandler::next) or do other work.
+    pub fn peek_next(&self) -> Option<(&T, Instant)> {
+        self.map.peek_next()
+    }
+
+    /// Returns the deadline of the timeout that is to expire next. Could be used to compose with
+    /// timeouts of a larger `select!`, without polling [`next()`](DelayHandler::next) itself.
+    ///
+    /// ```text
+    /// if let Some(deadline) = handler.next_deadline() {
+    ///     tokio::time::sleep_until(deadline).await;
+    /// }
+    /// ```
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.map.next_deadline()
+    }
+
+    /// Watch the [deadline of the timeout that is to expire next](DelayHandler::next_deadline),
+    /// which is updated on every insert, removal and expiry. Lets external supervisors schedule
+    /// around the handler without owning it.
+    #[cfg(feature = "channels")]
+    pub fn watch_next_deadline(&mut self) -> watch::Receiver<Option<Instant>> {
+        self.map.watch_next_deadline()
+    }
+
+    /// Watch whether the number of pending timeouts is at or above `watermark`, which flips every
+    /// time it is crossed in either direction. Lets producers shed load before the handler is full.
+    #[cfg(feature = "channels")]
+    pub fn watch_watermark(&mut self, watermark: Watermark) -> watch::Receiver<bool> {
+        self.map.watch_watermark(watermark)
+    }
+
+    /// Returns a future that resolves once the timeout for `item` has expired or been removed,
+    /// telling which, or `None` if no timeout exists for it. Lets a task that only cares about a
+    /// single item wait on it, while another owns and drives the handler.
+    #[cfg(feature = "channels")]
+    pub fn wait_for<Q>(&mut self, item: &Q) -> Option<WaitFor>
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.wait_for(item)
     }
 
     /// Remove a key from map if it has timedout and return the name.
     pub async fn next(&mut self) -> Option<T> {
-        let item = self.queue.next().await?.into_inner();
-        self.map.remove(&item);
+        let (item, _) = self.map.next().await?;
 
         Some(item)
     }
 
+    /// Remove a key from map once it has timedout and return the name, waiting for new inserts
+    /// while the delay-map is empty rather than returning `None`.
+    ///
+    /// ### Examples
+    /// ```no_run
+    /// # use delay_handler::DelayHandler;
+    /// # use std::time::Duration;
+    /// # async fn run() {
+    /// let mut handler = DelayHandler::default();
+    /// handler.insert(1, Duration::from_secs(5));
+    ///
+    /// // Unlike `while let Some(id) = handler.next().await`, keeps waiting once 1 has timedout,
+    /// // such as for timeouts inserted by another branch of a `select!` loop.
+    /// loop {
+    ///     let id = handler.next_or_pending().await;
+    ///     println!("{id} timedout");
+    /// }
+    /// # }
+    /// ```
+    pub async fn next_or_pending(&mut self) -> T {
+        self.map.next_or_pending().await.0
+    }
+
+    /// Wait for the next key to timeout, then remove all others that have already timedout, up to
+    /// `max` in total, returning them in order of expiry. Saves an `.await` round-trip per item,
+    /// when a lot of timeouts share the same deadline. Returns an empty batch if the queue is empty.
+    pub async fn next_batch(&mut self, max: usize) -> Vec<T> {
+        self.map
+            .next_batch(max)
+            .await
+            .into_iter()
+            .map(|(item, _)| item)
+            .collect()
+    }
+
+    /// Polls for a key that has timedout, registering the current task for wakeup if none has yet.
+    /// Useful when embedding the handler in hand-written `Future`/`Stream` implementations.
+    pub fn poll_expired(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.map
+            .poll_expired(cx)
+            .map(|expired| expired.map(|(item, _)| item))
+    }
+
+    /// Remove a key from map if it has timedout and return it, wrapped with the deadline it was
+    /// scheduled for and the instant it was delivered at. Useful to monitor scheduler lag.
+    pub async fn next_expired(&mut self) -> Option<Expired<T>> {
+        let expired = self.map.next_expired().await?;
+
+        Some(expired.map(|(item, _)| item))
+    }
+
+    /// Polls for a key that has timedout, like [`poll_expired()`](DelayHandler::poll_expired), but
+    /// wrapped with the deadline it was scheduled for and the instant it was delivered at.
+    pub fn poll_next_expired(&mut self, cx: &mut Context<'_>) -> Poll<Option<Expired<T>>> {
+        self.map
+            .poll_next_expired(cx)
+            .map(|expired| expired.map(|expired| expired.map(|(item, _)| item)))
+    }
+
+    /// Converts the handler into an owned stream of timedout items, that ends once it is drained.
+    pub fn into_stream(self) -> IntoStream<Self> {
+        IntoStream::new(self)
+    }
+
+    /// Remove a key from map if it has already timedout and return the name, without waiting.
+    /// Returns `None` if no timeout is due yet, useful when polling on a fixed tick from
+    /// synchronous code.
+    pub fn try_next(&mut self) -> Option<T> {
+        let (item, _) = self.map.try_next()?;
+
+        Some(item)
+    }
+
+    /// Remove all keys that have already timedout, in order of expiry.
+    pub fn drain_expired(&mut self) -> Vec<T> {
+        self.map
+            .drain_expired()
+            .into_iter()
+            .map(|(item, _)| item)
+            .collect()
+    }
+
+    /// Iterate over all pending items with their deadlines, in order of expiry.
+    pub fn iter(&self) -> impl Iterator<Item = (&T, Instant)> {
+        self.map.iter().map(|(item, _, deadline)| (item, deadline))
+    }
+
+    /// Retain only the timeouts for which `f` returns true, prematurely removing all others.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        self.map.retain(|item, _| f(item))
+    }
+
+    /// Remove all pending timeouts from the delay-map, returning them along with the time that was
+    /// remaining, in order of expiry. Useful to recover outstanding timers on teardown.
+    pub fn drain(&mut self) -> Vec<(T, Duration)> {
+        self.map
+            .drain()
+            .into_iter()
+            .map(|(item, _, remaining)| (item, remaining))
+            .collect()
+    }
+
+    /// Stops the handler from accepting new timeouts, inserts are rejected from here on, as if it
+    /// were full, and recurring timeouts expire one last time instead of being re-armed. Pending
+    /// timeouts are left as they are, to be drained or to expire as usual, after which
+    /// [`next()`](DelayHandler::next) returns `None` for good.
+    pub fn close(&mut self) {
+        self.map.close()
+    }
+
+    /// Check if the handler has been [closed](DelayHandler::close).
+    pub fn is_closed(&self) -> bool {
+        self.map.is_closed()
+    }
+
+    /// [Closes](DelayHandler::close) the handler and hands out the remaining items, either right
+    /// away or as they expire, as per `shutdown`, for a clean shutdown of services built on it.
+    ///
+    /// ### Examples
+    /// ```no_run
+    /// # use delay_handler::{DelayHandler, Shutdown};
+    /// # use std::time::Duration;
+    /// # async fn run() {
+    /// let mut handler = DelayHandler::default();
+    /// handler.insert("flush", Duration::from_secs(5));
+    ///
+    /// // Waits ~5s for "flush" to expire, while further inserts are rejected.
+    /// for item in handler.shutdown(Shutdown::Graceful).await {
+    ///     println!("{item}");
+    /// }
+    /// # }
+    /// ```
+    pub async fn shutdown(&mut self, shutdown: Shutdown) -> Vec<T> {
+        self.map
+            .shutdown(shutdown)
+            .await
+            .into_iter()
+            .map(|(item, _)| item)
+            .collect()
+    }
+
+    /// Moves all pending timeouts out of `other` into this handler, each keeping the time it had
+    /// remaining, such as to consolidate per-shard handlers when rebalancing. An item that already
+    /// has a timeout here keeps it, unless the handler was built to [reset](Duplicates::Reset)
+    /// duplicates, in which case it takes the time remaining of the one from `other`. Items that
+    /// are kept out, as duplicates or for lack of space, are returned with their time remaining.
+    /// See [`DelayMap::merge()`] for what else is carried over.
+    pub fn merge(&mut self, other: Self) -> Vec<(T, Duration)> {
+        self.map
+            .merge(other.map)
+            .into_iter()
+            .map(|(item, _, remaining)| (item, remaining))
+            .collect()
+    }
+
+    /// Copies out all pending timeouts along with the time remaining, in the same order as
+    /// [`drain()`](DelayHandler::drain), while leaving the handler untouched. Useful to periodically
+    /// checkpoint outstanding timers.
+    pub fn snapshot(&self) -> Vec<(T, Duration)>
+    where
+        T: Clone,
+    {
+        self.map
+            .pending()
+            .map(|(item, _, remaining)| (item.clone(), remaining))
+            .collect()
+    }
+
+    /// Replaces all pending timeouts with those from a [`snapshot()`](DelayHandler::snapshot), each
+    /// to timeout after the time that was remaining from now, skipping items that are repeated.
+    ///
+    /// ### Examples
+    /// ```
+    /// # use delay_handler::DelayHandler;
+    /// # use std::time::Duration;
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let mut handler = DelayHandler::default();
+    /// handler.insert(1, Duration::from_secs(5));
+    /// let checkpoint = handler.snapshot();
+    ///
+    /// let mut recovered = DelayHandler::default();
+    /// recovered.restore(checkpoint);
+    /// assert!(recovered.contains(&1));
+    /// # }
+    /// ```
+    pub fn restore<I>(&mut self, timeouts: I)
+    where
+        I: IntoIterator<Item = (T, Duration)>,
+    {
+        self.map.restore(
+            timeouts
+                .into_iter()
+                .map(|(item, remaining)| (item, (), remaining)),
+        )
+    }
+
+    /// Remove all pending timeouts from the delay-map.
+    pub fn clear(&mut self) {
+        self.map.clear()
+    }
+
+    /// Shrinks the capacity of the handler as much as possible, returning memory to the allocator
+    /// after the number of pending timeouts has come down from its peak.
+    pub fn shrink_to_fit(&mut self) {
+        self.map.shrink_to_fit()
+    }
+
+    /// Automatically [shrink](DelayHandler::shrink_to_fit) the handler on removal or expiry, whenever
+    /// its capacity grows to more than `factor` times the number of pending timeouts. Useful in
+    /// long-lived daemons with bursty load. Passing `None` disables automatic shrinking, which is
+    /// the default.
+    pub fn set_auto_shrink(&mut self, factor: Option<usize>) {
+        self.map.set_auto_shrink(factor)
+    }
+
+    /// Insert new timeout into the map and queue if it doesn't already exist, first waiting for space to
+    /// free up if the handler is [full](DelayHandler::set_max_entries), rather than failing. Timeouts
+    /// that expire while waiting are held back, to be returned by [`next()`](DelayHandler::next)
+    /// before all others.
+    pub async fn insert_when_ready(&mut self, item: T, period: Duration) -> bool {
+        self.map.insert_when_ready(item, (), period).await
+    }
+
+    /// Polls for space to free up in the handler, registering the current task for wakeup
+    /// if it is still full.
+    pub fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        self.map.poll_ready(cx)
+    }
+
+    /// Freezes the passage of time for all timeouts, none expire until [resumed](DelayHandler::resume).
+    /// Useful for maintenance windows, where timers must not fire.
+    pub fn pause(&mut self) {
+        self.map.pause()
+    }
+
+    /// Resumes the passage of time after a [pause](DelayHandler::pause), so that all timeouts have
+    /// the same time remaining as they did on pausing.
+    pub fn resume(&mut self) {
+        self.map.resume()
+    }
+
+    /// Compares the wall clock against the monotonic clock right away, such as on being notified
+    /// that the machine has resumed from suspend, returning the gap in time that was detected, if
+    /// [watched for](DelayHandlerBuilder::on_clock_gap).
+    pub fn resync(&mut self) -> Option<Duration> {
+        self.map.resync()
+    }
+
+    /// Check if the passage of time is [paused](DelayHandler::pause).
+    pub fn is_paused(&self) -> bool {
+        self.map.is_paused()
+    }
+
+    /// Subscribe to a copy of every item as it timesout, so that more than one consumer can observe
+    /// expiries, e.g. a metrics task alongside the business logic. Items are still returned from
+    /// [`next()`](DelayHandler::next), which must be called to drive the handler. Subscribers that
+    /// fall more than 1024 expiries behind miss the oldest of them.
+    #[cfg(feature = "channels")]
+    pub fn subscribe(&mut self) -> broadcast::Receiver<T>
+    where
+        T: Clone + Send + 'static,
+    {
+        let (sender, receiver) = broadcast::channel(BROADCAST_CAPACITY);
+        self.map
+            .observe(Box::new(move |item, _| sender.send(item.clone()).is_ok()));
+
+        receiver
+    }
+
+    /// Subscribe to every change in the lifecycle of timeouts, as they are inserted, reset, removed
+    /// and expire, e.g. for an audit trail of cancellations alongside expiries. Events are sent
+    /// independently of [`next()`](DelayHandler::next), which must still be called to drive the
+    /// handler. Subscribers that fall more than 1024 events behind miss the oldest of them.
+    #[cfg(feature = "channels")]
+    pub fn events(&mut self) -> broadcast::Receiver<Event<T>>
+    where
+        T: Clone + Send + 'static,
+    {
+        self.map.events()
+    }
+
+    /// Installs an [`Observer`], whose methods are invoked synchronously as timeouts are inserted,
+    /// reset, removed and expire, for as long as the handler lives.
+    pub fn add_observer<O>(&mut self, observer: O)
+    where
+        O: Observer<T> + 'static,
+    {
+        self.map.add_observer(observer)
+    }
+
+    /// Registers a callback that is invoked with every item as it timesout, before it is returned
+    /// from [`next()`](DelayHandler::next). Useful for side effects like logging or metrics, without
+    /// wrapping the consumer loop.
+    pub fn on_expire<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T) + Send + Sync + 'static,
+    {
+        self.map.on_expire(move |item, _| f(item))
+    }
+
+    /// Check if the number of pending timeouts has reached the [bound](DelayHandler::set_max_entries).
+    pub fn is_full(&self) -> bool {
+        self.map.is_full()
+    }
+
+    /// Returns the maximum number of pending timeouts allowed, if bounded.
+    pub fn max_entries(&self) -> Option<usize> {
+        self.map.max_entries()
+    }
+
+    /// Bounds the number of pending timeouts to `max`, with `overflow` deciding what happens on
+    /// inserting into a full handler, to protect against unbounded growth when the consumer stalls.
+    /// Passing `None` removes the bound, which is the default.
+    pub fn set_max_entries(&mut self, bound: Option<(usize, Overflow)>) {
+        self.map.set_max_entries(bound)
+    }
+
     /// Check if queue is empty. Could be used as precondition in an async select operation.
     /// NOTE: The following example assumes usage of `tokio::select`
     ///
@@ -121,18 +1062,288 @@ where
     /// }
     /// ```
     pub fn is_empty(&self) -> bool {
-        self.queue.is_empty()
+        self.map.is_empty()
+    }
+
+    /// Check if a timeout is currently scheduled for `item`.
+    pub fn contains<Q>(&self, item: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.contains(item)
+    }
+
+    /// Records items in the [`tracing`] events emitted on each insert, removal, reset
+    /// and expiry, formatted with their `Debug` implementation. Otherwise only the sequence number
+    /// identifying each timeout is recorded, as items aren't required to implement `Debug`.
+    #[cfg(feature = "tracing")]
+    pub fn trace_keys(&mut self)
+    where
+        T: Debug,
+    {
+        self.map.trace_keys()
+    }
+
+    /// Returns counters of the handler's activity since it was created, along with its current and
+    /// peak depth, to monitor a handler with a large number of pending timeouts.
+    pub fn stats(&self) -> Stats {
+        self.map.stats()
+    }
+
+    /// Number of timeouts currently pending in the delay-map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+}
+
+impl DelayHandler<()> {
+    /// Creates a builder, to configure the handler before constructing it, same as
+    /// [`DelayHandlerBuilder::new()`].
+    pub fn builder() -> DelayHandlerBuilder {
+        DelayHandlerBuilder::new()
     }
 }
 
 impl<T> Default for DelayHandler<T>
 where
-    T: Eq + Hash + Clone + Display,
+    T: Eq + Hash,
 {
     fn default() -> Self {
+        Self::with_capacity(0)
+    }
+}
+
+impl<T, S> Clone for DelayHandler<T, S>
+where
+    T: Eq + Hash + Clone,
+    S: BuildHasher + Clone,
+{
+    /// Forks the handler into an independent one, with the same configuration and pending timeouts,
+    /// each due after the same time remaining. Actions run on expiry, cancellation tokens and the
+    /// journal aren't carried over, see [`DelayMap::clone()`].
+    fn clone(&self) -> Self {
         Self {
-            queue: DelayQueue::new(),
-            map: HashMap::new(),
+            map: self.map.clone(),
+            #[cfg(feature = "persistent")]
+            journal: None,
+        }
+    }
+}
+
+impl<T, S> Debug for DelayHandler<T, S>
+where
+    T: Eq + Hash + Debug,
+    S: BuildHasher,
+{
+    /// Lists the number of pending timeouts and the first few to timeout, with their time remaining.
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let pending = self
+            .map
+            .pending()
+            .map(|(item, _, remaining)| (item, remaining));
+
+        f.debug_struct("DelayHandler")
+            .field("len", &self.len())
+            .field("pending", &Listed::new(pending))
+            .finish()
+    }
+}
+
+impl<T, S> Extend<(T, Duration)> for DelayHandler<T, S>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
+{
+    /// Inserts all timeouts from the iterator, skipping items that already exist.
+    fn extend<I: IntoIterator<Item = (T, Duration)>>(&mut self, iter: I) {
+        self.map
+            .extend(iter.into_iter().map(|(item, period)| (item, (), period)))
+    }
+}
+
+impl<T, S> FromIterator<(T, Duration)> for DelayHandler<T, S>
+where
+    T: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    fn from_iter<I: IntoIterator<Item = (T, Duration)>>(iter: I) -> Self {
+        let mut handler = Self::with_hasher(S::default());
+        handler.extend(iter);
+
+        handler
+    }
+}
+
+impl<T, S> IntoIterator for DelayHandler<T, S>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
+{
+    type Item = (T, Duration);
+    type IntoIter = std::vec::IntoIter<(T, Duration)>;
+
+    /// Tears down the handler, yielding all pending timeouts along with the time that was remaining,
+    /// in the same order as [`drain()`](DelayHandler::drain).
+    fn into_iter(mut self) -> Self::IntoIter {
+        self.drain().into_iter()
+    }
+}
+
+impl<T, S> Stream for DelayHandler<T, S>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
+{
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_expired(cx)
+    }
+}
+
+impl<T, S> FusedStream for DelayHandler<T, S>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
+{
+    fn is_terminated(&self) -> bool {
+        self.map.is_terminated()
+    }
+}
+
+#[cfg(all(test, feature = "tokio"))]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::{advance, timeout, Instant};
+
+    use crate::{Clock, DelayHandler, TokioClock};
+
+    #[tokio::test(start_paused = true)]
+    async fn expires_in_order_of_deadlines() {
+        let mut handler = DelayHandler::default();
+        handler.insert(1, Duration::from_secs(30));
+        handler.insert(2, Duration::from_secs(10));
+        handler.insert(3, Duration::from_secs(20));
+
+        let start = Instant::now();
+        for (item, secs) in [(2, 10), (3, 20), (1, 30)] {
+            assert_eq!(handler.next().await, Some(item));
+            assert_eq!(start.elapsed(), Duration::from_secs(secs));
+        }
+        assert_eq!(handler.next().await, None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn resets_push_back_the_deadline() {
+        let mut handler = DelayHandler::default();
+        handler.insert(1, Duration::from_secs(10));
+        advance(Duration::from_secs(5)).await;
+        assert!(handler.reset(&1, Duration::from_secs(10)));
+        assert!(!handler.reset(&2, Duration::from_secs(10)));
+
+        advance(Duration::from_secs(9)).await;
+        assert_eq!(handler.try_next(), None);
+        advance(Duration::from_secs(1)).await;
+        assert_eq!(handler.try_next(), Some(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pausing_stops_the_passage_of_time() {
+        let mut handler = DelayHandler::default();
+        handler.insert(1, Duration::from_secs(10));
+        advance(Duration::from_secs(4)).await;
+        handler.pause();
+
+        advance(Duration::from_secs(3600)).await;
+        assert_eq!(handler.try_next(), None);
+        handler.resume();
+        assert_eq!(handler.time_remaining(&1), Some(Duration::from_secs(6)));
+
+        let start = Instant::now();
+        assert_eq!(handler.next().await, Some(1));
+        assert_eq!(start.elapsed(), Duration::from_secs(6));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn frozen_timeouts_keep_their_time_remaining() {
+        let mut handler = DelayHandler::default();
+        handler.insert(1, Duration::from_secs(10));
+        handler.insert(2, Duration::from_secs(20));
+        advance(Duration::from_secs(4)).await;
+        assert!(handler.freeze(&1));
+        assert!(!handler.freeze(&1));
+
+        advance(Duration::from_secs(16)).await;
+        assert_eq!(handler.try_next(), Some(2));
+        assert_eq!(handler.try_next(), None);
+        assert!(handler.is_frozen(&1));
+
+        assert!(handler.thaw(&1));
+        advance(Duration::from_secs(5)).await;
+        assert_eq!(handler.try_next(), None);
+        advance(Duration::from_secs(1)).await;
+        assert_eq!(handler.try_next(), Some(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn next_or_pending_waits_while_empty() {
+        let mut handler = DelayHandler::default();
+        let waited = timeout(Duration::from_secs(3600), handler.next_or_pending()).await;
+        assert!(waited.is_err());
+
+        handler.insert(1, Duration::from_secs(10));
+        let start = Instant::now();
+        assert_eq!(handler.next_or_pending().await, 1);
+        assert_eq!(start.elapsed(), Duration::from_secs(10));
+        assert!(
+            timeout(Duration::from_secs(3600), handler.next_or_pending())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn schedules_delays_beyond_the_delay_queue() {
+        let year = Duration::from_secs(365 * 24 * 60 * 60);
+        let mut handler = DelayHandler::default();
+        handler.insert(1, 3 * year);
+        handler.insert(2, Duration::MAX);
+
+        // Yielded early by the queue a year out, then re-armed for the rest
+        advance(2 * year).await;
+        assert_eq!(handler.try_next(), None);
+        assert_eq!(handler.time_remaining(&1), Some(year));
+        advance(year).await;
+        assert_eq!(handler.try_next(), Some(1));
+        assert_eq!(handler.try_next(), None);
+        assert!(handler.contains(&2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn expires_same_deadlines_in_insertion_order() {
+        let mut handler = DelayHandler::default();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let items = [7, 3, 9, 1, 5, 8, 2];
+        for item in items {
+            handler.insert_at(item, deadline.into_std());
         }
+
+        advance(Duration::from_secs(10)).await;
+        assert_eq!(handler.drain_expired(), items);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wall_clock_deadlines_follow_the_paused_clock() {
+        let mut handler = DelayHandler::default();
+        let hour = Duration::from_secs(3600);
+        // Wall-clock time stands still while paused, as the tokio clock does
+        handler.insert_at_system_time(1, TokioClock.system_now() + hour);
+
+        advance(hour - Duration::from_millis(1)).await;
+        assert_eq!(handler.try_next(), None);
+        advance(Duration::from_millis(2)).await;
+        assert_eq!(handler.try_next(), Some(1));
     }
 }
@@ -0,0 +1,3309 @@
+use std::borrow::Borrow;
+use std::cmp::Reverse;
+use std::collections::hash_map::HashMap;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::{self, Debug, Formatter};
+use std::future::poll_fn;
+use std::hash::{BuildHasher, Hash};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll, Waker};
+use std::time::{Duration, Instant, SystemTime};
+
+#[cfg(feature = "cron")]
+use chrono::{DateTime, Utc};
+use futures_core::{FusedStream, Stream};
+use hashbrown::HashTable;
+#[cfg(feature = "channels")]
+use tokio::sync::{broadcast, oneshot, watch};
+#[cfg(feature = "tokio")]
+use tokio_util::sync::CancellationToken;
+
+use crate::clock::{saturating_add, Clock, Sleep};
+use crate::event::Event;
+#[cfg(feature = "metrics")]
+use crate::metrics::Metrics;
+use crate::observer::{self, Observer};
+use crate::queue::{Queue, QueueKey};
+use crate::rand::Rng;
+#[cfg(feature = "tracing")]
+use crate::trace::{FmtKey, Key};
+#[cfg(feature = "channels")]
+use crate::wait::WaitFor;
+#[cfg(feature = "channels")]
+use crate::Watermark;
+use crate::{
+    Backoff, ClockGap, ClockMode, DelayHandlerBuilder, Duplicates, Expired, IntoStream, Overflow,
+    Periodic, Scheduled, Shutdown, Stats,
+};
+
+mod entry;
+
+pub use entry::{Entry, OccupiedEntry, VacantEntry};
+
+/// A keyed variant of [`DelayHandler`](crate::DelayHandler), where deadlines are looked up by a key
+/// of type `K` and carry an associated payload of type `V`, that is returned along with the key
+/// on timeout.
+///
+/// Only the key needs to be `Eq + Hash`, the payload is moved into the delay-map and is handed back
+/// as is, when the delay is timedout or prematurely removed. Both are stored only once, no matter
+/// how large.
+///
+/// As with the [`DelayHandler`](crate::DelayHandler), timeouts that share a deadline are yielded
+/// in the order they were inserted.
+///
+/// ### Examples
+/// Insert payloads keyed by a small ID, print them as they timeout
+/// ```no_run
+/// # use delay_handler::DelayMap;
+/// # use std::time::Duration;
+/// # async fn run() {
+/// let mut map = DelayMap::default();
+/// // Adds "hello" and "world" to the delay-map, keyed by 1 and 2, with different delays
+/// map.insert(1, "hello".to_owned(), Duration::from_secs(10));
+/// map.insert(2, "world".to_owned(), Duration::from_secs(5));
+///
+/// // Prints should be in the order of first 2 and ~5s later 1.
+/// while let Some((id, payload)) = map.next().await {
+///     println!("{}: {}", id, payload);
+/// }
+/// # }
+/// ```
+pub struct DelayMap<K, V, S = DefaultHashBuilder> {
+    queue: Queue<Id>,
+    /// Slab of every timeout, the queue and all other bookkeeping only refer to them by index, so
+    /// that keys and payloads are stored once. Vacant slots are reused by later inserts.
+    slots: Vec<Option<Slot<K, V>>>,
+    vacant: Vec<usize>,
+    /// Indices of slots, looked up by the hash of their key.
+    index: HashTable<usize>,
+    hasher: S,
+    /// Indices of slots ordered by their deadlines, ties are broken by order of insertion.
+    order: BTreeMap<(Instant, u64), usize>,
+    /// Indices of slots that the queue has expired but are yet to be delivered, ordered by priority
+    /// and then deadline.
+    due: BTreeMap<DueKey, usize>,
+    len: usize,
+    /// Incremented on every insertion, uniquely identifies a slot.
+    seq: u64,
+    /// Shrink when capacity grows beyond this many times the number of pending timeouts.
+    auto_shrink: Option<usize>,
+    /// Upper bound of the random extra delay added to every insert with a period.
+    jitter: Duration,
+    default_delay: Option<Duration>,
+    duplicates: Duplicates,
+    max_entries: Option<(usize, Overflow)>,
+    /// Timeouts that expired while waiting for space to insert, delivered before all others.
+    held: VecDeque<Expired<(K, V)>>,
+    /// Slots inserted under each group label.
+    groups: HashMap<String, Vec<Id>>,
+    /// Slots carrying each tag.
+    tags: HashMap<String, Vec<Id>>,
+    /// Instant at which the passage of time was frozen, if paused.
+    paused: Option<Instant>,
+    /// Set once closed, after which no timeouts are inserted and recurring ones aren't re-armed.
+    closed: bool,
+    /// Task waiting on the delay-map while paused, to be woken up on resume.
+    waker: Option<Waker>,
+    observers: Vec<Callback<K, V>>,
+    /// Subscribers of lifecycle events and observers, dropped once they have gone away.
+    emitters: Vec<Emitter<K>>,
+    /// Slots inserted with a cancellation token, including some that may have gone since.
+    #[cfg(feature = "tokio")]
+    cancellable: Vec<Id>,
+    #[cfg(feature = "channels")]
+    deadline_watch: Option<watch::Sender<Option<Instant>>>,
+    /// Senders to be notified once the slot with each sequence number expires, dropped along with
+    /// the slot otherwise.
+    #[cfg(feature = "channels")]
+    waiting: HashMap<u64, Vec<oneshot::Sender<()>>>,
+    /// Watches of whether each watermark has been reached, dropped once no one is watching.
+    #[cfg(feature = "channels")]
+    watermarks: Vec<(Watermark, watch::Sender<bool>)>,
+    rng: Rng,
+    /// Counters of activity, the number of removals is derived from the others when read.
+    stats: Stats,
+    /// Number of timeouts that were removed as they expired for the last time.
+    retired: u64,
+    /// Configuration the delay-map was built with, that its clones are built with too.
+    config: DelayHandlerBuilder,
+    clock_mode: ClockMode,
+    clock_gap: Option<(Duration, ClockGap)>,
+    /// Number of timeouts with a wall-clock deadline.
+    walls: usize,
+    /// Watches for the wall clock jumping against the monotonic clock, while there are timeouts with
+    /// a wall-clock deadline or gaps in time are watched for.
+    wall_check: Option<WallCheck>,
+    #[cfg(feature = "metrics")]
+    metrics: Option<Metrics>,
+    #[cfg(feature = "tracing")]
+    fmt_key: Option<FmtKey<K>>,
+}
+
+/// How often the wall clock is compared against the monotonic clock.
+const WALL_CHECK: Duration = Duration::from_secs(1);
+/// How far the clocks may drift apart between checks before wall-clock deadlines are re-anchored.
+const WALL_DRIFT: Duration = Duration::from_millis(100);
+
+/// Instants at which both clocks were last read, along with the sleep until the next check.
+struct WallCheck {
+    monotonic: Instant,
+    wall: SystemTime,
+    sleep: Sleep,
+}
+
+/// Hasher that keys are hashed with unless configured with another, SipHash as used by
+/// [`HashMap`] by default, which is resistant to keys chosen by an attacker to collide. With the
+/// `ahash` feature it is the much faster [aHash](https://docs.rs/ahash), for keys such as integers and UUIDs.
+#[cfg(not(feature = "ahash"))]
+pub type DefaultHashBuilder = std::collections::hash_map::RandomState;
+/// Hasher that keys are hashed with unless configured with another, the much faster
+/// [aHash](ahash) as the `ahash` feature is enabled, instead of SipHash as used by [`HashMap`].
+#[cfg(feature = "ahash")]
+pub type DefaultHashBuilder = ahash::RandomState;
+
+/// Number of expiries a subscriber can fall behind by, before it starts missing them.
+#[cfg(feature = "channels")]
+pub(crate) const BROADCAST_CAPACITY: usize = 1024;
+
+/// Capacity below which automatic shrinking is never attempted, avoids churn on small delay-maps.
+const MIN_SHRINK_CAPACITY: usize = 1024;
+
+/// Number of pending timeouts listed when debug formatting a delay-map, the rest are elided.
+const DEBUG_ENTRIES: usize = 16;
+
+/// Orders due slots by highest priority first, then by deadline and order of insertion.
+type DueKey = (Reverse<u8>, Instant, u64);
+
+/// Identifies a slot in the delay-map, without having to refer to its key. The sequence number
+/// tells it apart from later slots that reuse the same index.
+#[derive(Clone, Copy)]
+struct Id {
+    index: usize,
+    seq: u64,
+}
+
+/// A key, its payload and the associated bookkeeping.
+struct Slot<K, V> {
+    key: K,
+    value: V,
+    /// Hash of the key, to find it in the index without hashing it again.
+    hash: u64,
+    /// Key of the timeout in the queue, none while it is due, frozen or was due as soon as it was scheduled.
+    delay: Option<QueueKey>,
+    deadline: Instant,
+    /// Period the timeout was last scheduled with, reused when it is touched.
+    ttl: Duration,
+    /// Time that was remaining when the timeout was frozen, it is not in the queue while frozen.
+    frozen: Option<Duration>,
+    /// Set once the queue has expired the timeout, it is then waiting in the backlog of due slots.
+    due: bool,
+    priority: u8,
+    seq: u64,
+    /// Number of times the timeout has expired, only ever more than one when recurring.
+    attempt: u32,
+    recurring: Option<Recurring<K, V>>,
+    action: Option<Action<K, V>>,
+    /// The cancellation token the timeout was inserted with, if any.
+    #[cfg(feature = "tokio")]
+    cancel: Option<Cancel>,
+    group: Option<String>,
+    tags: Vec<String>,
+    /// Wall-clock time the timeout is due at, its deadline is re-anchored to it whenever the clocks
+    /// drift apart.
+    wall: Option<SystemTime>,
+}
+
+/// Called with every key and payload as they timeout, dropped once it returns false.
+pub(crate) type Callback<K, V> = Box<dyn FnMut(&K, &V) -> bool + Send + Sync>;
+
+/// Called with every lifecycle event, dropped once it returns false.
+type Emitter<K> = Box<dyn FnMut(&Event<&K>) -> bool + Send + Sync>;
+
+/// Run with the key and payload of a timeout on expiry, instead of them being handed out.
+type Action<K, V> = Box<dyn FnOnce(K, V) + Send + Sync>;
+
+/// A cancellation token, along with a future that completes once it is cancelled, polled along
+/// with the delay-map so that the task polling it is woken up on cancellation.
+#[cfg(feature = "tokio")]
+struct Cancel {
+    token: CancellationToken,
+    cancelled: Pin<Box<dyn std::future::Future<Output = ()> + Send + Sync>>,
+}
+
+/// Describes how a recurring timeout is re-armed after each expiry.
+struct Recurring<K, V> {
+    rearm: Rearm,
+    /// Copies the key and payload, as they are handed out on every expiry.
+    duplicate: fn(&K, &V) -> (K, V),
+}
+
+#[derive(Clone)]
+enum Rearm {
+    Periodic(Duration, Periodic),
+    Backoff(Backoff),
+    #[cfg(feature = "cron")]
+    Cron(Box<Cron>),
+}
+
+/// Schedule of a timeout that expires at the wall-clock times matching a cron expression.
+#[cfg(feature = "cron")]
+#[derive(Clone)]
+struct Cron {
+    schedule: cron::Schedule,
+    /// Occurrence that the timeout is currently scheduled for.
+    next: DateTime<Utc>,
+}
+
+#[cfg(feature = "cron")]
+impl Cron {
+    /// Moves on to the first occurrence after both the current one and `wall`, skipping any that
+    /// were missed, returning how long from `wall` it is.
+    fn advance(&mut self, wall: DateTime<Utc>) -> Option<Duration> {
+        self.next = self.schedule.after(&self.next.max(wall)).next()?;
+
+        Some((self.next - wall).to_std().unwrap_or_default())
+    }
+}
+
+impl<K, V> Slot<K, V> {
+    fn due_key(&self) -> DueKey {
+        (Reverse(self.priority), self.deadline, self.seq)
+    }
+}
+
+impl<K: Clone, V: Clone> Slot<K, V> {
+    /// Copies the slot for a clone of the delay-map, without its action or cancellation token, and
+    /// yet to be scheduled on the clone's queue.
+    fn copy(&self) -> Self {
+        Self {
+            key: self.key.clone(),
+            value: self.value.clone(),
+            hash: self.hash,
+            delay: None,
+            deadline: self.deadline,
+            ttl: self.ttl,
+            frozen: self.frozen,
+            due: self.due,
+            priority: self.priority,
+            seq: self.seq,
+            attempt: self.attempt,
+            recurring: self.recurring.as_ref().map(|recurring| Recurring {
+                rearm: recurring.rearm.clone(),
+                duplicate: recurring.duplicate,
+            }),
+            action: None,
+            #[cfg(feature = "tokio")]
+            cancel: None,
+            group: self.group.clone(),
+            tags: self.tags.clone(),
+            wall: self.wall,
+        }
+    }
+}
+
+impl<K, V> DelayMap<K, V>
+where
+    K: Eq + Hash,
+{
+    /// Creates an empty delay-map, with space for at least `capacity` timeouts before reallocating.
+    pub fn with_capacity(capacity: usize) -> Self {
+        DelayHandlerBuilder::new().capacity(capacity).build_map()
+    }
+}
+
+impl<K, V, S> DelayMap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    /// Creates an empty delay-map, that hashes keys with `hasher`.
+    pub fn with_hasher(hasher: S) -> Self {
+        Self::with_capacity_and_hasher(0, hasher)
+    }
+
+    /// Creates an empty delay-map, with space for at least `capacity` timeouts before reallocating,
+    /// that hashes keys with `hasher`.
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        DelayHandlerBuilder::new()
+            .capacity(capacity)
+            .build_map_with_hasher(hasher)
+    }
+
+    /// Returns a reference to the hasher that keys are hashed with.
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
+
+    pub(crate) fn from_builder(builder: DelayHandlerBuilder, hasher: S) -> Self {
+        let config = builder.clone();
+        Self {
+            queue: Queue::new(
+                builder.backend,
+                builder.clock,
+                builder.resolution,
+                builder.capacity,
+            ),
+            slots: Vec::with_capacity(builder.capacity),
+            vacant: vec![],
+            index: HashTable::with_capacity(builder.capacity),
+            hasher,
+            order: BTreeMap::new(),
+            due: BTreeMap::new(),
+            len: 0,
+            seq: 0,
+            auto_shrink: builder.auto_shrink,
+            jitter: builder.jitter,
+            default_delay: builder.default_delay,
+            duplicates: builder.duplicates,
+            max_entries: builder.max_entries,
+            held: VecDeque::new(),
+            groups: HashMap::new(),
+            tags: HashMap::new(),
+            paused: None,
+            closed: false,
+            waker: None,
+            observers: vec![],
+            emitters: vec![],
+            #[cfg(feature = "tokio")]
+            cancellable: vec![],
+            #[cfg(feature = "channels")]
+            deadline_watch: None,
+            #[cfg(feature = "channels")]
+            waiting: HashMap::new(),
+            #[cfg(feature = "channels")]
+            watermarks: vec![],
+            rng: Rng::new(),
+            stats: Stats::default(),
+            retired: 0,
+            config,
+            clock_mode: builder.clock_mode,
+            clock_gap: builder.clock_gap,
+            walls: 0,
+            wall_check: None,
+            #[cfg(feature = "metrics")]
+            metrics: builder.metrics.as_deref().map(Metrics::new),
+            #[cfg(feature = "tracing")]
+            fmt_key: None,
+        }
+    }
+
+    /// Reserves space for at least `additional` more timeouts to be inserted without reallocating.
+    pub fn reserve(&mut self, additional: usize) {
+        self.queue.reserve(additional);
+        self.slots
+            .reserve(additional.saturating_sub(self.vacant.len()));
+        let slots = &self.slots;
+        self.index
+            .reserve(additional, |&index| slot_hash(slots, index));
+    }
+
+    /// Looks up the timeout associated with `key`, for in-place manipulation, such as inserting it
+    /// if it doesn't exist or rescheduling it if it does, without looking it up twice.
+    ///
+    /// ### Examples
+    /// ```
+    /// # use delay_handler::{DelayMap, Entry};
+    /// # use std::time::Duration;
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let mut map = DelayMap::default();
+    /// match map.entry("session") {
+    ///     Entry::Occupied(mut entry) => {
+    ///         *entry.get_mut() += 1;
+    ///         entry.reset(Duration::from_secs(30));
+    ///     }
+    ///     Entry::Vacant(entry) => {
+    ///         entry.insert(1, Duration::from_secs(30));
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V, S> {
+        let hash = self.hasher.hash_one(&key);
+        match self.find_index(hash, &key) {
+            Some(index) => {
+                let seq = self.slots[index].as_ref().unwrap().seq;
+                Entry::Occupied(OccupiedEntry::new(self, Id { index, seq }))
+            }
+            _ => Entry::Vacant(VacantEntry::new(self, key, hash)),
+        }
+    }
+
+    /// Insert new timeout with associated payload into the map and queue if it doesn't already exist.
+    /// If one already exists, don't insert and drop the provided payload, returning false.
+    /// A timeout with a zero `period` is due right away, to be yielded on the next poll without
+    /// waiting on a timer.
+    ///
+    /// Returns true only if the timeout was newly inserted. With [`Duplicates::Reset`] an existing
+    /// timeout is rescheduled with the new payload instead, which also returns false, as it does for
+    /// every other method that inserts.
+    pub fn insert(&mut self, key: K, value: V, period: Duration) -> bool {
+        let deadline = self.jittered(period, self.jitter);
+        self.insert_at(key, value, deadline)
+    }
+
+    /// Insert new timeout with associated payload into the map and queue if it doesn't already exist,
+    /// with the [default delay](DelayMap::set_default_delay). If one already exists, don't insert and
+    /// drop the provided payload, returning false.
+    ///
+    /// # Panics
+    /// If no default delay has been set.
+    pub fn insert_default(&mut self, key: K, value: V) -> bool {
+        let period = self.default_delay.expect("default delay not set");
+        self.insert(key, value, period)
+    }
+
+    /// Sets the period used by [`insert_default()`](DelayMap::insert_default).
+    pub fn set_default_delay(&mut self, period: Duration) {
+        self.default_delay = Some(period);
+    }
+
+    /// Returns the period used by [`insert_default()`](DelayMap::insert_default), if set.
+    pub fn default_delay(&self) -> Option<Duration> {
+        self.default_delay
+    }
+
+    /// Insert new timeout with associated payload into the map and queue if it doesn't already exist,
+    /// with the delay randomized to somewhere between `period` and `period + jitter`. Useful to
+    /// avoid a lot of timeouts inserted together, from expiring together.
+    /// If one already exists, don't insert and drop the provided payload, returning false.
+    pub fn insert_with_jitter(
+        &mut self,
+        key: K,
+        value: V,
+        period: Duration,
+        jitter: Duration,
+    ) -> bool {
+        let deadline = self.jittered(period, jitter);
+        self.insert_at(key, value, deadline)
+    }
+
+    /// Deadline after `period` from now, randomized by upto `jitter`.
+    fn jittered(&mut self, period: Duration, jitter: Duration) -> Instant {
+        if jitter.is_zero() {
+            return saturating_add(self.now(), period);
+        }
+
+        saturating_add(self.now(), period.saturating_add(self.rng.duration(jitter)))
+    }
+
+    /// Insert new timeout with associated payload, to timeout at the given `deadline`, if it doesn't
+    /// already exist. If one already exists, don't insert and drop the provided payload, returning false.
+    pub fn insert_at(&mut self, key: K, value: V, deadline: Instant) -> bool {
+        self.schedule(key, value, deadline, None)
+    }
+
+    /// Insert new timeout with associated payload, to timeout once the wall clock reaches `wall`,
+    /// if it doesn't already exist. Unlike other deadlines which follow the monotonic clock, it is
+    /// re-anchored whenever the wall clock jumps, such as after the machine was suspended or the
+    /// time was adjusted, as checked every second while the delay-map is polled. Rescheduling the
+    /// timeout makes it follow the monotonic clock. If one already exists, don't insert and drop
+    /// the provided payload, returning false.
+    pub fn insert_at_system_time(&mut self, key: K, value: V, wall: SystemTime) -> bool {
+        let remaining = wall.duration_since(self.system_now()).unwrap_or_default();
+        let deadline = saturating_add(self.now(), remaining);
+        if !self.insert_at(key, value, deadline) {
+            return false;
+        }
+
+        let id = self.inserted(deadline);
+        self.set_wall(id, Some(wall));
+
+        true
+    }
+
+    /// Insert a batch of new timeouts with associated payloads, reserving space for all of them
+    /// upfront, which is faster than inserting them one at a time. As with
+    /// [`insert()`](DelayMap::insert) returning false, returns the timeouts that weren't newly
+    /// inserted: those rejected as their key already exists, or the map is full or closed, along
+    /// with their payloads. With [`Duplicates::Reset`], existing timeouts are rescheduled instead,
+    /// and returned along with the payload they had before.
+    pub fn insert_many<I>(&mut self, timeouts: I) -> Vec<(K, V)>
+    where
+        I: IntoIterator<Item = (K, V, Duration)>,
+    {
+        let timeouts = timeouts.into_iter();
+        self.reserve(timeouts.size_hint().0);
+
+        let mut rejected = vec![];
+        for (key, value, period) in timeouts {
+            match self.id(&key) {
+                Some(id) if self.duplicates == Duplicates::Reset => {
+                    let deadline = self.jittered(period, self.jitter);
+                    let value = self.replace(id, value, deadline);
+                    rejected.push((key, value));
+                    continue;
+                }
+                Some(_) => {
+                    self.stats.duplicates += 1;
+                    rejected.push((key, value));
+                    continue;
+                }
+                _ if self.closed || !self.make_room() => {
+                    rejected.push((key, value));
+                    continue;
+                }
+                _ => {}
+            }
+
+            self.insert(key, value, period);
+        }
+        self.report();
+
+        rejected
+    }
+
+    /// Insert new periodic timeout with associated payload if it doesn't already exist, which first
+    /// times out after `interval` from now and is then automatically re-armed after each expiry,
+    /// until it is removed. With [`Periodic::FixedRate`] ticks are not dropped, even if the consumer
+    /// falls behind. If one already exists, don't insert and drop the provided payload, returning false.
+    ///
+    /// # Panics
+    /// If `interval` is zero.
+    pub fn insert_periodic(&mut self, key: K, value: V, interval: Duration, mode: Periodic) -> bool
+    where
+        K: Clone,
+        V: Clone,
+    {
+        assert!(!interval.is_zero(), "periodic interval must be non-zero");
+
+        let recurring = Recurring {
+            rearm: Rearm::Periodic(interval, mode),
+            duplicate: |key: &K, value: &V| (key.clone(), value.clone()),
+        };
+        self.schedule(
+            key,
+            value,
+            saturating_add(self.now(), interval),
+            Some(recurring),
+        )
+    }
+
+    /// Insert new timeout with associated payload if it doesn't already exist, which is re-armed after
+    /// each expiry with a delay that grows as per the `backoff` policy, until it runs out of attempts
+    /// or is removed. Use [`next_expired()`](DelayMap::next_expired) to learn the attempt count.
+    /// If one already exists, don't insert and drop the provided payload, returning false.
+    pub fn insert_with_backoff(&mut self, key: K, value: V, backoff: Backoff) -> bool
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let recurring = Recurring {
+            rearm: Rearm::Backoff(backoff),
+            duplicate: |key: &K, value: &V| (key.clone(), value.clone()),
+        };
+        self.schedule(
+            key,
+            value,
+            saturating_add(self.now(), backoff.delay(1)),
+            Some(recurring),
+        )
+    }
+
+    /// Insert new timeout with associated payload if it doesn't already exist, that expires at every
+    /// wall-clock time matching the cron `expression`, with seconds, e.g. `"0 */5 * * * *"` for every
+    /// 5 minutes. The next occurrence is recomputed after each expiry, skipping any that were missed.
+    /// If one already exists or the expression has no upcoming occurrences, don't insert and drop
+    /// the provided payload, returning false.
+    ///
+    /// # Errors
+    /// If `expression` isn't a valid cron expression.
+    #[cfg(feature = "cron")]
+    pub fn insert_cron(
+        &mut self,
+        key: K,
+        value: V,
+        expression: &str,
+    ) -> Result<bool, cron::error::Error>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let wall = DateTime::from(self.system_now());
+        let mut cron = Cron {
+            schedule: expression.parse()?,
+            next: wall,
+        };
+        let delay = match cron.advance(wall) {
+            Some(delay) => delay,
+            _ => return Ok(false),
+        };
+        let recurring = Recurring {
+            rearm: Rearm::Cron(Box::new(cron)),
+            duplicate: |key: &K, value: &V| (key.clone(), value.clone()),
+        };
+
+        Ok(self.schedule(
+            key,
+            value,
+            saturating_add(self.now(), delay),
+            Some(recurring),
+        ))
+    }
+
+    /// Insert new timeout with associated payload under the `group` label if it doesn't already exist,
+    /// so that it can later be removed along with every other timeout in the group, with
+    /// [`remove_group()`](DelayMap::remove_group). If one already exists, don't insert and drop
+    /// the provided payload, returning false.
+    pub fn insert_in_group(&mut self, group: &str, key: K, value: V, period: Duration) -> bool {
+        let deadline = self.jittered(period, self.jitter);
+        if !self.insert_at(key, value, deadline) {
+            return false;
+        }
+
+        let id = self.inserted(deadline);
+        self.slot_mut(id).group = Some(group.to_owned());
+        self.groups.entry(group.to_owned()).or_default().push(id);
+
+        true
+    }
+
+    /// Insert new timeout with associated payload if it doesn't already exist, that runs `f` with the
+    /// key and payload on expiry, instead of them being returned from [`next()`](DelayMap::next).
+    /// The action runs while the delay-map is being polled. If one already exists, don't insert and
+    /// drop the provided payload and action, returning false.
+    pub fn insert_with<F>(&mut self, key: K, value: V, period: Duration, f: F) -> bool
+    where
+        F: FnOnce(K, V) + Send + Sync + 'static,
+    {
+        let deadline = self.jittered(period, self.jitter);
+        if !self.insert_at(key, value, deadline) {
+            return false;
+        }
+
+        let id = self.inserted(deadline);
+        self.slot_mut(id).action = Some(Box::new(f));
+
+        true
+    }
+
+    /// Insert new timeout with associated payload if it doesn't already exist, that is automatically
+    /// removed when `token` is cancelled, the next time the delay-map is polled. Ties the lifetime of
+    /// the timeout to that of a task. If one already exists or the token is already cancelled,
+    /// don't insert and drop the provided payload, returning false.
+    ///
+    /// Tokens are watched without spawning tasks, so that it works with any executor, by checking
+    /// each of them whenever the delay-map is polled.
+    #[cfg(feature = "tokio")]
+    pub fn insert_with_token(
+        &mut self,
+        key: K,
+        value: V,
+        period: Duration,
+        token: CancellationToken,
+    ) -> bool {
+        if token.is_cancelled() {
+            return false;
+        }
+
+        let deadline = self.jittered(period, self.jitter);
+        if !self.insert_at(key, value, deadline) {
+            return false;
+        }
+
+        let id = self.inserted(deadline);
+        let cancelled = {
+            let token = token.clone();
+            Box::pin(async move { token.cancelled().await })
+        };
+        self.slot_mut(id).cancel = Some(Cancel { token, cancelled });
+        self.cancellable.push(id);
+
+        true
+    }
+
+    /// Removes every timeout whose cancellation token has been cancelled, registering the current
+    /// task for wakeup on the next cancellation, if given a context.
+    #[cfg(feature = "tokio")]
+    fn purge_cancelled(&mut self, mut cx: Option<&mut Context<'_>>) {
+        let mut i = 0;
+        while let Some(&id) = self.cancellable.get(i) {
+            // The timeout may have expired or been removed since
+            let cancel = self
+                .slots
+                .get_mut(id.index)
+                .and_then(Option::as_mut)
+                .filter(|slot| slot.seq == id.seq)
+                .and_then(|slot| slot.cancel.as_mut());
+            let cancelled = match (cancel, cx.as_deref_mut()) {
+                (Some(cancel), Some(cx)) => cancel.cancelled.as_mut().poll(cx).is_ready(),
+                (Some(cancel), None) => cancel.token.is_cancelled(),
+                _ => {
+                    self.cancellable.swap_remove(i);
+                    continue;
+                }
+            };
+            if !cancelled {
+                i += 1;
+                continue;
+            }
+
+            self.cancellable.swap_remove(i);
+            let slot = self.unlink(id);
+            self.dequeue(&slot);
+        }
+    }
+
+    #[cfg(not(feature = "tokio"))]
+    fn purge_cancelled(&mut self, _: Option<&mut Context<'_>>) {}
+
+    /// Insert new timeout with associated payload if it doesn't already exist, with a `priority` that
+    /// decides the order of delivery among timeouts that have already expired, highest first.
+    /// Timeouts inserted otherwise have the lowest priority of zero. If one already exists, don't
+    /// insert and drop the provided payload, returning false.
+    pub fn insert_with_priority(
+        &mut self,
+        key: K,
+        value: V,
+        period: Duration,
+        priority: u8,
+    ) -> bool {
+        let deadline = self.jittered(period, self.jitter);
+        if !self.insert_at(key, value, deadline) {
+            return false;
+        }
+
+        let id = self.inserted(deadline);
+        self.set_priority(id, priority);
+
+        true
+    }
+
+    /// Insert new timeout with associated payload carrying one or more `tags` if it doesn't already
+    /// exist. Unlike groups, a timeout may carry many tags and can later be inspected with
+    /// [`tagged()`](DelayMap::tagged) or removed with [`remove_by_tag()`](DelayMap::remove_by_tag)
+    /// by any of them. If one already exists, don't insert and drop the provided payload, returning false.
+    pub fn insert_with_tags(&mut self, key: K, value: V, period: Duration, tags: &[&str]) -> bool {
+        let deadline = self.jittered(period, self.jitter);
+        if !self.insert_at(key, value, deadline) {
+            return false;
+        }
+
+        let id = self.inserted(deadline);
+        for &tag in tags {
+            let slot = self.slot_mut(id);
+            if slot.tags.iter().any(|t| t == tag) {
+                continue;
+            }
+
+            slot.tags.push(tag.to_owned());
+            self.tags.entry(tag.to_owned()).or_default().push(id);
+        }
+
+        true
+    }
+
+    /// Identifies the slot that was just inserted to timeout at `deadline`, which is always the one
+    /// with the latest sequence number.
+    fn inserted(&self, deadline: Instant) -> Id {
+        let seq = self.seq;
+
+        Id {
+            index: self.order[&(deadline, seq)],
+            seq,
+        }
+    }
+
+    fn schedule(
+        &mut self,
+        key: K,
+        value: V,
+        deadline: Instant,
+        recurring: Option<Recurring<K, V>>,
+    ) -> bool {
+        // Hashing the key once, both to look for an existing timeout and to insert a new one
+        let hash = self.hasher.hash_one(&key);
+        if let Some(index) = self.find_index(hash, &key) {
+            let id = Id {
+                index,
+                seq: self.slots[index].as_ref().unwrap().seq,
+            };
+            if self.duplicates == Duplicates::Reset {
+                self.replace(id, value, deadline);
+            } else {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(key = ?Key(&key, self.fmt_key), "rejected duplicate timeout");
+                self.stats.duplicates += 1;
+                self.report();
+            }
+
+            return false;
+        }
+
+        self.schedule_vacant(hash, key, value, deadline, recurring)
+    }
+
+    /// Reschedules the existing timeout identified by `id` to `deadline` with a new payload, as done
+    /// for [`Duplicates::Reset`], returning the payload it replaced.
+    fn replace(&mut self, id: Id, value: V, deadline: Instant) -> V {
+        let ttl = deadline.saturating_duration_since(self.now());
+        let slot = self.slot_mut(id);
+        let value = std::mem::replace(&mut slot.value, value);
+        slot.ttl = ttl;
+        self.reschedule(id, deadline);
+
+        value
+    }
+
+    /// Inserts a new timeout for `key`, that is known not to exist already and hashes to `hash`.
+    fn schedule_vacant(
+        &mut self,
+        hash: u64,
+        key: K,
+        value: V,
+        deadline: Instant,
+        recurring: Option<Recurring<K, V>>,
+    ) -> bool {
+        if self.closed {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(key = ?Key(&key, self.fmt_key), "rejected timeout, delay-map is closed");
+            return false;
+        }
+        if !self.make_room() {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(key = ?Key(&key, self.fmt_key), "rejected timeout, delay-map is full");
+            return false;
+        }
+
+        self.seq += 1;
+        let index = self.vacant.pop().unwrap_or(self.slots.len());
+        let ttl = deadline.saturating_duration_since(self.now());
+        #[cfg(feature = "tracing")]
+        tracing::debug!(id = self.seq, key = ?Key(&key, self.fmt_key), delay = ?ttl, "inserted timeout");
+        let slot = Some(Slot {
+            key,
+            value,
+            hash,
+            delay: None,
+            deadline,
+            ttl,
+            frozen: None,
+            due: false,
+            priority: 0,
+            seq: self.seq,
+            attempt: 0,
+            recurring,
+            action: None,
+            #[cfg(feature = "tokio")]
+            cancel: None,
+            group: None,
+            tags: vec![],
+            wall: None,
+        });
+        match self.slots.get_mut(index) {
+            Some(vacant) => *vacant = slot,
+            _ => self.slots.push(slot),
+        }
+        let slots = &self.slots;
+        self.index
+            .insert_unique(hash, index, |&index| slot_hash(slots, index));
+        self.order.insert((deadline, self.seq), index);
+        self.publish_deadline();
+        let id = Id {
+            index,
+            seq: self.seq,
+        };
+        self.set_wall(id, self.wall_for(deadline));
+        self.enqueue(id, deadline);
+        let key = &self.slots[index].as_ref().unwrap().key;
+        emit(&mut self.emitters, self.queue.clock(), |at| {
+            Event::Inserted { key, deadline, at }
+        });
+        self.len += 1;
+        self.publish_depth();
+        self.stats.inserted += 1;
+        self.stats.peak_depth = self.stats.peak_depth.max(self.len);
+        self.report();
+
+        true
+    }
+
+    /// Schedules a timeout taken out of another delay-map to expire at `deadline`, keeping its
+    /// priority, group, tags, recurrence, action and whether it is frozen. An existing timeout for
+    /// the key is reset with its payload if [duplicates](Duplicates::Reset) are, otherwise the key
+    /// and payload are handed back, as they are if there is no room.
+    fn adopt(&mut self, slot: Slot<K, V>, deadline: Instant) -> Result<Id, (K, V)> {
+        let Slot {
+            key,
+            value,
+            ttl,
+            frozen,
+            priority,
+            attempt,
+            recurring,
+            action,
+            group,
+            tags,
+            wall,
+            ..
+        } = slot;
+
+        let hash = self.hasher.hash_one(&key);
+        if let Some(index) = self.find_index(hash, &key) {
+            if self.duplicates != Duplicates::Reset {
+                return Err((key, value));
+            }
+
+            let id = Id {
+                index,
+                seq: self.slots[index].as_ref().unwrap().seq,
+            };
+            let slot = self.slot_mut(id);
+            slot.value = value;
+            slot.ttl = ttl;
+            self.reschedule(id, deadline);
+
+            return Ok(id);
+        }
+        if self.closed || !self.make_room() {
+            return Err((key, value));
+        }
+
+        self.schedule_vacant(hash, key, value, deadline, recurring);
+        let id = self.inserted(deadline);
+        if let Some(group) = &group {
+            self.groups.entry(group.clone()).or_default().push(id);
+        }
+        for tag in &tags {
+            self.tags.entry(tag.clone()).or_default().push(id);
+        }
+        let slot = self.slot_mut(id);
+        slot.ttl = ttl;
+        slot.attempt = attempt;
+        slot.action = action;
+        slot.group = group;
+        slot.tags = tags;
+        self.set_priority(id, priority);
+        if wall.is_some() {
+            self.set_wall(id, wall);
+        }
+        if frozen.is_some() {
+            self.suspend(id);
+        }
+
+        Ok(id)
+    }
+
+    /// Ensures there is space for one more timeout, evicting as per the overflow policy if full.
+    /// Returns false if the timeout should be rejected.
+    fn make_room(&mut self) -> bool {
+        let (max, overflow) = match self.max_entries {
+            Some((max, _)) if self.len < max => return true,
+            Some(bound) => bound,
+            _ => return true,
+        };
+
+        let evicted = match overflow {
+            Overflow::Reject => return false,
+            Overflow::EvictSoonest => self.order.first_key_value(),
+            Overflow::EvictLatest => self.order.last_key_value(),
+        };
+        let id = match evicted {
+            Some((&(_, seq), &index)) => Id { index, seq },
+            _ => return false,
+        };
+
+        let slot = self.unlink(id);
+        self.dequeue(&slot);
+
+        self.len < max
+    }
+
+    /// Insert new timeout with associated payload if it doesn't already exist, otherwise reschedule
+    /// the existing one to timeout after `period` from now, replacing its payload.
+    pub fn insert_or_reset(&mut self, key: K, value: V, period: Duration) -> Scheduled {
+        match self.entry(key) {
+            Entry::Occupied(mut entry) => {
+                entry.insert(value);
+                entry.reset(period);
+
+                Scheduled::Reset
+            }
+            Entry::Vacant(entry) => match entry.insert(value, period) {
+                Some(_) => Scheduled::Inserted,
+                _ => Scheduled::Rejected,
+            },
+        }
+    }
+
+    /// Insert new timeout with associated payload into the map and queue if it doesn't already exist,
+    /// first waiting for space to free up if the delay-map is [full](DelayMap::set_max_entries).
+    /// Timeouts that expire while waiting are held back, to be returned by [`next()`](DelayMap::next)
+    /// before all others.
+    pub async fn insert_when_ready(&mut self, key: K, value: V, period: Duration) -> bool {
+        poll_fn(|cx| self.poll_ready(cx)).await;
+        self.insert(key, value, period)
+    }
+
+    /// Polls for space to free up in the delay-map, registering the current task for wakeup
+    /// if it is still full.
+    pub fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        while !self.closed && self.is_full() {
+            if self.paused.is_some() {
+                self.waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+
+            match ready!(self.poll_due(cx)) {
+                Some(id) => {
+                    if let Some(expired) = self.fire(id) {
+                        self.held.push_back(expired);
+                    }
+                }
+                None => break,
+            }
+        }
+
+        Poll::Ready(())
+    }
+
+    /// Freezes the passage of time for all timeouts, none expire until [resumed](DelayMap::resume).
+    /// Timeouts inserted or reset while paused are scheduled relative to the instant of pausing,
+    /// so that they too have all of their period remaining on resume.
+    pub fn pause(&mut self) {
+        if self.paused.is_none() {
+            self.paused = Some(self.queue.clock().now());
+        }
+    }
+
+    /// Resumes the passage of time after a [pause](DelayMap::pause), pushing back every deadline
+    /// by the length of the pause, so that all timeouts have the same time remaining as they did
+    /// on pausing.
+    pub fn resume(&mut self) {
+        let paused = match self.paused.take() {
+            Some(paused) => paused,
+            _ => return,
+        };
+
+        // Timeouts that were already due on pausing stay due, with the deadline they were due at
+        let elapsed = self.queue.clock().now().saturating_duration_since(paused);
+        self.shift_deadlines(|slot| {
+            (!slot.due && slot.deadline > paused).then(|| saturating_add(slot.deadline, elapsed))
+        });
+    }
+
+    /// Compares the wall clock against the monotonic clock right away, instead of at the next
+    /// periodic check, such as on being notified that the machine has resumed from suspend.
+    /// Timeouts with a wall-clock deadline are re-anchored and a gap in time since the clocks were
+    /// last compared, if [watched for](DelayHandlerBuilder::on_clock_gap), is handled and returned.
+    pub fn resync(&mut self) -> Option<Duration> {
+        self.compare_clocks()
+    }
+
+    /// Moves the deadline of every scheduled timeout to the one returned by `shift`, given its slot.
+    /// Timeouts for which it returns `None` are left as is.
+    fn shift_deadlines(&mut self, mut shift: impl FnMut(&Slot<K, V>) -> Option<Instant>) {
+        for ((deadline, seq), index) in std::mem::take(&mut self.order) {
+            let id = Id { index, seq };
+            let slot = self.slot_mut(id);
+            let deadline = match shift(slot) {
+                Some(shifted) => shifted,
+                _ => {
+                    self.order.insert((deadline, seq), index);
+                    continue;
+                }
+            };
+            let previous = slot.due_key();
+            slot.deadline = deadline;
+            let (delay, due) = (slot.delay, slot.due);
+
+            if due {
+                self.due.remove(&previous);
+                self.due.insert((previous.0, deadline, seq), index);
+            } else if let Some(delay) = delay {
+                self.queue.reset_at(&delay, deadline);
+            }
+            self.order.insert((deadline, seq), index);
+            if let Some(wall) = self.wall_for(deadline) {
+                self.set_wall(id, Some(wall));
+            }
+        }
+        self.publish_deadline();
+
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Check if the passage of time is [paused](DelayMap::pause).
+    pub fn is_paused(&self) -> bool {
+        self.paused.is_some()
+    }
+
+    /// The current instant as far as scheduling is concerned, frozen while paused.
+    pub(crate) fn now(&self) -> Instant {
+        self.paused.unwrap_or_else(|| self.queue.clock().now())
+    }
+
+    /// The current wall-clock time, as told by the clock that timeouts are scheduled against.
+    pub(crate) fn system_now(&self) -> SystemTime {
+        self.queue.clock().system_now()
+    }
+
+    /// The clock that timeouts are scheduled against.
+    pub(crate) fn clock(&self) -> &dyn Clock {
+        self.queue.clock()
+    }
+
+    /// Subscribe to a copy of every key and payload as they timeout, in addition to them being
+    /// returned from [`next()`](DelayMap::next), which must still be called to drive the delay-map.
+    /// Subscribers that fall more than 1024 expiries behind miss the oldest of them, see
+    /// [`RecvError::Lagged`](broadcast::error::RecvError::Lagged).
+    #[cfg(feature = "channels")]
+    pub fn subscribe(&mut self) -> broadcast::Receiver<(K, V)>
+    where
+        K: Clone + Send + 'static,
+        V: Clone + Send + 'static,
+    {
+        let (sender, receiver) = broadcast::channel(BROADCAST_CAPACITY);
+        self.observe(Box::new(move |key, value| {
+            sender.send((key.clone(), value.clone())).is_ok()
+        }));
+
+        receiver
+    }
+
+    /// Subscribe to every change in the lifecycle of timeouts, as they are inserted, reset, removed
+    /// and expire, independently of [`next()`](DelayMap::next), which must still be called to drive
+    /// the delay-map. Subscribers that fall more than 1024 events behind miss the oldest of them, see
+    /// [`RecvError::Lagged`](broadcast::error::RecvError::Lagged).
+    ///
+    /// ### Examples
+    /// ```no_run
+    /// # use delay_handler::{DelayMap, Event};
+    /// # use std::time::Duration;
+    /// # async fn run() {
+    /// let mut map = DelayMap::default();
+    /// let mut events = map.events();
+    /// tokio::spawn(async move {
+    ///     while let Ok(event) = events.recv().await {
+    ///         if let Event::Removed { key, at } = event {
+    ///             println!("{key} cancelled at {at:?}");
+    ///         }
+    ///     }
+    /// });
+    ///
+    /// map.insert(1, "hello", Duration::from_secs(10));
+    /// map.remove(&1);
+    /// # }
+    /// ```
+    #[cfg(feature = "channels")]
+    pub fn events(&mut self) -> broadcast::Receiver<Event<K>>
+    where
+        K: Clone + Send + 'static,
+    {
+        let (sender, receiver) = broadcast::channel(BROADCAST_CAPACITY);
+        self.emitters
+            .push(Box::new(move |event| sender.send(event.cloned()).is_ok()));
+
+        receiver
+    }
+
+    /// Installs an [`Observer`], whose methods are invoked synchronously as timeouts are inserted,
+    /// reset, removed and expire, for as long as the delay-map lives. Unlike
+    /// [`events()`](DelayMap::events), keys needn't be cloned and nothing is missed.
+    pub fn add_observer<O>(&mut self, mut observer: O)
+    where
+        O: Observer<K> + 'static,
+    {
+        self.emitters.push(Box::new(move |event| {
+            observer::notify(&mut observer, event);
+            true
+        }));
+    }
+
+    /// Registers a callback that is invoked with every key and payload as they timeout, before
+    /// they are returned from [`next()`](DelayMap::next).
+    pub fn on_expire<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &V) + Send + Sync + 'static,
+    {
+        self.observe(Box::new(move |key, value| {
+            f(key, value);
+            true
+        }));
+    }
+
+    pub(crate) fn observe(&mut self, observer: Callback<K, V>) {
+        self.observers.push(observer);
+    }
+
+    #[cfg(feature = "persistent")]
+    pub(crate) fn emit_to(&mut self, emitter: Emitter<K>) {
+        self.emitters.push(emitter);
+    }
+
+    /// Check if the number of pending timeouts has reached the [bound](DelayMap::set_max_entries).
+    pub fn is_full(&self) -> bool {
+        self.max_entries().is_some_and(|max| self.len >= max)
+    }
+
+    /// Returns the maximum number of pending timeouts allowed, if bounded.
+    pub fn max_entries(&self) -> Option<usize> {
+        self.max_entries.map(|(max, _)| max)
+    }
+
+    /// Bounds the number of pending timeouts to `max`, with `overflow` deciding what happens on
+    /// inserting into a full delay-map. Passing `None` removes the bound, which is the default.
+    pub fn set_max_entries(&mut self, bound: Option<(usize, Overflow)>) {
+        self.max_entries = bound;
+        self.publish_depth();
+    }
+
+    /// Prematurely removes timeout from delay-map, returning the associated payload if it existed.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (_, value, _) = self.take(key)?;
+
+        Some(value)
+    }
+
+    /// Prematurely removes timeout from delay-map, returning the owned key, associated payload and
+    /// time that was remaining, if it existed. Useful to re-schedule the timeout elsewhere.
+    pub fn take<Q>(&mut self, key: &Q) -> Option<(K, V, Duration)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let id = self.id(key)?;
+        let slot = self.unlink(id);
+        self.dequeue(&slot);
+        self.maybe_shrink();
+
+        let remaining = slot
+            .frozen
+            .unwrap_or_else(|| slot.deadline.saturating_duration_since(self.now()));
+
+        Some((slot.key, slot.value, remaining))
+    }
+
+    /// Prematurely removes a batch of timeouts from delay-map, returning the keys that existed along
+    /// with their associated payloads. Faster than removing them one at a time, as the map is only
+    /// shrunk once at the end.
+    pub fn remove_many<'a, Q, I>(&mut self, keys: I) -> Vec<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized + 'a,
+        I: IntoIterator<Item = &'a Q>,
+    {
+        let mut removed = vec![];
+        for key in keys {
+            if let Some(id) = self.id(key) {
+                let slot = self.unlink(id);
+                self.dequeue(&slot);
+                removed.push((slot.key, slot.value));
+            }
+        }
+        self.maybe_shrink();
+
+        removed
+    }
+
+    /// Prematurely removes every timeout inserted under the `group` label, returning the keys along
+    /// with their associated payloads.
+    pub fn remove_group(&mut self, group: &str) -> Vec<(K, V)> {
+        let ids = self.groups.remove(group).unwrap_or_default();
+        self.remove_ids(ids)
+    }
+
+    /// Number of timeouts currently pending under the `group` label.
+    pub fn group_len(&self, group: &str) -> usize {
+        self.groups.get(group).map_or(0, Vec::len)
+    }
+
+    /// Prematurely removes every timeout carrying `tag`, returning the keys along with their
+    /// associated payloads.
+    pub fn remove_by_tag(&mut self, tag: &str) -> Vec<(K, V)> {
+        let ids = self.tags.remove(tag).unwrap_or_default();
+        self.remove_ids(ids)
+    }
+
+    /// Iterate over all pending keys carrying `tag`, with their associated payloads.
+    pub fn tagged<'a>(&'a self, tag: &str) -> impl Iterator<Item = (&'a K, &'a V)> + 'a {
+        self.tags.get(tag).into_iter().flatten().map(|&id| {
+            let slot = self.slot(id);
+
+            (&slot.key, &slot.value)
+        })
+    }
+
+    fn remove_ids(&mut self, ids: Vec<Id>) -> Vec<(K, V)> {
+        let mut removed = Vec::with_capacity(ids.len());
+        for id in ids {
+            let slot = self.unlink(id);
+            self.dequeue(&slot);
+            removed.push((slot.key, slot.value));
+        }
+        self.maybe_shrink();
+
+        removed
+    }
+
+    /// Updates the deadline of an existing timeout in place, to timeout after `period` from now.
+    /// If it didn't already exist returns false.
+    pub fn reset<Q>(&mut self, key: &Q, period: Duration) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        match self.id(key) {
+            Some(id) => {
+                self.slot_mut(id).ttl = period;
+                self.reschedule(id, saturating_add(self.now(), period));
+
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Reschedules an existing timeout to expire immediately, so that it is delivered by
+    /// [`next()`](DelayMap::next) like any other, after those that are already due. Frozen timeouts
+    /// are thawed. If it didn't already exist returns false.
+    pub fn expire_now<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let id = match self.id(key) {
+            Some(id) => id,
+            _ => return false,
+        };
+
+        if let Some(remaining) = self.slot_mut(id).frozen.as_mut() {
+            *remaining = Duration::ZERO;
+            return self.unfreeze(id);
+        }
+
+        self.reschedule(id, self.now());
+
+        true
+    }
+
+    /// Marks every pending timeout that isn't already due as due, so that all of them are delivered
+    /// by [`next()`](DelayMap::next), in order of priority and deadline as they would have been. They
+    /// keep their deadline, which [`Expired`] reports. Frozen timeouts are thawed.
+    pub fn expire_all(&mut self) {
+        let frozen: Vec<Id> = self
+            .slots
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| {
+                let slot = slot.as_ref().filter(|slot| slot.frozen.is_some())?;
+
+                Some(Id {
+                    index,
+                    seq: slot.seq,
+                })
+            })
+            .collect();
+        for id in frozen {
+            self.slot_mut(id).frozen = Some(Duration::ZERO);
+            self.unfreeze(id);
+        }
+
+        let pending: Vec<Id> = self
+            .order
+            .iter()
+            .map(|(&(_, seq), &index)| Id { index, seq })
+            .filter(|&id| !self.slot(id).due)
+            .collect();
+        for id in pending {
+            if let Some(delay) = self.slot(id).delay {
+                self.queue.remove(&delay);
+            }
+            self.push_due(id);
+        }
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Pushes back the deadline of an existing timeout by `extra`, without having to know how much
+    /// time it has remaining. If it didn't already exist returns false.
+    pub fn extend_delay<Q>(&mut self, key: &Q, extra: Duration) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let id = match self.id(key) {
+            Some(id) => id,
+            _ => return false,
+        };
+
+        let slot = self.slot_mut(id);
+        match slot.frozen.as_mut() {
+            Some(remaining) => *remaining = remaining.saturating_add(extra),
+            _ => {
+                let deadline = saturating_add(slot.deadline, extra);
+                self.reschedule(id, deadline);
+            }
+        }
+
+        true
+    }
+
+    /// Pushes back the deadline of an existing timeout, to timeout after the same period it was last
+    /// scheduled with, from now. Gives idle-timeout semantics, where a session or cache entry only
+    /// expires once it hasn't been touched for that long. If it didn't already exist returns false.
+    pub fn touch<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.get_and_touch(key).is_some()
+    }
+
+    /// Returns the payload associated with `key`, [touching](DelayMap::touch) the timeout so that
+    /// it expires only after being idle for the period it was last scheduled with.
+    pub fn get_and_touch<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let id = self.id(key)?;
+        let deadline = saturating_add(self.now(), self.slot(id).ttl);
+        self.reschedule(id, deadline);
+
+        Some(&mut self.slot_mut(id).value)
+    }
+
+    /// Returns the payload associated with `key`, without touching the timeout.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.find(key).map(|slot| &slot.value)
+    }
+
+    /// Returns the payload associated with `key` for modification, such as to accumulate updates
+    /// while the timeout is pending, without touching the timeout.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.find_mut(key).map(|slot| &mut slot.value)
+    }
+
+    /// Replaces the payload associated with `key`, without touching the timeout, returning the
+    /// previous one. If it didn't exist, hands back the provided payload as an error.
+    pub fn set_value<Q>(&mut self, key: &Q, value: V) -> Result<V, V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        match self.get_mut(key) {
+            Some(previous) => Ok(std::mem::replace(previous, value)),
+            _ => Err(value),
+        }
+    }
+
+    /// Moves the deadline of the slot identified by `id`, frozen slots only have the time remaining
+    /// updated.
+    fn reschedule(&mut self, id: Id, deadline: Instant) {
+        let now = self.now();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            id = id.seq,
+            key = ?Key(&self.slot(id).key, self.fmt_key),
+            delay = ?deadline.saturating_duration_since(now),
+            "reset timeout"
+        );
+        let key = &self.slots[id.index].as_ref().unwrap().key;
+        emit(&mut self.emitters, self.queue.clock(), |at| Event::Reset {
+            key,
+            deadline,
+            at,
+        });
+        let slot = self.slot_mut(id);
+        if let Some(remaining) = slot.frozen.as_mut() {
+            *remaining = deadline.saturating_duration_since(now);
+            return;
+        }
+        self.set_wall(id, self.wall_for(deadline));
+
+        let slot = self.slot_mut(id);
+        let previous = slot.due_key();
+        slot.deadline = deadline;
+        let (due, delay) = (std::mem::take(&mut slot.due), slot.delay);
+        if due {
+            self.due.remove(&previous);
+        }
+        self.order.remove(&(previous.1, id.seq));
+        self.order.insert((deadline, id.seq), id.index);
+        self.publish_deadline();
+
+        match delay {
+            Some(delay) if deadline > now => self.queue.reset_at(&delay, deadline),
+            Some(delay) => {
+                self.queue.remove(&delay);
+                self.enqueue(id, deadline);
+            }
+            _ => self.enqueue(id, deadline),
+        }
+    }
+
+    /// Changes the priority of the slot identified by `id`, moving it within the backlog if it is due.
+    fn set_priority(&mut self, id: Id, priority: u8) {
+        let slot = self.slot_mut(id);
+        let (previous, due) = (slot.due_key(), slot.due);
+        slot.priority = priority;
+        if due {
+            let key = slot.due_key();
+            self.due.remove(&previous);
+            self.due.insert(key, id.index);
+        }
+    }
+
+    /// Puts the slot identified by `id` in the queue, or straight into the backlog of due slots if
+    /// its deadline has already been reached, so that it is delivered on the next poll without
+    /// waiting on a timer.
+    fn enqueue(&mut self, id: Id, deadline: Instant) {
+        if deadline > self.now() {
+            let delay = self.queue.insert_at(id, deadline);
+            self.slot_mut(id).delay = Some(delay);
+            return;
+        }
+
+        self.mark_due(id);
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Suspends the timeout associated with `key`, so that it can't expire until
+    /// [thawed](DelayMap::thaw), preserving the time it has remaining. Returns false if it didn't
+    /// exist or is already frozen.
+    pub fn freeze<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        match self.id(key) {
+            Some(id) => self.suspend(id),
+            _ => false,
+        }
+    }
+
+    /// Takes the slot identified by `id` out of the queue, unless it is already frozen.
+    fn suspend(&mut self, id: Id) -> bool {
+        let now = self.now();
+        let slot = self.slot_mut(id);
+        if slot.frozen.is_some() {
+            return false;
+        }
+
+        slot.frozen = Some(slot.deadline.saturating_duration_since(now));
+        self.set_wall(id, None);
+        let slot = self.slot_mut(id);
+        let (deadline, delay, due) = (slot.deadline, slot.delay.take(), slot.due_key());
+        if slot.due {
+            slot.due = false;
+            self.due.remove(&due);
+        } else if let Some(delay) = delay {
+            self.queue.remove(&delay);
+        }
+        self.order.remove(&(deadline, id.seq));
+        self.publish_deadline();
+
+        true
+    }
+
+    /// Resumes a [frozen](DelayMap::freeze) timeout, to expire after the time it had remaining when
+    /// it was frozen, from now. Returns false if it didn't exist or isn't frozen.
+    pub fn thaw<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        match self.id(key) {
+            Some(id) => self.unfreeze(id),
+            _ => false,
+        }
+    }
+
+    /// Puts the slot identified by `id` back in the queue, if it is frozen.
+    fn unfreeze(&mut self, id: Id) -> bool {
+        let now = self.now();
+        let remaining = match self.slot_mut(id).frozen.take() {
+            Some(remaining) => remaining,
+            _ => return false,
+        };
+
+        let deadline = saturating_add(now, remaining);
+        self.order.insert((deadline, id.seq), id.index);
+        self.publish_deadline();
+        self.slot_mut(id).deadline = deadline;
+        self.set_wall(id, self.wall_for(deadline));
+        self.enqueue(id, deadline);
+
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+
+        true
+    }
+
+    /// Check if the timeout associated with `key` is [frozen](DelayMap::freeze).
+    pub fn is_frozen<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.find(key).is_some_and(|slot| slot.frozen.is_some())
+    }
+
+    /// Returns the instant at which the timeout associated with `key` is scheduled to expire.
+    /// Frozen timeouts aren't scheduled, so have no deadline.
+    pub fn deadline<Q>(&self, key: &Q) -> Option<Instant>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.find(key)
+            .filter(|slot| slot.frozen.is_none())
+            .map(|slot| slot.deadline)
+    }
+
+    /// Returns how long until the timeout associated with `key` expires, zero if it is already due.
+    /// For frozen timeouts, this is the time that will be remaining once thawed.
+    pub fn time_remaining<Q>(&self, key: &Q) -> Option<Duration>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.find(key).map(|slot| match slot.frozen {
+            Some(remaining) => remaining,
+            _ if slot.due => Duration::ZERO,
+            _ => slot.deadline.saturating_duration_since(self.now()),
+        })
+    }
+
+    /// Returns the key that is to timeout next along with its deadline, without removing it.
+    pub fn peek_next(&self) -> Option<(&K, Instant)> {
+        self.iter().next().map(|(key, _, deadline)| (key, deadline))
+    }
+
+    /// Returns the deadline of the timeout that is to expire next.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.order
+            .first_key_value()
+            .map(|((deadline, _), _)| *deadline)
+    }
+
+    /// Watch the [deadline of the timeout that is to expire next](DelayMap::next_deadline), which
+    /// is updated on every insert, removal and expiry. Lets a supervisor schedule around the
+    /// delay-map without owning it.
+    #[cfg(feature = "channels")]
+    pub fn watch_next_deadline(&mut self) -> watch::Receiver<Option<Instant>> {
+        match &self.deadline_watch {
+            Some(watch) => watch.subscribe(),
+            _ => {
+                let (watch, receiver) = watch::channel(self.next_deadline());
+                self.deadline_watch = Some(watch);
+
+                receiver
+            }
+        }
+    }
+
+    /// Publishes the deadline of the timeout that is to expire next to watchers, if it changed.
+    #[cfg(feature = "channels")]
+    fn publish_deadline(&self) {
+        let watch = match &self.deadline_watch {
+            Some(watch) => watch,
+            _ => return,
+        };
+
+        let next = self.next_deadline();
+        watch.send_if_modified(|current| {
+            if *current == next {
+                return false;
+            }
+
+            *current = next;
+            true
+        });
+    }
+
+    #[cfg(not(feature = "channels"))]
+    fn publish_deadline(&self) {}
+
+    /// Watch whether the number of pending timeouts is at or above `watermark`, which flips every
+    /// time it is crossed in either direction, including on [changing the bound](DelayMap::set_max_entries)
+    /// that a percentage is of. Lets producers shed load before the delay-map is full.
+    ///
+    /// ### Examples
+    /// ```no_run
+    /// # use delay_handler::{DelayMap, Overflow, Watermark};
+    /// # async fn run() {
+    /// let mut map: DelayMap<u32, String> = DelayMap::default();
+    /// map.set_max_entries(Some((1000, Overflow::Reject)));
+    /// let mut high = map.watch_watermark(Watermark::Percent(80));
+    ///
+    /// // Elsewhere, a producer stops sending while there are 800 or more pending timeouts.
+    /// while high.changed().await.is_ok() {
+    ///     println!("shedding load: {}", *high.borrow());
+    /// }
+    /// # }
+    /// ```
+    #[cfg(feature = "channels")]
+    pub fn watch_watermark(&mut self, watermark: Watermark) -> watch::Receiver<bool> {
+        let (watch, receiver) = watch::channel(self.reached(watermark));
+        self.watermarks.push((watermark, watch));
+
+        receiver
+    }
+
+    /// Check if the number of pending timeouts is at or above `watermark`.
+    #[cfg(feature = "channels")]
+    fn reached(&self, watermark: Watermark) -> bool {
+        let level = match watermark {
+            Watermark::Count(count) => Some(count),
+            Watermark::Percent(percent) => self
+                .max_entries()
+                .map(|max| max.saturating_mul(percent as usize).div_ceil(100)),
+        };
+
+        level.is_some_and(|level| self.len >= level)
+    }
+
+    /// Publishes whether each watermark has been reached to its watchers, if it changed.
+    #[cfg(feature = "channels")]
+    fn publish_depth(&mut self) {
+        if self.watermarks.is_empty() {
+            return;
+        }
+
+        let mut watermarks = std::mem::take(&mut self.watermarks);
+        watermarks.retain(|(watermark, watch)| {
+            let reached = self.reached(*watermark);
+            watch.send_if_modified(|current| std::mem::replace(current, reached) != reached);
+
+            !watch.is_closed()
+        });
+        self.watermarks = watermarks;
+    }
+
+    #[cfg(not(feature = "channels"))]
+    fn publish_depth(&mut self) {}
+
+    /// Returns a future that resolves once the timeout for `key` has expired or been removed, telling
+    /// which, or `None` if no timeout exists for it. Recurring timeouts resolve the future on their
+    /// next expiry. Lets a task that only cares about a single key wait on it, while another owns
+    /// and drives the delay-map.
+    ///
+    /// ### Examples
+    /// ```no_run
+    /// # use delay_handler::{DelayMap, Fate};
+    /// # use std::time::Duration;
+    /// # async fn run() {
+    /// let mut map = DelayMap::default();
+    /// map.insert("lease", 42, Duration::from_secs(10));
+    /// let lease = map.wait_for("lease").unwrap();
+    /// tokio::spawn(async move {
+    ///     if lease.await == Fate::Expired {
+    ///         println!("lease expired");
+    ///     }
+    /// });
+    ///
+    /// while let Some((key, value)) = map.next().await {
+    ///     println!("{key}: {value}");
+    /// }
+    /// # }
+    /// ```
+    #[cfg(feature = "channels")]
+    pub fn wait_for<Q>(&mut self, key: &Q) -> Option<WaitFor>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let id = self.id(key)?;
+        let (sender, receiver) = oneshot::channel();
+        let waiting = self.waiting.entry(id.seq).or_default();
+        waiting.retain(|sender| !sender.is_closed());
+        waiting.push(sender);
+
+        Some(WaitFor::new(receiver))
+    }
+
+    /// Remove a key from map if it has timedout and return it along with the associated payload.
+    pub async fn next(&mut self) -> Option<(K, V)> {
+        poll_fn(|cx| self.poll_expired(cx)).await
+    }
+
+    /// Remove a key from map once it has timedout and return it along with the associated payload,
+    /// waiting for new inserts while the delay-map is empty rather than returning `None`, so that
+    /// consumer loops can outlive any one batch of timeouts.
+    pub async fn next_or_pending(&mut self) -> (K, V) {
+        poll_fn(|cx| match self.poll_expired(cx) {
+            Poll::Ready(Some(expired)) => Poll::Ready(expired),
+            // Woken by the queue on the next insert, or directly if it is due right away
+            Poll::Ready(None) => {
+                self.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            Poll::Pending => Poll::Pending,
+        })
+        .await
+    }
+
+    /// Wait for the next key to timeout, then remove all others that have already timedout, up to
+    /// `max` in total, returning them along with their associated payloads in order of expiry.
+    /// Returns an empty batch if the delay-map is empty.
+    pub async fn next_batch(&mut self, max: usize) -> Vec<(K, V)> {
+        let mut batch = vec![];
+        if max == 0 {
+            return batch;
+        }
+
+        if let Some(expired) = self.next().await {
+            batch.push(expired);
+        }
+
+        while batch.len() < max {
+            match self.try_next() {
+                Some(expired) => batch.push(expired),
+                _ => break,
+            }
+        }
+
+        batch
+    }
+
+    /// Polls for a key that has timedout, registering the current task for wakeup if none has yet.
+    /// Returns `Poll::Ready(None)` if the delay-map is empty.
+    pub fn poll_expired(&mut self, cx: &mut Context<'_>) -> Poll<Option<(K, V)>> {
+        self.poll_next_expired(cx)
+            .map(|expired| expired.map(Expired::into_inner))
+    }
+
+    /// Remove a key from map if it has timedout and return it along with the associated payload,
+    /// wrapped with the deadline it was scheduled for and the instant it was delivered at.
+    pub async fn next_expired(&mut self) -> Option<Expired<(K, V)>> {
+        poll_fn(|cx| self.poll_next_expired(cx)).await
+    }
+
+    /// Polls for a key that has timedout, like [`poll_expired()`](DelayMap::poll_expired), but
+    /// wrapped with the deadline it was scheduled for and the instant it was delivered at.
+    pub fn poll_next_expired(&mut self, cx: &mut Context<'_>) -> Poll<Option<Expired<(K, V)>>> {
+        if self.paused.is_some() && !self.is_empty() {
+            self.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        if let Some(expired) = self.held.pop_front() {
+            return Poll::Ready(Some(expired));
+        }
+
+        loop {
+            let id = match ready!(self.poll_due(cx)) {
+                Some(id) => id,
+                None => return Poll::Ready(None),
+            };
+
+            if let Some(expired) = self.fire(id) {
+                return Poll::Ready(Some(expired));
+            }
+        }
+    }
+
+    /// Moves every timeout that the queue has expired into the backlog of due slots, from which
+    /// the one with the highest priority is picked for delivery.
+    fn poll_due(&mut self, cx: &mut Context<'_>) -> Poll<Option<Id>> {
+        self.purge_cancelled(Some(cx));
+        self.poll_wall_check(cx);
+        while let Poll::Ready(Some(expired)) = self.queue.poll_expired(cx) {
+            // Deadlines too far out for the queue are re-armed, each time the furthest it schedules is reached
+            let deadline = self.slot(expired).deadline;
+            if self.queue.is_early(deadline) {
+                self.slot_mut(expired).delay = Some(self.queue.insert_at(expired, deadline));
+                continue;
+            }
+            self.mark_due(expired);
+        }
+        // The queue can yield only some of the timeouts that are due at once, such as when the task
+        // runs out of budget, so that those ordered ahead of the next to be delivered are pulled in
+        if let Some(&(_, deadline, seq)) = self.due.keys().next() {
+            self.pull_due((deadline, seq));
+        }
+
+        match self.due.pop_first() {
+            Some(((_, _, seq), index)) => {
+                let id = Id { index, seq };
+                self.slot_mut(id).due = false;
+
+                Poll::Ready(Some(id))
+            }
+            // Woken by the queue, or on a timeout being thawed or scheduled to be due right away
+            None if self.len > 0 => {
+                self.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            None => Poll::Ready(None),
+        }
+    }
+
+    /// Takes every timeout ordered ahead of `until` out of the queue, into the backlog of due slots.
+    fn pull_due(&mut self, until: (Instant, u64)) {
+        let ahead: Vec<Id> = self
+            .order
+            .range(..until)
+            .map(|(&(_, seq), &index)| Id { index, seq })
+            .filter(|&id| !self.slot(id).due)
+            .collect();
+        for id in ahead {
+            if let Some(delay) = self.slot(id).delay {
+                self.queue.remove(&delay);
+            }
+            self.mark_due(id);
+        }
+    }
+
+    /// Adds the slot identified by `id`, which is no longer in the queue, to the backlog of due slots.
+    /// Timeouts with a wall-clock deadline that hasn't been reached yet, as the wall clock was set
+    /// back, are re-anchored instead.
+    fn mark_due(&mut self, id: Id) {
+        let wall = self.push_due(id);
+        let early = wall
+            .and_then(|wall| wall.duration_since(self.system_now()).ok())
+            .is_some_and(|remaining| remaining > WALL_DRIFT);
+        if early {
+            self.reanchor(id);
+        }
+    }
+
+    /// Adds the slot identified by `id`, which is no longer in the queue, to the backlog of due slots
+    /// as is, returning its wall-clock deadline, if any.
+    fn push_due(&mut self, id: Id) -> Option<SystemTime> {
+        let slot = self.slot_mut(id);
+        slot.due = true;
+        slot.delay = None;
+        let (key, wall) = (slot.due_key(), slot.wall);
+        self.due.insert(key, id.index);
+
+        wall
+    }
+
+    /// Compares the wall clock against the monotonic clock every second, while there are timeouts
+    /// with a wall-clock deadline or gaps in time are watched for.
+    fn poll_wall_check(&mut self, cx: &mut Context<'_>) {
+        if self.walls == 0 && self.clock_gap.is_none() {
+            self.wall_check = None;
+            return;
+        }
+
+        let clock = self.queue.clock();
+        let check = self.wall_check.get_or_insert_with(|| {
+            let monotonic = clock.now();
+            WallCheck {
+                monotonic,
+                wall: clock.system_now(),
+                sleep: clock.sleep_until(monotonic + WALL_CHECK),
+            }
+        });
+        if check.sleep.as_mut().poll(cx).is_pending() {
+            return;
+        }
+
+        self.compare_clocks();
+        if let Some(check) = self.wall_check.as_mut() {
+            // Registers for wakeup at the next check, it can't be due already
+            let _ = check.sleep.as_mut().poll(cx);
+        }
+    }
+
+    /// Compares the wall clock against the monotonic clock, since they were last compared,
+    /// re-anchoring wall-clock deadlines if they have drifted apart and handling a gap in time if
+    /// the wall clock got ahead by more than the threshold. Returns the gap, if one was detected.
+    fn compare_clocks(&mut self) -> Option<Duration> {
+        let clock = self.queue.clock();
+        let check = self.wall_check.as_mut()?;
+        let (monotonic, wall) = (clock.now(), clock.system_now());
+        let expected = check.wall + monotonic.saturating_duration_since(check.monotonic);
+        let (drift, ahead) = match wall.duration_since(expected) {
+            Ok(ahead) => (ahead, ahead),
+            Err(behind) => (behind.duration(), Duration::ZERO),
+        };
+        check.monotonic = monotonic;
+        check.wall = wall;
+        check.sleep = clock.sleep_until(monotonic + WALL_CHECK);
+
+        let gap = self
+            .clock_gap
+            .filter(|&(threshold, _)| ahead > threshold)
+            .map(|(_, policy)| (ahead, policy));
+        if let Some((gap, policy)) = gap {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(?gap, ?policy, "clock gap");
+            let now = monotonic;
+            match policy {
+                // Timeouts with a wall-clock deadline are moved up as they are re-anchored
+                ClockGap::Fire => self.shift_deadlines(|slot| match slot.wall {
+                    Some(_) => None,
+                    _ => Some(slot.deadline.checked_sub(gap).unwrap_or(now)),
+                }),
+                ClockGap::Postpone => {
+                    for slot in self.slots.iter_mut().flatten() {
+                        if let Some(wall) = slot.wall.as_mut() {
+                            *wall += gap;
+                        }
+                    }
+                }
+            }
+        }
+
+        if drift > WALL_DRIFT || gap.is_some() {
+            let walls: Vec<Id> = self
+                .order
+                .values()
+                .filter_map(|&index| {
+                    let slot = self.slots[index].as_ref()?;
+                    slot.wall.map(|_| Id {
+                        index,
+                        seq: slot.seq,
+                    })
+                })
+                .collect();
+            for id in walls {
+                self.reanchor(id);
+            }
+        }
+
+        gap.map(|(gap, _)| gap)
+    }
+
+    /// Reschedules the timeout identified by `id` to its wall-clock deadline, as of the current
+    /// wall-clock time.
+    fn reanchor(&mut self, id: Id) {
+        let wall = self.slot(id).wall.unwrap();
+        let remaining = wall.duration_since(self.system_now()).unwrap_or_default();
+        self.reschedule(id, saturating_add(self.now(), remaining));
+        self.set_wall(id, Some(wall));
+    }
+
+    /// Wall-clock time that `deadline` falls on, for timeouts scheduled while periods are measured
+    /// against the wall clock.
+    fn wall_for(&self, deadline: Instant) -> Option<SystemTime> {
+        let remaining = deadline.saturating_duration_since(self.now());
+
+        (self.clock_mode == ClockMode::Wall)
+            .then(|| self.system_now().checked_add(remaining))
+            .flatten()
+    }
+
+    /// Sets the wall-clock deadline of the slot identified by `id`, keeping count of the slots
+    /// that have one.
+    fn set_wall(&mut self, id: Id, wall: Option<SystemTime>) {
+        let previous = std::mem::replace(&mut self.slot_mut(id).wall, wall);
+        match (previous, wall) {
+            (None, Some(_)) => self.walls += 1,
+            (Some(_), None) => self.walls -= 1,
+            _ => {}
+        }
+    }
+
+    /// Takes a slot that has been unlinked out of the queue or the backlog of due slots,
+    /// whichever it is in.
+    fn dequeue(&mut self, slot: &Slot<K, V>) {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(id = slot.seq, key = ?Key(&slot.key, self.fmt_key), "removed timeout");
+        emit(&mut self.emitters, self.queue.clock(), |at| {
+            Event::Removed { key: &slot.key, at }
+        });
+        if slot.due {
+            self.due.remove(&slot.due_key());
+        } else if let Some(delay) = &slot.delay {
+            self.queue.remove(delay);
+        }
+    }
+
+    /// Converts the delay-map into an owned stream of timedout keys and payloads, that ends once
+    /// it is drained.
+    pub fn into_stream(self) -> IntoStream<Self> {
+        IntoStream::new(self)
+    }
+
+    /// Remove a key from map if it has already timedout and return it along with the associated
+    /// payload, without waiting. Returns `None` if no timeout is due yet.
+    pub fn try_next(&mut self) -> Option<(K, V)> {
+        self.try_next_expired().map(Expired::into_inner)
+    }
+
+    /// Remove a key from map if it has already timedout, like [`try_next()`](DelayMap::try_next),
+    /// but wrapped with the deadline it was scheduled for and the instant it was delivered at.
+    pub(crate) fn try_next_expired(&mut self) -> Option<Expired<(K, V)>> {
+        if self.paused.is_some() {
+            return None;
+        }
+
+        if let Some(expired) = self.held.pop_front() {
+            return Some(expired);
+        }
+
+        self.purge_cancelled(None);
+        let now = self.now();
+        self.pull_due((now, u64::MAX));
+
+        while let Some(((_, _, seq), index)) = self.due.pop_first() {
+            let id = Id { index, seq };
+            self.slot_mut(id).due = false;
+
+            if let Some(expired) = self.fire(id) {
+                return Some(expired);
+            }
+        }
+
+        None
+    }
+
+    /// Remove all keys that have already timedout, along with their associated payloads,
+    /// in order of expiry.
+    pub fn drain_expired(&mut self) -> Vec<(K, V)> {
+        std::iter::from_fn(|| self.try_next()).collect()
+    }
+
+    /// Iterate over all pending keys with their associated payloads and deadlines, in order of expiry.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V, Instant)> {
+        self.order.iter().map(|(&(deadline, seq), &index)| {
+            let slot = self.slot(Id { index, seq });
+
+            (&slot.key, &slot.value, deadline)
+        })
+    }
+
+    /// Every outstanding key with its associated payload and time remaining: those held back after
+    /// expiring, then the pending ones in order of expiry, followed by those that are frozen.
+    pub(crate) fn pending(&self) -> impl Iterator<Item = (&K, &V, Duration)> {
+        let now = self.now();
+        let held = self.held.iter().map(|expired| {
+            let (key, value) = expired.get_ref();
+
+            (key, value, Duration::ZERO)
+        });
+        let frozen = self.slots.iter().flatten().filter_map(|slot| {
+            slot.frozen
+                .map(|remaining| (&slot.key, &slot.value, remaining))
+        });
+
+        held.chain(self.iter().map(move |(key, value, deadline)| {
+            (key, value, deadline.saturating_duration_since(now))
+        }))
+        .chain(frozen)
+    }
+
+    /// Retain only the timeouts for which `f` returns true, prematurely removing all others.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        for index in 0..self.slots.len() {
+            let slot = match &mut self.slots[index] {
+                Some(slot) => slot,
+                _ => continue,
+            };
+            if f(&slot.key, &mut slot.value) {
+                continue;
+            }
+
+            let id = Id {
+                index,
+                seq: slot.seq,
+            };
+            let slot = self.unlink(id);
+            self.dequeue(&slot);
+        }
+        self.maybe_shrink();
+    }
+
+    /// Remove all pending timeouts from the delay-map, returning the keys along with their associated
+    /// payloads and time that was remaining, in order of expiry.
+    pub fn drain(&mut self) -> Vec<(K, V, Duration)> {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(len = self.len, "drained timeouts");
+        let now = self.now();
+        self.queue.clear();
+        self.due.clear();
+
+        let mut drained = Vec::with_capacity(self.len + self.held.len());
+        for expired in self.held.drain(..) {
+            let (key, value) = expired.into_inner();
+            drained.push((key, value, Duration::ZERO));
+        }
+
+        while let Some(((_, seq), index)) = self.order.pop_first() {
+            let slot = self.unlink(Id { index, seq });
+            let remaining = slot.deadline.saturating_duration_since(now);
+            emit(&mut self.emitters, self.queue.clock(), |at| {
+                Event::Removed { key: &slot.key, at }
+            });
+            drained.push((slot.key, slot.value, remaining));
+        }
+
+        // Only frozen timeouts remain
+        self.walls = 0;
+        for slot in self.slots.drain(..).flatten() {
+            emit(&mut self.emitters, self.queue.clock(), |at| {
+                Event::Removed { key: &slot.key, at }
+            });
+            drained.push((slot.key, slot.value, slot.frozen.unwrap_or_default()));
+        }
+        self.vacant.clear();
+        self.index.clear();
+        #[cfg(feature = "channels")]
+        self.waiting.clear();
+        self.groups.clear();
+        self.tags.clear();
+        self.len = 0;
+        self.publish_depth();
+        self.report();
+
+        drained
+    }
+
+    /// Stops the delay-map from accepting new timeouts, inserts are rejected from here on, as if it
+    /// were full, and recurring timeouts expire one last time instead of being re-armed. Pending
+    /// timeouts are left as they are, to be drained or to expire as usual, after which
+    /// [`next()`](DelayMap::next) returns `None` for good.
+    pub fn close(&mut self) {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(len = self.len, "closed delay-map");
+        self.closed = true;
+    }
+
+    /// Check if the delay-map has been [closed](DelayMap::close).
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// [Closes](DelayMap::close) the delay-map and hands out the remaining timeouts with their
+    /// payloads, either right away, in the same order as [`drain()`](DelayMap::drain), or in order
+    /// of expiry once each has expired, as per `shutdown`. To handle each timeout as soon as it
+    /// expires instead, close the delay-map and keep calling [`next()`](DelayMap::next) until it
+    /// returns `None`.
+    pub async fn shutdown(&mut self, shutdown: Shutdown) -> Vec<(K, V)> {
+        self.close();
+        if shutdown == Shutdown::Immediate {
+            return self
+                .drain()
+                .into_iter()
+                .map(|(key, value, _)| (key, value))
+                .collect();
+        }
+
+        self.resume();
+        let frozen: Vec<Id> = self
+            .slots
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| {
+                let slot = slot.as_ref().filter(|slot| slot.frozen.is_some())?;
+                Some(Id {
+                    index,
+                    seq: slot.seq,
+                })
+            })
+            .collect();
+        for id in frozen {
+            self.unfreeze(id);
+        }
+
+        let mut expired = Vec::with_capacity(self.len + self.held.len());
+        while let Some(timeout) = self.next().await {
+            expired.push(timeout);
+        }
+
+        expired
+    }
+
+    /// Copies out all pending timeouts, along with their associated payloads and time remaining, in
+    /// the same order as [`drain()`](DelayMap::drain), while leaving the delay-map untouched.
+    pub fn snapshot(&self) -> Vec<(K, V, Duration)>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.pending()
+            .map(|(key, value, remaining)| (key.clone(), value.clone(), remaining))
+            .collect()
+    }
+
+    /// Moves all pending timeouts out of `other` into this delay-map, each keeping the time it had
+    /// remaining, as well as its priority, group, tags, recurrence, action and whether it is frozen.
+    /// Timeouts that already expired in `other`, yet to be returned by its [`next()`](DelayMap::next),
+    /// are returned by this one before all others. Useful to consolidate per-shard delay-maps.
+    ///
+    /// A key that already has a timeout here follows the [duplicates](DelayHandlerBuilder::duplicates)
+    /// policy this delay-map was built with: by default the existing timeout is kept, with
+    /// [`Duplicates::Reset`] it takes the payload and time remaining of the one from `other`.
+    /// Timeouts that are kept out, as duplicates or for lack of space, are returned along with the
+    /// time they had remaining. Cancellation tokens, subscribers and observers of `other` aren't
+    /// carried over, while futures from its [`wait_for()`](DelayMap::wait_for) are.
+    pub fn merge(&mut self, mut other: Self) -> Vec<(K, V, Duration)> {
+        let (now, then) = (self.now(), other.now());
+        self.held.append(&mut other.held);
+
+        let pending = other
+            .order
+            .iter()
+            .map(|(&(_, seq), &index)| Id { index, seq });
+        let frozen = other.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let slot = slot.as_ref().filter(|slot| slot.frozen.is_some())?;
+            Some(Id {
+                index,
+                seq: slot.seq,
+            })
+        });
+        let ids: Vec<Id> = pending.chain(frozen).collect();
+
+        let mut rejected = vec![];
+        for id in ids {
+            #[cfg(feature = "channels")]
+            let waiting = other.waiting.remove(&id.seq);
+            let slot = other.unlink(id);
+            other.dequeue(&slot);
+            let remaining = slot
+                .frozen
+                .unwrap_or_else(|| slot.deadline.saturating_duration_since(then));
+
+            match self.adopt(slot, saturating_add(now, remaining)) {
+                #[cfg(feature = "channels")]
+                Ok(id) => self
+                    .waiting
+                    .entry(id.seq)
+                    .or_default()
+                    .extend(waiting.into_iter().flatten()),
+                #[cfg(not(feature = "channels"))]
+                Ok(_) => {}
+                Err((key, value)) => rejected.push((key, value, remaining)),
+            }
+        }
+
+        rejected
+    }
+
+    /// Replaces all pending timeouts with those from a [`snapshot()`](DelayMap::snapshot), each to
+    /// timeout after the time that was remaining from now, skipping keys that are repeated.
+    pub fn restore<I>(&mut self, timeouts: I)
+    where
+        I: IntoIterator<Item = (K, V, Duration)>,
+    {
+        self.clear();
+        self.extend(timeouts);
+    }
+
+    /// Remove all pending timeouts from the delay-map, dropping their payloads.
+    pub fn clear(&mut self) {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(len = self.len, "cleared timeouts");
+        for slot in self.slots.iter().flatten() {
+            emit(&mut self.emitters, self.queue.clock(), |at| {
+                Event::Removed { key: &slot.key, at }
+            });
+        }
+        self.queue.clear();
+        self.due.clear();
+        self.slots.clear();
+        self.walls = 0;
+        self.vacant.clear();
+        self.index.clear();
+        self.order.clear();
+        self.publish_deadline();
+        self.held.clear();
+        #[cfg(feature = "channels")]
+        self.waiting.clear();
+        self.groups.clear();
+        self.tags.clear();
+        self.len = 0;
+        self.publish_depth();
+        self.report();
+    }
+
+    /// Shrinks the capacity of the delay-map as much as possible, returning memory to the allocator
+    /// after the number of pending timeouts has come down from its peak. Space for timeouts is only
+    /// returned beyond the last one still pending, as the rest are referred to by their position.
+    pub fn shrink_to_fit(&mut self) {
+        while let Some(None) = self.slots.last() {
+            self.slots.pop();
+        }
+        let len = self.slots.len();
+        self.vacant.retain(|&index| index < len);
+        self.slots.shrink_to_fit();
+        self.vacant.shrink_to_fit();
+        let slots = &self.slots;
+        self.index.shrink_to_fit(|&index| slot_hash(slots, index));
+        self.queue.compact();
+    }
+
+    /// Automatically [shrink](DelayMap::shrink_to_fit) the delay-map on removal or expiry, whenever
+    /// its capacity grows to more than `factor` times the number of pending timeouts. Passing `None`
+    /// disables automatic shrinking, which is the default.
+    pub fn set_auto_shrink(&mut self, factor: Option<usize>) {
+        self.auto_shrink = factor;
+    }
+
+    fn maybe_shrink(&mut self) {
+        // Shrinking is O(n), make sure it isn't attempted on every removal
+        let factor = match self.auto_shrink {
+            Some(factor) => factor.max(2),
+            _ => return,
+        };
+
+        let capacity = self.index.capacity();
+        if capacity > MIN_SHRINK_CAPACITY && capacity > self.len.saturating_mul(factor) {
+            self.shrink_to_fit();
+        }
+    }
+
+    /// Check if queue is empty. Could be used as precondition in an async select operation.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0 && self.held.is_empty()
+    }
+
+    /// Check if a timeout is currently scheduled for `key`.
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.find(key).is_some()
+    }
+
+    /// Records keys in the [`tracing`](::tracing) events emitted on each insert, removal, reset and
+    /// expiry, formatted with their `Debug` implementation. Otherwise only the sequence number
+    /// identifying each timeout is recorded, as keys aren't required to implement `Debug`.
+    #[cfg(feature = "tracing")]
+    pub fn trace_keys(&mut self)
+    where
+        K: Debug,
+    {
+        self.fmt_key = Some(<K as Debug>::fmt);
+    }
+
+    /// Returns counters of the delay-map's activity since it was created, along with its current
+    /// and peak depth.
+    pub fn stats(&self) -> Stats {
+        Stats {
+            // Every timeout that was inserted is either still pending, retired or removed
+            removed: self.stats.inserted - self.retired - self.len as u64,
+            depth: self.len,
+            ..self.stats
+        }
+    }
+
+    /// Number of timeouts currently pending in the delay-map.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    fn find<Q>(&self, key: &Q) -> Option<&Slot<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.find_hashed(self.hasher.hash_one(key), key)
+    }
+
+    fn find_hashed<Q>(&self, hash: u64, key: &Q) -> Option<&Slot<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        let index = self.find_index(hash, key)?;
+
+        self.slots[index].as_ref()
+    }
+
+    fn find_mut<Q>(&mut self, key: &Q) -> Option<&mut Slot<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let index = self.find_index(self.hasher.hash_one(key), key)?;
+
+        self.slots[index].as_mut()
+    }
+
+    /// Index of the slot holding `key`, which hashes to `hash`.
+    fn find_index<Q>(&self, hash: u64, key: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        self.index
+            .find(hash, |&index| {
+                self.slots[index]
+                    .as_ref()
+                    .is_some_and(|slot| slot.key.borrow() == key)
+            })
+            .copied()
+    }
+
+    fn id<Q>(&self, key: &Q) -> Option<Id>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let index = self.find_index(self.hasher.hash_one(key), key)?;
+
+        Some(Id {
+            index,
+            seq: self.slots[index].as_ref()?.seq,
+        })
+    }
+
+    /// Slot identified by `id`, which must be present in the delay-map.
+    fn slot(&self, id: Id) -> &Slot<K, V> {
+        self.slots[id.index].as_ref().unwrap()
+    }
+
+    fn slot_mut(&mut self, id: Id) -> &mut Slot<K, V> {
+        self.slots[id.index].as_mut().unwrap()
+    }
+
+    /// Hands out the slot identified by `id`, which has timedout and is no longer in the queue.
+    /// Periodic timeouts are re-armed, all others are removed from the delay-map.
+    /// Timeouts inserted along with an action have it run instead, returning `None`.
+    fn fire(&mut self, id: Id) -> Option<Expired<(K, V)>> {
+        let slot = self.slots[id.index].as_ref().unwrap();
+        emit(&mut self.emitters, self.queue.clock(), |at| {
+            Event::Expired {
+                key: &slot.key,
+                deadline: slot.deadline,
+                at,
+            }
+        });
+        let action = self.slot_mut(id).action.take();
+        #[cfg(feature = "channels")]
+        if let Some(waiting) = self.waiting.remove(&id.seq) {
+            for sender in waiting {
+                let _ = sender.send(());
+            }
+        }
+        let expired = self.release(id);
+        let (key, value) = expired.get_ref();
+        self.observers.retain_mut(|observe| observe(key, value));
+
+        match action {
+            Some(action) => {
+                let (key, value) = expired.into_inner();
+                action(key, value);
+
+                None
+            }
+            _ => Some(expired),
+        }
+    }
+
+    fn release(&mut self, id: Id) -> Expired<(K, V)> {
+        let now = self.queue.clock().now();
+        #[cfg(feature = "cron")]
+        let wall = self.system_now();
+        self.stats.expired += 1;
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &self.metrics {
+            metrics.lag(now.saturating_duration_since(self.slot(id).deadline));
+        }
+        #[cfg(feature = "tracing")]
+        {
+            let slot = self.slot(id);
+            tracing::debug!(
+                id = id.seq,
+                key = ?Key(&slot.key, self.fmt_key),
+                attempt = slot.attempt + 1,
+                lateness = ?now.saturating_duration_since(slot.deadline),
+                "timeout expired"
+            );
+        }
+        let closed = self.closed;
+        let slot = self.slot_mut(id);
+        slot.attempt += 1;
+        let (deadline, attempt) = (slot.deadline, slot.attempt);
+        let recurring = slot.recurring.as_mut().filter(|_| !closed);
+        let rearmed = recurring.and_then(|recurring| {
+            let next = match &mut recurring.rearm {
+                Rearm::Periodic(interval, Periodic::FixedRate) => {
+                    saturating_add(deadline, *interval)
+                }
+                Rearm::Periodic(interval, Periodic::FixedDelay) => saturating_add(now, *interval),
+                Rearm::Backoff(backoff) if backoff.should_retry(attempt) => {
+                    saturating_add(now, backoff.delay(attempt + 1))
+                }
+                Rearm::Backoff(_) => return None,
+                #[cfg(feature = "cron")]
+                Rearm::Cron(cron) => saturating_add(now, cron.advance(wall.into())?),
+            };
+
+            Some((next, (recurring.duplicate)(&slot.key, &slot.value)))
+        });
+
+        let (next, expired) = match rearmed {
+            Some(rearmed) => rearmed,
+            _ => {
+                self.retired += 1;
+                let slot = self.unlink(id);
+                self.maybe_shrink();
+
+                return Expired::new((slot.key, slot.value), deadline, now, attempt);
+            }
+        };
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(id = id.seq, delay = ?next.saturating_duration_since(now), "re-armed timeout");
+        self.order.remove(&(deadline, id.seq));
+        self.order.insert((next, id.seq), id.index);
+        self.publish_deadline();
+        self.slot_mut(id).deadline = next;
+        self.set_wall(id, self.wall_for(next));
+        self.enqueue(id, next);
+        self.report();
+
+        Expired::new(expired, deadline, now, attempt)
+    }
+
+    /// Removes the slot identified by `id` from the slab, index and ordering, but not the queue.
+    /// Frozen slots aren't in the ordering to begin with.
+    fn unlink(&mut self, id: Id) -> Slot<K, V> {
+        let slot = self.slots[id.index].take().unwrap();
+        self.vacant.push(id.index);
+        self.index
+            .find_entry(slot.hash, |&index| index == id.index)
+            .unwrap()
+            .remove();
+
+        self.order.remove(&(slot.deadline, slot.seq));
+        self.publish_deadline();
+        #[cfg(feature = "channels")]
+        self.waiting.remove(&slot.seq);
+        if slot.wall.is_some() {
+            self.walls -= 1;
+        }
+        if let Some(group) = &slot.group {
+            unindex(&mut self.groups, group, slot.seq);
+        }
+        for tag in &slot.tags {
+            unindex(&mut self.tags, tag, slot.seq);
+        }
+        self.len -= 1;
+        self.publish_depth();
+        self.report();
+
+        slot
+    }
+
+    /// Reports activity since the last report to the configured metrics, if any.
+    fn report(&mut self) {
+        #[cfg(feature = "metrics")]
+        {
+            let stats = self.stats();
+            if let Some(metrics) = &mut self.metrics {
+                metrics.report(stats);
+            }
+        }
+    }
+}
+
+/// Hands the event made at the current instant to every subscriber of lifecycle events, if any,
+/// dropping those that have gone away.
+fn emit<'a, K: 'a>(
+    emitters: &mut Vec<Emitter<K>>,
+    clock: &dyn Clock,
+    event: impl FnOnce(Instant) -> Event<&'a K>,
+) {
+    if emitters.is_empty() {
+        return;
+    }
+
+    let event = event(clock.now());
+    emitters.retain_mut(|emit| emit(&event));
+}
+
+/// Hash of the key held by the slot at `index`, which must be occupied.
+fn slot_hash<K, V>(slots: &[Option<Slot<K, V>>], index: usize) -> u64 {
+    slots[index].as_ref().unwrap().hash
+}
+
+/// Removes the slot identified by `seq` from the members of a group or tag in `index`, dropping
+/// the label once it has no members.
+fn unindex(index: &mut HashMap<String, Vec<Id>>, label: &str, seq: u64) {
+    let members = match index.get_mut(label) {
+        Some(members) => members,
+        _ => return,
+    };
+
+    members.retain(|id| id.seq != seq);
+    if members.is_empty() {
+        index.remove(label);
+    }
+}
+
+impl DelayMap<(), ()> {
+    /// Creates a builder, to configure the delay-map before constructing it, same as
+    /// [`DelayHandlerBuilder::new()`].
+    pub fn builder() -> DelayHandlerBuilder {
+        DelayHandlerBuilder::new()
+    }
+}
+
+impl<K, V, S> Clone for DelayMap<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher + Clone,
+{
+    /// Forks the delay-map into an independent one, built with the same configuration, holding a
+    /// copy of every pending timeout, due at the same deadline, while frozen timeouts keep the time
+    /// they have remaining. Copies keep their priority, group, tags and recurrence, but not actions
+    /// run on expiry or cancellation tokens, nor are subscribers and observers carried over. The
+    /// copies aren't counted as inserts, the clone starts out with the same [stats](DelayMap::stats).
+    fn clone(&self) -> Self {
+        let mut config = self.config.clone();
+        config.capacity = self.slots.len();
+        let mut map = Self::from_builder(config, self.hasher.clone());
+        map.slots = self
+            .slots
+            .iter()
+            .map(|slot| slot.as_ref().map(Slot::copy))
+            .collect();
+        map.vacant = self.vacant.clone();
+        map.index = self.index.clone();
+        map.order = self.order.clone();
+        map.due = self.due.clone();
+        map.len = self.len;
+        map.seq = self.seq;
+        map.auto_shrink = self.auto_shrink;
+        map.default_delay = self.default_delay;
+        map.max_entries = self.max_entries;
+        map.held = self.held.clone();
+        map.groups = self.groups.clone();
+        map.tags = self.tags.clone();
+        map.paused = self.paused;
+        map.closed = self.closed;
+        map.stats = self.stats;
+        map.retired = self.retired;
+        map.walls = self.walls;
+        #[cfg(feature = "tracing")]
+        {
+            map.fmt_key = self.fmt_key;
+        }
+
+        // The queue can't be copied, so the copies that are yet to be due are scheduled on a new one
+        for (&(deadline, seq), &index) in &self.order {
+            if !self.slot(Id { index, seq }).due {
+                map.enqueue(Id { index, seq }, deadline);
+            }
+        }
+
+        map
+    }
+}
+
+impl<K, V> Default for DelayMap<K, V>
+where
+    K: Eq + Hash,
+{
+    fn default() -> Self {
+        Self::with_capacity(0)
+    }
+}
+
+impl<K, V, S> Debug for DelayMap<K, V, S>
+where
+    K: Eq + Hash + Debug,
+    V: Debug,
+    S: BuildHasher,
+{
+    /// Lists the number of pending timeouts and the first few to timeout, with their payloads
+    /// and time remaining.
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let pending = self
+            .pending()
+            .map(|(key, value, remaining)| (key, (value, remaining)));
+
+        f.debug_struct("DelayMap")
+            .field("len", &self.len)
+            .field("pending", &Listed::new(pending))
+            .finish()
+    }
+}
+
+/// The first few entries of an iterator, formatted as a map that is elided beyond them.
+pub(crate) struct Listed<K, V> {
+    entries: Vec<(K, V)>,
+    elided: bool,
+}
+
+impl<K, V> Listed<K, V> {
+    pub(crate) fn new<I: Iterator<Item = (K, V)>>(mut iter: I) -> Self {
+        let entries = iter.by_ref().take(DEBUG_ENTRIES).collect();
+
+        Self {
+            entries,
+            elided: iter.next().is_some(),
+        }
+    }
+}
+
+impl<K: Debug, V: Debug> Debug for Listed<K, V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut map = f.debug_map();
+        map.entries(self.entries.iter().map(|(key, value)| (key, value)));
+
+        match self.elided {
+            true => map.finish_non_exhaustive(),
+            false => map.finish(),
+        }
+    }
+}
+
+impl<K, V, S> IntoIterator for DelayMap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    type Item = (K, V, Duration);
+    type IntoIter = std::vec::IntoIter<(K, V, Duration)>;
+
+    /// Tears down the delay-map, yielding all pending keys with their associated payloads and the
+    /// time that was remaining, in the same order as [`drain()`](DelayMap::drain).
+    fn into_iter(mut self) -> Self::IntoIter {
+        self.drain().into_iter()
+    }
+}
+
+impl<K, V, S> Extend<(K, V, Duration)> for DelayMap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    /// Inserts all timeouts from the iterator, skipping keys that already exist.
+    fn extend<I: IntoIterator<Item = (K, V, Duration)>>(&mut self, iter: I) {
+        for (key, value, period) in iter {
+            self.insert(key, value, period);
+        }
+    }
+}
+
+impl<K, V, S> FromIterator<(K, V, Duration)> for DelayMap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V, Duration)>>(iter: I) -> Self {
+        let mut map = Self::with_hasher(S::default());
+        map.extend(iter);
+
+        map
+    }
+}
+
+// We never put `K` or `V` in a `Pin`...
+impl<K, V, S> Unpin for DelayMap<K, V, S> {}
+
+impl<K, V, S> Stream for DelayMap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    type Item = (K, V);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_expired(cx)
+    }
+}
+
+impl<K, V, S> FusedStream for DelayMap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    /// Terminated for good, once closed and all timeouts have expired or been removed.
+    fn is_terminated(&self) -> bool {
+        self.closed && self.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::pin;
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+
+    use futures::executor::block_on;
+    use futures::task::noop_waker_ref;
+
+    use super::DelayMap;
+    use crate::{Clock, ClockGap, Duplicates, Entry, MockClock, Overflow, Scheduled, Shutdown};
+
+    const GAP: Duration = Duration::from_millis(50);
+
+    fn map(clock: &MockClock) -> DelayMap<&'static str, u32> {
+        DelayMap::builder().clock(clock.clone()).build_map()
+    }
+
+    /// Keys of every timeout that is already due, in order of delivery.
+    fn expired(map: &mut DelayMap<&'static str, u32>) -> Vec<&'static str> {
+        map.drain_expired()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    /// Builds a map watching for gaps longer than `threshold`, with timeouts 30ms and 60s away and
+    /// the wall clock having got ahead of `clock` by a gap of 50ms.
+    fn gapped(
+        clock: &MockClock,
+        threshold: Duration,
+        policy: ClockGap,
+    ) -> DelayMap<&'static str, ()> {
+        let mut map = DelayMap::builder()
+            .clock(clock.clone())
+            .on_clock_gap(threshold, policy)
+            .build_map();
+        map.insert("soon", (), Duration::from_millis(30));
+        map.insert("later", (), Duration::from_secs(60));
+
+        // Clocks are first compared as the map is polled
+        let mut cx = Context::from_waker(noop_waker_ref());
+        assert!(map.poll_expired(&mut cx).is_pending());
+        clock.set_system_time(clock.system_now() + GAP);
+
+        map
+    }
+
+    #[test]
+    fn fires_timeouts_within_a_clock_gap() {
+        let clock = MockClock::new();
+        let mut map = gapped(&clock, Duration::from_millis(20), ClockGap::Fire);
+        assert_eq!(map.resync(), Some(GAP));
+
+        assert_eq!(map.try_next(), Some(("soon", ())));
+        assert_eq!(map.try_next(), None);
+        let remaining = Duration::from_secs(60) - GAP;
+        assert_eq!(map.time_remaining("later"), Some(remaining));
+    }
+
+    #[test]
+    fn postpones_timeouts_past_a_clock_gap() {
+        let clock = MockClock::new();
+        let mut map = gapped(&clock, Duration::from_millis(20), ClockGap::Postpone);
+        assert_eq!(map.resync(), Some(GAP));
+
+        assert_eq!(map.try_next(), None);
+        assert_eq!(map.time_remaining("soon"), Some(Duration::from_millis(30)));
+        assert_eq!(map.time_remaining("later"), Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn ignores_gaps_within_the_threshold() {
+        let clock = MockClock::new();
+        let mut map = gapped(&clock, Duration::from_secs(10), ClockGap::Fire);
+        assert_eq!(map.resync(), None);
+
+        assert_eq!(map.try_next(), None);
+        assert_eq!(map.time_remaining("later"), Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn overflows_as_per_the_policy() {
+        let clock = MockClock::new();
+        let full = |overflow| {
+            let mut map = DelayMap::builder()
+                .clock(clock.clone())
+                .max_entries(2, overflow)
+                .build_map();
+            map.insert("b", 2, Duration::from_secs(2));
+            map.insert("a", 1, Duration::from_secs(1));
+            let inserted = map.insert("c", 3, Duration::from_secs(3));
+            (map, inserted)
+        };
+
+        let (mut rejecting, inserted) = full(Overflow::Reject);
+        let (mut soonest, evicted_soonest) = full(Overflow::EvictSoonest);
+        let (mut latest, evicted_latest) = full(Overflow::EvictLatest);
+        assert!(!inserted && evicted_soonest && evicted_latest);
+        assert_eq!(soonest.stats().removed, 1);
+
+        clock.advance(Duration::from_secs(3));
+        assert_eq!(expired(&mut rejecting), ["a", "b"]);
+        assert_eq!(expired(&mut soonest), ["b", "c"]);
+        assert_eq!(expired(&mut latest), ["a", "c"]);
+    }
+
+    #[test]
+    fn delivers_due_timeouts_by_priority() {
+        let clock = MockClock::new();
+        let mut map = map(&clock);
+        map.insert("low", 1, Duration::from_secs(1));
+        map.insert_with_priority("mid", 2, Duration::from_secs(3), 1);
+        map.insert_with_priority("urgent", 3, Duration::from_secs(3), 5);
+        map.insert_with_priority("high", 4, Duration::from_secs(2), 5);
+        map.insert_with_priority("later", 5, Duration::from_secs(4), 9);
+
+        // Same priorities are delivered in order of deadlines, none ahead of those yet to expire
+        clock.advance(Duration::from_secs(3));
+        assert_eq!(expired(&mut map), ["high", "urgent", "mid", "low"]);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(expired(&mut map), ["later"]);
+    }
+
+    #[test]
+    fn entries_are_occupied_or_vacant() {
+        let clock = MockClock::new();
+        let mut map = DelayMap::builder()
+            .clock(clock.clone())
+            .max_entries(1, Overflow::Reject)
+            .build_map();
+        let period = Duration::from_secs(10);
+        match map.entry("a") {
+            Entry::Vacant(entry) => assert_eq!(entry.insert(1, period), Some(&mut 1)),
+            Entry::Occupied(_) => panic!("entry for a missing key is occupied"),
+        }
+        assert_eq!(map.entry("b").or_insert(2, period), None);
+
+        clock.advance(Duration::from_secs(4));
+        let mut entry = match map.entry("a") {
+            Entry::Occupied(entry) => entry,
+            Entry::Vacant(_) => panic!("entry for a pending key is vacant"),
+        };
+        assert_eq!(entry.time_remaining(), Duration::from_secs(6));
+        *entry.get_mut() += 1;
+        assert_eq!(entry.insert(3), 2);
+        entry.reset(period);
+        assert_eq!(entry.deadline(), Some(map.now() + period));
+        assert_eq!(map.entry("a").or_insert(4, period), Some(&mut 3));
+
+        clock.advance(Duration::from_secs(6));
+        assert_eq!(map.try_next(), None);
+        match map.entry("a") {
+            Entry::Occupied(entry) => assert_eq!(entry.remove_entry(), ("a", 3)),
+            Entry::Vacant(_) => panic!("entry for a pending key is vacant"),
+        }
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn clones_are_independent() {
+        let clock = MockClock::new();
+        let mut map = map(&clock);
+        map.insert("a", 1, Duration::from_secs(1));
+        map.insert("b", 2, Duration::from_secs(2));
+        map.insert("c", 3, Duration::from_secs(5));
+        map.freeze("c");
+        clock.advance(Duration::from_secs(1));
+
+        let mut clone = map.clone();
+        assert_eq!(clone.stats(), map.stats());
+        clone.remove("b");
+        clone.insert("d", 4, Duration::from_secs(1));
+        *clone.get_mut("a").unwrap() = 5;
+        clone.thaw("c");
+        assert_eq!(clone.time_remaining("c"), Some(Duration::from_secs(5)));
+
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(map.drain_expired(), [("a", 1), ("b", 2)]);
+        assert_eq!(clone.drain_expired(), [("a", 5), ("d", 4)]);
+        assert!(map.is_frozen("c"));
+        assert_eq!(clone.time_remaining("c"), Some(Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn shuts_down_immediately() {
+        let clock = MockClock::new();
+        let mut map = map(&clock);
+        map.insert("a", 1, Duration::from_secs(1));
+        map.insert("b", 2, Duration::from_secs(2));
+        map.close();
+        assert!(map.is_closed());
+        assert!(!map.insert("c", 3, Duration::from_secs(1)));
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(map.try_next(), Some(("a", 1)));
+        assert_eq!(block_on(map.shutdown(Shutdown::Immediate)), [("b", 2)]);
+        assert_eq!(block_on(map.next()), None);
+    }
+
+    #[test]
+    fn shuts_down_gracefully() {
+        let clock = MockClock::new();
+        let mut map = map(&clock);
+        map.insert("a", 1, Duration::from_secs(1));
+        map.insert("b", 2, Duration::from_secs(2));
+        map.freeze("b");
+        map.pause();
+        clock.advance(Duration::from_secs(5));
+
+        // Resumes and thaws, so that every timeout is waited for from here on
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let expired = {
+            let mut shutdown = pin!(map.shutdown(Shutdown::Graceful));
+            assert!(shutdown.as_mut().poll(&mut cx).is_pending());
+            clock.advance(Duration::from_secs(1));
+            assert!(shutdown.as_mut().poll(&mut cx).is_pending());
+            clock.advance(Duration::from_secs(1));
+            shutdown.as_mut().poll(&mut cx)
+        };
+        assert_eq!(expired, Poll::Ready(vec![("a", 1), ("b", 2)]));
+        assert!(map.is_closed() && map.is_empty());
+    }
+
+    #[test]
+    fn merges_pending_and_frozen_timeouts() {
+        let clock = MockClock::new();
+        let other = || {
+            let mut other = map(&clock);
+            other.insert("a", 10, Duration::from_secs(1));
+            other.insert("b", 20, Duration::from_secs(2));
+            other.insert("c", 30, Duration::from_secs(3));
+            other.freeze("c");
+            other
+        };
+        let mut ignoring = map(&clock);
+        ignoring.insert("a", 1, Duration::from_secs(5));
+        let mut resetting = DelayMap::builder()
+            .clock(clock.clone())
+            .duplicates(Duplicates::Reset)
+            .build_map();
+        resetting.insert("a", 1, Duration::from_secs(5));
+
+        let rejected = ignoring.merge(other());
+        assert_eq!(rejected, [("a", 10, Duration::from_secs(1))]);
+        assert!(resetting.merge(other()).is_empty());
+        for map in [&ignoring, &resetting] {
+            assert!(map.is_frozen("c"));
+            assert_eq!(map.time_remaining("c"), Some(Duration::from_secs(3)));
+        }
+
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(expired(&mut ignoring), ["b", "a"]);
+        assert_eq!(resetting.drain_expired(), [("a", 10), ("b", 20)]);
+    }
+
+    #[test]
+    fn restores_snapshots_with_the_time_remaining() {
+        let clock = MockClock::new();
+        let mut map = map(&clock);
+        map.insert("a", 1, Duration::from_secs(10));
+        map.insert("b", 2, Duration::from_secs(20));
+        map.insert("c", 3, Duration::from_secs(30));
+        map.freeze("c");
+        clock.advance(Duration::from_secs(15));
+
+        let checkpoint = map.snapshot();
+        assert_eq!(map.len(), 3);
+        assert_eq!(
+            checkpoint,
+            [
+                ("a", 1, Duration::ZERO),
+                ("b", 2, Duration::from_secs(5)),
+                ("c", 3, Duration::from_secs(30)),
+            ]
+        );
+
+        clock.advance(Duration::from_secs(60));
+        let mut restored = DelayMap::builder().clock(clock.clone()).build_map();
+        restored.insert("d", 4, Duration::from_secs(1));
+        restored.restore(checkpoint);
+        assert!(!restored.contains("d"));
+        assert_eq!(restored.time_remaining("b"), Some(Duration::from_secs(5)));
+        assert_eq!(restored.time_remaining("c"), Some(Duration::from_secs(30)));
+        assert_eq!(expired(&mut restored), ["a"]);
+    }
+
+    #[test]
+    fn expire_all_keeps_the_order_of_deadlines() {
+        let clock = MockClock::new();
+        let mut map = map(&clock);
+        let start = map.now();
+        map.insert("hour", 1, Duration::from_secs(3600));
+        map.insert("second", 2, Duration::from_secs(1));
+        map.insert("minute", 3, Duration::from_secs(60));
+        map.insert("frozen", 4, Duration::from_secs(10));
+        map.freeze("frozen");
+
+        map.expire_all();
+        assert_eq!(map.time_remaining("hour"), Some(Duration::ZERO));
+        let next = map.try_next_expired().unwrap();
+        assert_eq!(next.get_ref().0, "frozen");
+        let next = map.try_next_expired().unwrap();
+        assert_eq!(next.get_ref().0, "second");
+        assert_eq!(next.deadline(), start + Duration::from_secs(1));
+        assert_eq!(next.lateness(), Duration::ZERO);
+        assert_eq!(expired(&mut map), ["minute", "hour"]);
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn resume_leaves_due_timeouts_due() {
+        let clock = MockClock::new();
+        let mut map = map(&clock);
+        map.insert("a", 1, Duration::from_secs(1));
+        map.insert("b", 2, Duration::from_secs(1));
+        map.insert("c", 3, Duration::from_secs(10));
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(map.try_next(), Some(("a", 1)));
+
+        map.pause();
+        clock.advance(Duration::from_secs(5));
+        map.resume();
+        assert_eq!(map.time_remaining("b"), Some(Duration::ZERO));
+        assert_eq!(map.time_remaining("c"), Some(Duration::from_secs(8)));
+        let next = map.try_next_expired().unwrap();
+        assert_eq!(next.get_ref().0, "b");
+        assert_eq!(next.lateness(), Duration::from_secs(6));
+        assert_eq!(map.try_next(), None);
+    }
+
+    #[test]
+    fn insert_or_reset_upserts() {
+        let clock = MockClock::new();
+        let mut map = DelayMap::builder()
+            .clock(clock.clone())
+            .max_entries(2, Overflow::Reject)
+            .build_map();
+        let period = Duration::from_secs(10);
+        assert_eq!(map.insert_or_reset("a", 1, period), Scheduled::Inserted);
+        assert_eq!(map.insert_or_reset("b", 2, period), Scheduled::Inserted);
+        assert_eq!(map.insert_or_reset("c", 3, period), Scheduled::Rejected);
+        assert!(!map.contains("c"));
+
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(map.insert_or_reset("a", 4, period), Scheduled::Reset);
+        assert_eq!(map.get("a"), Some(&4));
+        assert_eq!(map.time_remaining("a"), Some(period));
+
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(expired(&mut map), ["b"]);
+        map.close();
+        assert_eq!(map.insert_or_reset("b", 5, period), Scheduled::Rejected);
+        assert_eq!(map.insert_or_reset("a", 6, period), Scheduled::Reset);
+    }
+
+    #[test]
+    fn wall_clock_deadlines_follow_the_clock() {
+        let clock = MockClock::new();
+        let mut map = map(&clock);
+        let hour = Duration::from_secs(3600);
+        map.insert_at_system_time("a", 1, clock.system_now() + hour);
+        map.insert_at_system_time("b", 2, clock.system_now() + 2 * hour);
+        let mut cx = Context::from_waker(noop_waker_ref());
+        assert!(map.poll_expired(&mut cx).is_pending());
+
+        clock.advance(hour);
+        assert_eq!(expired(&mut map), ["a"]);
+
+        // Setting the time back re-anchors the deadline, while the monotonic clock stands still
+        clock.set_system_time(clock.system_now() - Duration::from_secs(600));
+        assert_eq!(map.resync(), None);
+        assert_eq!(
+            map.time_remaining("b"),
+            Some(hour + Duration::from_secs(600))
+        );
+    }
+}
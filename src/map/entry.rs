@@ -0,0 +1,185 @@
+use std::hash::{BuildHasher, Hash};
+use std::mem;
+use std::time::{Duration, Instant};
+
+use super::{DefaultHashBuilder, DelayMap, Id};
+use crate::clock::saturating_add;
+
+/// A view into the timeout associated with a key in a [`DelayMap`], which is either pending or
+/// vacant, returned by [`entry()`](DelayMap::entry).
+pub enum Entry<'a, K, V, S = DefaultHashBuilder> {
+    /// A timeout exists for the key, possibly frozen or already due.
+    Occupied(OccupiedEntry<'a, K, V, S>),
+    /// No timeout exists for the key.
+    Vacant(VacantEntry<'a, K, V, S>),
+}
+
+/// A view into a timeout that exists in a [`DelayMap`], part of an [`Entry`].
+pub struct OccupiedEntry<'a, K, V, S = DefaultHashBuilder> {
+    map: &'a mut DelayMap<K, V, S>,
+    id: Id,
+}
+
+/// A view into a key that has no timeout in a [`DelayMap`], part of an [`Entry`].
+pub struct VacantEntry<'a, K, V, S = DefaultHashBuilder> {
+    map: &'a mut DelayMap<K, V, S>,
+    key: K,
+    /// Hash of the key, so that it isn't hashed again on inserting.
+    hash: u64,
+}
+
+impl<'a, K, V, S> Entry<'a, K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    /// Returns the key of the entry.
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    /// Returns the payload of the timeout, inserting it with `value` to timeout after `period` if
+    /// it doesn't exist. Returns `None` if it had to be inserted but was rejected, as the delay-map
+    /// is full.
+    pub fn or_insert(self, value: V, period: Duration) -> Option<&'a mut V> {
+        match self {
+            Entry::Occupied(entry) => Some(entry.into_mut()),
+            Entry::Vacant(entry) => entry.insert(value, period),
+        }
+    }
+}
+
+impl<'a, K, V, S> OccupiedEntry<'a, K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    pub(super) fn new(map: &'a mut DelayMap<K, V, S>, id: Id) -> Self {
+        Self { map, id }
+    }
+
+    /// Returns the key of the timeout.
+    pub fn key(&self) -> &K {
+        &self.map.slot(self.id).key
+    }
+
+    /// Returns the payload of the timeout.
+    pub fn get(&self) -> &V {
+        &self.map.slot(self.id).value
+    }
+
+    /// Returns the payload of the timeout for modification.
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.map.slot_mut(self.id).value
+    }
+
+    /// Converts the entry into the payload of the timeout, borrowed for as long as the delay-map.
+    pub fn into_mut(self) -> &'a mut V {
+        &mut self.map.slot_mut(self.id).value
+    }
+
+    /// Replaces the payload of the timeout, leaving its deadline as is, returning the previous one.
+    pub fn insert(&mut self, value: V) -> V {
+        mem::replace(self.get_mut(), value)
+    }
+
+    /// Returns the instant at which the timeout expires, `None` if it is frozen.
+    pub fn deadline(&self) -> Option<Instant> {
+        let slot = self.map.slot(self.id);
+
+        slot.frozen.is_none().then_some(slot.deadline)
+    }
+
+    /// Returns how long until the timeout expires, zero if it is already due. For frozen timeouts,
+    /// this is the time that will be remaining once thawed.
+    pub fn time_remaining(&self) -> Duration {
+        let slot = self.map.slot(self.id);
+
+        match slot.frozen {
+            Some(remaining) => remaining,
+            _ if slot.due => Duration::ZERO,
+            _ => slot.deadline.saturating_duration_since(self.map.now()),
+        }
+    }
+
+    /// Reschedules the timeout to expire after `period` from now.
+    pub fn reset(&mut self, period: Duration) {
+        self.reset_at(saturating_add(self.map.now(), period));
+    }
+
+    /// Reschedules the timeout to expire at `deadline`.
+    pub fn reset_at(&mut self, deadline: Instant) {
+        let ttl = deadline.saturating_duration_since(self.map.now());
+        self.map.slot_mut(self.id).ttl = ttl;
+        self.map.reschedule(self.id, deadline);
+    }
+
+    /// Returns the priority that decides the order in which the timeout is delivered, among those
+    /// that are due together.
+    pub fn priority(&self) -> u8 {
+        self.map.slot(self.id).priority
+    }
+
+    /// Changes the priority of the timeout, as if it had been inserted with
+    /// [`insert_with_priority()`](DelayMap::insert_with_priority).
+    pub fn set_priority(&mut self, priority: u8) {
+        self.map.set_priority(self.id, priority);
+    }
+
+    /// Prematurely removes the timeout from the delay-map, returning its payload.
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+
+    /// Prematurely removes the timeout from the delay-map, returning the owned key along with its
+    /// payload.
+    pub fn remove_entry(self) -> (K, V) {
+        let slot = self.map.unlink(self.id);
+        self.map.dequeue(&slot);
+        self.map.maybe_shrink();
+
+        (slot.key, slot.value)
+    }
+}
+
+impl<'a, K, V, S> VacantEntry<'a, K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    pub(super) fn new(map: &'a mut DelayMap<K, V, S>, key: K, hash: u64) -> Self {
+        Self { map, key, hash }
+    }
+
+    /// Returns the key that would be inserted.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Takes back ownership of the key.
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    /// Insert a new timeout for the key with associated payload, to timeout after `period`,
+    /// returning the payload. Returns `None` if it was rejected, as the delay-map is full.
+    pub fn insert(self, value: V, period: Duration) -> Option<&'a mut V> {
+        let deadline = self.map.jittered(period, self.map.jitter);
+        self.insert_at(value, deadline)
+    }
+
+    /// Insert a new timeout for the key with associated payload, to timeout at `deadline`,
+    /// returning the payload. Returns `None` if it was rejected, as the delay-map is full.
+    pub fn insert_at(self, value: V, deadline: Instant) -> Option<&'a mut V> {
+        let map = self.map;
+        if !map.schedule_vacant(self.hash, self.key, value, deadline, None) {
+            return None;
+        }
+
+        let id = map.inserted(deadline);
+        Some(&mut map.slot_mut(id).value)
+    }
+}
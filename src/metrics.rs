@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use metrics::{Counter, Gauge, Histogram, Label};
+
+use crate::Stats;
+
+/// Handles to the metrics that a delay-map reports its activity to, registered with the recorder
+/// installed when it was built. Everything is reported as increments since the last report, so
+/// that delay-maps sharing the same labels add up.
+pub(crate) struct Metrics {
+    inserted: Counter,
+    duplicates: Counter,
+    removed: Counter,
+    expired: Counter,
+    depth: Gauge,
+    lag: Histogram,
+    reported: Stats,
+}
+
+impl Metrics {
+    pub(crate) fn new(labels: &[(String, String)]) -> Self {
+        metrics::describe_counter!("delay_handler_inserted_total", "Timeouts inserted");
+        metrics::describe_counter!(
+            "delay_handler_duplicates_total",
+            "Inserts rejected as a timeout already existed for the key"
+        );
+        metrics::describe_counter!(
+            "delay_handler_removed_total",
+            "Timeouts removed before expiring"
+        );
+        metrics::describe_counter!("delay_handler_expired_total", "Expiries delivered");
+        metrics::describe_gauge!("delay_handler_depth", "Timeouts currently pending");
+        metrics::describe_histogram!(
+            "delay_handler_expiry_lag_seconds",
+            metrics::Unit::Seconds,
+            "Time between the deadline of a timeout and its expiry"
+        );
+
+        let labels: Vec<Label> = labels
+            .iter()
+            .map(|(key, value)| Label::new(key.clone(), value.clone()))
+            .collect();
+
+        Self {
+            inserted: metrics::counter!("delay_handler_inserted_total", labels.clone()),
+            duplicates: metrics::counter!("delay_handler_duplicates_total", labels.clone()),
+            removed: metrics::counter!("delay_handler_removed_total", labels.clone()),
+            expired: metrics::counter!("delay_handler_expired_total", labels.clone()),
+            depth: metrics::gauge!("delay_handler_depth", labels.clone()),
+            lag: metrics::histogram!("delay_handler_expiry_lag_seconds", labels),
+            reported: Stats::default(),
+        }
+    }
+
+    /// Reports the change in `stats` since they were last reported.
+    pub(crate) fn report(&mut self, stats: Stats) {
+        let reported = self.reported;
+        self.inserted.increment(stats.inserted - reported.inserted);
+        self.duplicates
+            .increment(stats.duplicates - reported.duplicates);
+        self.removed.increment(stats.removed - reported.removed);
+        self.expired.increment(stats.expired - reported.expired);
+        self.depth
+            .increment(stats.depth as f64 - reported.depth as f64);
+        self.reported = stats;
+    }
+
+    /// Records how late a timeout expired, past its deadline.
+    pub(crate) fn lag(&self, lag: Duration) {
+        self.lag.record(lag.as_secs_f64());
+    }
+}
+
+impl Drop for Metrics {
+    fn drop(&mut self) {
+        // Timeouts pending in a dropped delay-map are no longer part of the depth
+        self.depth.decrement(self.reported.depth as f64);
+    }
+}
@@ -0,0 +1,69 @@
+use std::time::Instant;
+
+use crate::Event;
+
+/// Hooks into the lifecycle of timeouts, invoked synchronously as they are inserted, reset,
+/// removed and expire, once installed with [`add_observer()`](crate::DelayMap::add_observer).
+///
+/// Every method does nothing by default, so that only those of interest need to be implemented,
+/// e.g. to keep custom metrics or check invariants. Being called while the delay-map is borrowed
+/// for the operation, they should return quickly.
+///
+/// ### Examples
+/// Count timeouts that were cancelled, as opposed to those that expired
+/// ```
+/// # use delay_handler::{DelayHandler, Observer};
+/// # use std::sync::atomic::{AtomicU64, Ordering};
+/// # use std::sync::Arc;
+/// # use std::time::Duration;
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// struct Cancellations(Arc<AtomicU64>);
+///
+/// impl<K> Observer<K> for Cancellations {
+///     fn on_remove(&mut self, _: &K) {
+///         self.0.fetch_add(1, Ordering::Relaxed);
+///     }
+/// }
+///
+/// let cancelled = Arc::new(AtomicU64::new(0));
+/// let mut handler = DelayHandler::<u32>::default();
+/// handler.add_observer(Cancellations(cancelled.clone()));
+///
+/// handler.insert(1, Duration::from_secs(5));
+/// handler.remove(&1);
+/// assert_eq!(cancelled.load(Ordering::Relaxed), 1);
+/// # }
+/// ```
+pub trait Observer<K>: Send + Sync {
+    /// Invoked once a new timeout for `key` is inserted, to expire at `deadline`.
+    fn on_insert(&mut self, key: &K, deadline: Instant) {
+        let _ = (key, deadline);
+    }
+
+    /// Invoked once the pending timeout for `key` is rescheduled to expire at `deadline`.
+    fn on_reset(&mut self, key: &K, deadline: Instant) {
+        let _ = (key, deadline);
+    }
+
+    /// Invoked once the timeout for `key` is removed before it could expire, such as by removal,
+    /// eviction, cancellation, clearing or draining.
+    fn on_remove(&mut self, key: &K) {
+        let _ = key;
+    }
+
+    /// Invoked once the timeout for `key`, that was due at `deadline`, expires.
+    fn on_expire(&mut self, key: &K, deadline: Instant) {
+        let _ = (key, deadline);
+    }
+}
+
+/// Invokes the method of `observer` that corresponds to `event`.
+pub(crate) fn notify<K>(observer: &mut dyn Observer<K>, event: &Event<&K>) {
+    match *event {
+        Event::Inserted { key, deadline, .. } => observer.on_insert(key, deadline),
+        Event::Reset { key, deadline, .. } => observer.on_reset(key, deadline),
+        Event::Removed { key, .. } => observer.on_remove(key),
+        Event::Expired { key, deadline, .. } => observer.on_expire(key, deadline),
+    }
+}
@@ -0,0 +1,248 @@
+use std::borrow::Borrow;
+use std::future::poll_fn;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_core::{FusedStream, Stream};
+use tokio::sync::oneshot;
+
+use crate::DelayMap;
+
+/// Correlates requests with their responses, failing those that aren't responded to in time.
+///
+/// Each pending request is a [`oneshot::Sender`] keyed by its request ID, that is fulfilled with
+/// [`complete()`](PendingRequests::complete) once the response arrives, cancelling its timeout.
+/// Requests that timeout are yielded by [`next()`](PendingRequests::next), which drops their
+/// sender, so that whoever is waiting on the receiver gets a [`RecvError`](oneshot::error::RecvError).
+///
+/// ### Examples
+/// ```
+/// # use delay_handler::PendingRequests;
+/// # use std::time::Duration;
+/// # #[tokio::main(flavor = "current_thread", start_paused = true)]
+/// # async fn main() {
+/// let mut pending: PendingRequests<u64, String> = PendingRequests::default();
+/// let first = pending.request(1, Duration::from_secs(5));
+/// let second = pending.request(2, Duration::from_secs(5));
+///
+/// // The response to the first request arrives in time, the second is never responded to.
+/// pending.complete(&1, "pong".to_owned()).unwrap();
+/// assert_eq!(first.await.unwrap(), "pong");
+///
+/// // Expect a delay of ~5s, after which the second request fails.
+/// assert_eq!(pending.next().await, Some(2));
+/// assert!(second.await.is_err());
+/// # }
+/// ```
+pub struct PendingRequests<K, R> {
+    map: DelayMap<K, oneshot::Sender<R>>,
+}
+
+impl<K, R> PendingRequests<K, R>
+where
+    K: Eq + Hash,
+{
+    /// Creates an empty set of pending requests, with space for at least `capacity` requests
+    /// before reallocating.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            map: DelayMap::with_capacity(capacity),
+        }
+    }
+
+    /// Insert a new request, to be responded to through `sender` within `timeout`. If a request is
+    /// already pending with the same key, don't insert and drop the provided sender, returning false.
+    pub fn insert(&mut self, key: K, sender: oneshot::Sender<R>, timeout: Duration) -> bool {
+        self.map.insert(key, sender, timeout)
+    }
+
+    /// Insert a new request that has to be responded to within `timeout`, returning the receiver
+    /// that the response will be delivered to. The receiver errors if a request is already pending
+    /// with the same key, or once the request timesout.
+    pub fn request(&mut self, key: K, timeout: Duration) -> oneshot::Receiver<R> {
+        let (sender, receiver) = oneshot::channel();
+        self.insert(key, sender, timeout);
+
+        receiver
+    }
+
+    /// Fulfils a pending request with its `response`, cancelling its timeout. Returns the response
+    /// back if no request is pending with the key, as it has already timedout or was never
+    /// inserted, or if the receiver has been dropped.
+    pub fn complete<Q>(&mut self, key: &Q, response: R) -> Result<(), R>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        match self.map.remove(key) {
+            Some(sender) => sender.send(response),
+            _ => Err(response),
+        }
+    }
+
+    /// Prematurely removes a pending request without responding to it, dropping its sender.
+    /// Returns false if no request is pending with the key.
+    pub fn cancel<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.map.remove(key).is_some()
+    }
+
+    /// Returns how long until a pending request timesout, zero if it is already due.
+    pub fn time_remaining<Q>(&self, key: &Q) -> Option<Duration>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.map.time_remaining(key)
+    }
+
+    /// Remove a request that has timedout and return its key, failing the request by dropping its
+    /// sender.
+    pub async fn next(&mut self) -> Option<K> {
+        poll_fn(|cx| self.poll_expired(cx)).await
+    }
+
+    /// Polls for a request that has timedout, registering the current task for wakeup if none has
+    /// yet.
+    pub fn poll_expired(&mut self, cx: &mut Context<'_>) -> Poll<Option<K>> {
+        self.map
+            .poll_expired(cx)
+            .map(|expired| expired.map(|(key, _)| key))
+    }
+
+    /// Remove a request if it has already timedout and return its key, without waiting.
+    pub fn try_next(&mut self) -> Option<K> {
+        let (key, _) = self.map.try_next()?;
+
+        Some(key)
+    }
+
+    /// Remove all pending requests, failing them by dropping their senders.
+    pub fn clear(&mut self) {
+        self.map.clear()
+    }
+
+    /// Check if no requests are pending.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Check if a request is pending with the key.
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.map.contains(key)
+    }
+
+    /// Number of requests currently pending.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+}
+
+impl<K, R> Default for PendingRequests<K, R>
+where
+    K: Eq + Hash,
+{
+    fn default() -> Self {
+        Self::with_capacity(0)
+    }
+}
+
+impl<K, R> Stream for PendingRequests<K, R>
+where
+    K: Eq + Hash,
+{
+    type Item = K;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_expired(cx)
+    }
+}
+
+impl<K, R> FusedStream for PendingRequests<K, R>
+where
+    K: Eq + Hash,
+{
+    fn is_terminated(&self) -> bool {
+        self.map.is_terminated()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::sync::oneshot::error::TryRecvError;
+
+    use super::PendingRequests;
+    use crate::{DelayMap, MockClock};
+
+    fn pending(clock: &MockClock) -> PendingRequests<u64, &'static str> {
+        PendingRequests {
+            map: DelayMap::builder().clock(clock.clone()).build_map(),
+        }
+    }
+
+    #[test]
+    fn completes_requests_before_they_timeout() {
+        let clock = MockClock::new();
+        let mut pending = pending(&clock);
+        let mut response = pending.request(1, Duration::from_secs(5));
+
+        clock.advance(Duration::from_secs(4));
+        assert_eq!(pending.complete(&1, "pong"), Ok(()));
+        assert_eq!(response.try_recv(), Ok("pong"));
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(pending.try_next(), None);
+        assert_eq!(pending.complete(&1, "late"), Err("late"));
+    }
+
+    #[test]
+    fn fails_requests_that_timeout() {
+        let clock = MockClock::new();
+        let mut pending = pending(&clock);
+        let mut response = pending.request(1, Duration::from_secs(5));
+        let _other = pending.request(2, Duration::from_secs(10));
+
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(pending.try_next(), Some(1));
+        assert_eq!(pending.try_next(), None);
+        assert_eq!(response.try_recv(), Err(TryRecvError::Closed));
+        assert_eq!(pending.complete(&1, "late"), Err("late"));
+        assert_eq!(pending.time_remaining(&2), Some(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn keeps_the_first_of_duplicate_requests() {
+        let clock = MockClock::new();
+        let mut pending = pending(&clock);
+        let mut first = pending.request(1, Duration::from_secs(5));
+        let mut second = pending.request(1, Duration::from_secs(1));
+
+        assert_eq!(second.try_recv(), Err(TryRecvError::Closed));
+        assert_eq!(pending.time_remaining(&1), Some(Duration::from_secs(5)));
+        assert_eq!(pending.complete(&1, "pong"), Ok(()));
+        assert_eq!(first.try_recv(), Ok("pong"));
+    }
+
+    #[test]
+    fn cancels_requests_without_responding() {
+        let clock = MockClock::new();
+        let mut pending = pending(&clock);
+        let mut response = pending.request(1, Duration::from_secs(5));
+
+        assert!(pending.cancel(&1));
+        assert!(!pending.cancel(&1));
+        assert_eq!(response.try_recv(), Err(TryRecvError::Closed));
+        assert!(pending.is_empty());
+    }
+}
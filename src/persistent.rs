@@ -0,0 +1,696 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::future::poll_fn;
+use std::hash::{BuildHasher, Hash};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "tokio")]
+use crate::clock::TokioClock;
+use crate::clock::{saturating_add, Clock};
+use crate::{DelayHandler, DelayHandlerBuilder, Event};
+
+/// A [`DelayHandler`] that journals every insert, removal and expiry to an append-only file, so that
+/// scheduled timeouts survive crashes and restarts. On [opening](PersistentDelayHandler::open) the
+/// journal is replayed to reschedule outstanding timeouts, counting the time spent down against them,
+/// so that those which should have expired in the meantime do so right away.
+///
+/// An expiry is journaled as it is handed out, if that fails the error is returned instead and the
+/// item is expired again on the next open. Records are flushed to the OS as they are written, which
+/// survives the process crashing, but not necessarily the machine, unless opened with an [`Fsync`]
+/// policy through [`open_with()`](PersistentDelayHandler::open_with). To configure the handler, such
+/// as with a [`Clock`], open it through [`DelayHandlerBuilder::build_persistent()`].
+///
+/// ### Examples
+/// ```no_run
+/// # use delay_handler::PersistentDelayHandler;
+/// # use std::time::Duration;
+/// # async fn run() -> std::io::Result<()> {
+/// let mut handler = PersistentDelayHandler::open("retries.journal")?;
+/// handler.insert("job-1".to_owned(), Duration::from_secs(60))?;
+///
+/// // Prints "job-1" after ~60s, even if the process was restarted in between.
+/// while let Some(expired) = handler.next().await {
+///     println!("{}", expired?);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct PersistentDelayHandler<T> {
+    handler: DelayHandler<T>,
+    journal: Journal,
+}
+
+/// How often the journal of a [`PersistentDelayHandler`] is synced to disk, trading throughput for
+/// durability against power loss.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Fsync {
+    /// Only flush records to the OS, leaving it to decide when they reach the disk.
+    #[default]
+    Never,
+    /// Sync after every record, so that no acknowledged mutation is ever lost.
+    Always,
+    /// Sync after every `n` records, losing at most the last `n - 1` of them.
+    Every(usize),
+}
+
+/// An open journal, appended to and synced to disk as per its [`Fsync`] policy.
+struct Journal {
+    writer: BufWriter<File>,
+    path: PathBuf,
+    fsync: Fsync,
+    /// Records appended since the journal was last synced to disk.
+    unsynced: usize,
+}
+
+/// The journal of a [`DelayHandler`] built with
+/// [`build_journaled()`](DelayHandlerBuilder::build_journaled), shared with the emitter that
+/// appends its mutations, along with the error that stopped it, if any.
+pub(crate) struct Wal {
+    journal: Journal,
+    error: Option<io::Error>,
+}
+
+/// A single journaled mutation, deadlines are stored as wall-clock time since the unix epoch, to be
+/// meaningful across restarts.
+#[derive(Serialize, Deserialize)]
+enum Record<T> {
+    Schedule(T, Duration),
+    Remove(T),
+    Expire(T),
+}
+
+impl<T> PersistentDelayHandler<T>
+where
+    T: Eq + Hash + Serialize + DeserializeOwned,
+{
+    /// Opens the journal at `path`, creating it if it doesn't exist, and reschedules every timeout
+    /// that was outstanding in it. The journal is compacted to hold only those timeouts.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::open_with(path, Fsync::Never)
+    }
+
+    /// Opens the journal at `path` as with [`open()`](PersistentDelayHandler::open), syncing it to
+    /// disk as configured by `fsync`.
+    pub fn open_with<P: AsRef<Path>>(path: P, fsync: Fsync) -> io::Result<Self> {
+        DelayHandlerBuilder::new().build_persistent(path, fsync)
+    }
+
+    fn append<Q>(&mut self, record: Record<&Q>) -> io::Result<()>
+    where
+        Q: Serialize + ?Sized,
+    {
+        self.journal.append(record)
+    }
+
+    /// Syncs all journaled records to disk, regardless of the [`Fsync`] policy.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.journal.sync()
+    }
+
+    /// Insert new timeout if it doesn't already exist, journaling it before it is scheduled.
+    /// If one already exists, don't insert, returning false.
+    pub fn insert(&mut self, item: T, period: Duration) -> io::Result<bool> {
+        if self.handler.contains(&item) {
+            return Ok(false);
+        }
+
+        let deadline = unix_time(self.handler.map.system_now()).saturating_add(period);
+        self.append(Record::Schedule(&item, deadline))?;
+
+        Ok(self.handler.insert(item, period))
+    }
+
+    /// Prematurely removes timeout from delay-map, returning false if it didn't already exist.
+    pub fn remove<Q>(&mut self, item: &Q) -> io::Result<bool>
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + Serialize + ?Sized,
+    {
+        if !self.handler.contains(item) {
+            return Ok(false);
+        }
+
+        self.append(Record::Remove(item))?;
+
+        Ok(self.handler.remove(item))
+    }
+
+    /// Updates the deadline of an existing timeout in place, to timeout after `period` from now.
+    /// If it didn't already exist returns false.
+    pub fn reset<Q>(&mut self, item: &Q, period: Duration) -> io::Result<bool>
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + Serialize + ?Sized,
+    {
+        if !self.handler.contains(item) {
+            return Ok(false);
+        }
+
+        let deadline = unix_time(self.handler.map.system_now()).saturating_add(period);
+        self.append(Record::Schedule(item, deadline))?;
+
+        Ok(self.handler.reset(item, period))
+    }
+
+    /// Remove an item from map if it has timedout and return it, journaling its expiry.
+    /// Returns `None` once the handler is empty.
+    pub async fn next(&mut self) -> Option<io::Result<T>> {
+        poll_fn(|cx| self.poll_expired(cx)).await
+    }
+
+    /// Polls for an item that has timedout, registering the current task for wakeup if none has yet.
+    pub fn poll_expired(&mut self, cx: &mut Context<'_>) -> Poll<Option<io::Result<T>>> {
+        match self.handler.poll_expired(cx) {
+            Poll::Ready(Some(item)) => Poll::Ready(Some(self.expire(item))),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    /// Remove an item from map if it has already timedout and return it, without waiting.
+    pub fn try_next(&mut self) -> Option<io::Result<T>> {
+        let item = self.handler.try_next()?;
+
+        Some(self.expire(item))
+    }
+
+    fn expire(&mut self, item: T) -> io::Result<T> {
+        self.append(Record::Expire(&item))?;
+
+        Ok(item)
+    }
+
+    /// Remove all pending timeouts from the delay-map, truncating the journal.
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.handler.clear();
+        self.compact()
+    }
+
+    /// Rewrites the journal to hold only the timeouts that are currently outstanding, as it
+    /// otherwise keeps growing with every mutation.
+    pub fn compact(&mut self) -> io::Result<()> {
+        self.journal.compact(&self.handler)
+    }
+
+    /// Check if queue is empty.
+    pub fn is_empty(&self) -> bool {
+        self.handler.is_empty()
+    }
+
+    /// Check if a timeout is currently scheduled for `item`.
+    pub fn contains<Q>(&self, item: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.handler.contains(item)
+    }
+
+    /// Number of timeouts currently pending in the delay-map.
+    pub fn len(&self) -> usize {
+        self.handler.len()
+    }
+}
+
+impl<T> DelayHandler<T>
+where
+    T: Eq + Hash + DeserializeOwned,
+{
+    /// Rebuilds an in-memory handler by replaying the journal of a [`PersistentDelayHandler`] or of
+    /// a [journaled](DelayHandlerBuilder::build_journaled) handler at `path`, counting the time spent
+    /// down against every outstanding timeout. Unlike [`PersistentDelayHandler::open()`], the journal
+    /// is left untouched and no longer written to. To configure the handler, recover it through
+    /// [`DelayHandlerBuilder::recover()`] instead.
+    pub fn recover<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        DelayHandlerBuilder::new().recover(path)
+    }
+
+    /// Reschedules every timeout outstanding in the journal at `path`, at its deadline on the wall
+    /// clock, so that jitter isn't added again.
+    fn replay(&mut self, path: &Path) -> io::Result<()> {
+        let (now, then) = (unix_time(self.map.system_now()), self.map.now());
+        for (item, deadline) in replay(path)? {
+            self.insert_at(item, saturating_add(then, deadline.saturating_sub(now)));
+        }
+
+        Ok(())
+    }
+}
+
+impl<T> DelayHandler<T>
+where
+    T: Eq + Hash + Serialize,
+{
+    /// Syncs every mutation journaled so far to disk, regardless of the [`Fsync`] policy the handler
+    /// was [built with](DelayHandlerBuilder::build_journaled). If writing to the journal failed,
+    /// returns that error instead, as mutations stopped being journaled at that point. Does nothing
+    /// if the handler isn't journaled, including once such an error has been returned.
+    pub fn sync_journal(&mut self) -> io::Result<()> {
+        self.with_journal(|journal, _| journal.sync())
+    }
+
+    /// Rewrites the journal to hold only the timeouts that are currently outstanding, as it otherwise
+    /// keeps growing with every mutation. Errors are returned as with
+    /// [`sync_journal()`](DelayHandler::sync_journal).
+    pub fn compact_journal(&mut self) -> io::Result<()> {
+        self.with_journal(|journal, handler| journal.compact(handler))
+    }
+
+    fn with_journal<F>(&mut self, f: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut Journal, &Self) -> io::Result<()>,
+    {
+        let wal = match &self.journal {
+            Some(wal) => wal.clone(),
+            _ => return Ok(()),
+        };
+
+        let mut wal = wal.lock().unwrap();
+        if let Some(e) = wal.error.take() {
+            self.journal = None;
+            return Err(e);
+        }
+
+        f(&mut wal.journal, self)
+    }
+}
+
+impl DelayHandlerBuilder {
+    /// Constructs a [`DelayHandler`] with the configuration, rebuilt by replaying the journal at
+    /// `path` as with [`DelayHandler::recover()`], with deadlines read from the configured clock.
+    pub fn recover<T, P>(self, path: P) -> io::Result<DelayHandler<T>>
+    where
+        T: Eq + Hash + DeserializeOwned,
+        P: AsRef<Path>,
+    {
+        let mut handler = self.build();
+        handler.replay(path.as_ref())?;
+
+        Ok(handler)
+    }
+
+    /// Opens a [`PersistentDelayHandler`] as with [`PersistentDelayHandler::open_with()`], wrapping
+    /// a handler constructed with the configuration.
+    pub fn build_persistent<T, P>(
+        self,
+        path: P,
+        fsync: Fsync,
+    ) -> io::Result<PersistentDelayHandler<T>>
+    where
+        T: Eq + Hash + Serialize + DeserializeOwned,
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let handler = self.recover(path)?;
+        let journal = Journal::open(path, fsync, &handler)?;
+
+        Ok(PersistentDelayHandler { handler, journal })
+    }
+
+    /// The clock that handlers built with the configuration schedule timeouts against.
+    fn clock_or_default(&self) -> Arc<dyn Clock> {
+        #[cfg(feature = "tokio")]
+        let fallback = || Arc::new(TokioClock) as Arc<dyn Clock>;
+        #[cfg(not(feature = "tokio"))]
+        let fallback = crate::clock::fallback;
+
+        self.clock.clone().unwrap_or_else(fallback)
+    }
+
+    /// Constructs a [`DelayHandler`] with the configuration, that journals every insert, reset,
+    /// removal and expiry to the append-only file at `path`, synced to disk as per `fsync`. Timeouts
+    /// outstanding in the journal are rescheduled first, counting the time spent down against them,
+    /// after which it is compacted to hold only those timeouts. Lighter than a
+    /// [`PersistentDelayHandler`], as the handler keeps its whole API, though only items and their
+    /// deadlines are journaled, not actions or recurrence.
+    ///
+    /// Mutations don't fail if their record can't be written, they stop being journaled instead,
+    /// which is reported by [`sync_journal()`](DelayHandler::sync_journal).
+    ///
+    /// ### Examples
+    /// ```no_run
+    /// # use delay_handler::{DelayHandler, Fsync};
+    /// # use std::time::Duration;
+    /// # async fn run() -> std::io::Result<()> {
+    /// let mut handler = DelayHandler::builder().build_journaled("retries.journal", Fsync::Always)?;
+    /// handler.insert("job-1".to_owned(), Duration::from_secs(60));
+    ///
+    /// // Prints "job-1" after ~60s, even if the process was restarted in between.
+    /// while let Some(expired) = handler.next().await {
+    ///     println!("{expired}");
+    /// }
+    /// handler.sync_journal()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn build_journaled<T, P>(self, path: P, fsync: Fsync) -> io::Result<DelayHandler<T>>
+    where
+        T: Eq + Hash + Serialize + DeserializeOwned,
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let clock = self.clock_or_default();
+        let mut handler = self.recover(path)?;
+
+        let wal = Arc::new(Mutex::new(Wal {
+            journal: Journal::open(path, fsync, &handler)?,
+            error: None,
+        }));
+        let shared = wal.clone();
+        handler.map.emit_to(Box::new(move |event| {
+            let mut wal = shared.lock().unwrap();
+            let record = match *event {
+                Event::Inserted { key, deadline, at } | Event::Reset { key, deadline, at } => {
+                    let remaining = deadline.saturating_duration_since(at);
+                    Record::Schedule(key, unix_time(clock.system_now()).saturating_add(remaining))
+                }
+                Event::Removed { key, .. } => Record::Remove(key),
+                Event::Expired { key, .. } => Record::Expire(key),
+            };
+
+            // Keep the error for the handler to report, dropping this emitter
+            match wal.journal.append(record) {
+                Ok(()) => true,
+                Err(e) => {
+                    wal.error = Some(e);
+                    false
+                }
+            }
+        }));
+        handler.journal = Some(wal);
+
+        Ok(handler)
+    }
+}
+
+impl Journal {
+    /// Opens the journal at `path` for appending, once compacted to hold only the outstanding
+    /// timeouts of `handler`.
+    fn open<T, S>(path: &Path, fsync: Fsync, handler: &DelayHandler<T, S>) -> io::Result<Self>
+    where
+        T: Eq + Hash + Serialize,
+        S: BuildHasher,
+    {
+        Ok(Self {
+            writer: compact(path, handler)?,
+            path: path.to_owned(),
+            fsync,
+            unsynced: 0,
+        })
+    }
+
+    fn append<Q>(&mut self, record: Record<&Q>) -> io::Result<()>
+    where
+        Q: Serialize + ?Sized,
+    {
+        serde_json::to_writer(&mut self.writer, &record)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+
+        self.unsynced += 1;
+        match self.fsync {
+            Fsync::Always => self.sync(),
+            Fsync::Every(n) if self.unsynced >= n => self.sync(),
+            _ => Ok(()),
+        }
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.writer.get_ref().sync_data()?;
+        self.unsynced = 0;
+
+        Ok(())
+    }
+
+    fn compact<T, S>(&mut self, handler: &DelayHandler<T, S>) -> io::Result<()>
+    where
+        T: Eq + Hash + Serialize,
+        S: BuildHasher,
+    {
+        self.writer.flush()?;
+        self.writer = compact(&self.path, handler)?;
+        self.unsynced = 0;
+
+        Ok(())
+    }
+}
+
+/// Time since the unix epoch at the wall-clock time `wall`, as journaled deadlines are stored.
+fn unix_time(wall: SystemTime) -> Duration {
+    wall.duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+/// Reads the journal at `path`, returning the outstanding timeouts with their deadlines, in order
+/// of expiry. Only the last record may be torn, as would happen on crashing mid-write.
+fn replay<T>(path: &Path) -> io::Result<Vec<(T, Duration)>>
+where
+    T: Eq + Hash + DeserializeOwned,
+{
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+
+    let mut scheduled = HashMap::new();
+    let mut lines = BufReader::new(file).lines().peekable();
+    while let Some(line) = lines.next() {
+        let record = match serde_json::from_str(&line?) {
+            Ok(record) => record,
+            Err(_) if lines.peek().is_none() => break,
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+
+        match record {
+            Record::Schedule(item, deadline) => {
+                scheduled.insert(item, deadline);
+            }
+            Record::Remove(item) | Record::Expire(item) => {
+                scheduled.remove(&item);
+            }
+        }
+    }
+
+    let mut scheduled: Vec<_> = scheduled.into_iter().collect();
+    scheduled.sort_by_key(|&(_, deadline)| deadline);
+
+    Ok(scheduled)
+}
+
+/// Atomically replaces the journal at `path` with one scheduling only the outstanding timeouts of
+/// `handler`, returning it open for appending.
+fn compact<T, S>(path: &Path, handler: &DelayHandler<T, S>) -> io::Result<BufWriter<File>>
+where
+    T: Eq + Hash + Serialize,
+    S: BuildHasher,
+{
+    let mut tmp = OsString::from(path);
+    tmp.push(".tmp");
+
+    let mut journal = BufWriter::new(File::create(&tmp)?);
+    let now = unix_time(handler.map.system_now());
+    for (item, _, remaining) in handler.map.pending() {
+        serde_json::to_writer(
+            &mut journal,
+            &Record::Schedule(item, now.saturating_add(remaining)),
+        )?;
+        journal.write_all(b"\n")?;
+    }
+    journal.into_inner()?.sync_all()?;
+    fs::rename(&tmp, path)?;
+
+    let file = OpenOptions::new().append(true).open(path)?;
+
+    Ok(BufWriter::new(file))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::{self, Write};
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use super::{unix_time, Fsync, PersistentDelayHandler, Record};
+    use crate::{Backend, Clock, DelayHandler, MockClock, Overflow};
+
+    /// Path of a journal unique to the test, removed as it is dropped.
+    struct TempJournal(PathBuf);
+
+    impl TempJournal {
+        fn new(name: &str) -> Self {
+            let file = format!("delay-handler-{}-{name}.journal", std::process::id());
+            let path = std::env::temp_dir().join(file);
+            let _ = fs::remove_file(&path);
+
+            Self(path)
+        }
+
+        fn build(&self, clock: &MockClock) -> io::Result<DelayHandler<String>> {
+            DelayHandler::builder()
+                .clock(clock.clone())
+                .build_journaled(&self.0, Fsync::Always)
+        }
+
+        fn open(&self, clock: &MockClock) -> io::Result<PersistentDelayHandler<String>> {
+            DelayHandler::builder()
+                .clock(clock.clone())
+                .build_persistent(&self.0, Fsync::Never)
+        }
+
+        fn write(&self, records: &[Record<&str>], tail: &str) {
+            let mut file = fs::File::create(&self.0).unwrap();
+            for record in records {
+                serde_json::to_writer(&mut file, record).unwrap();
+                file.write_all(b"\n").unwrap();
+            }
+            file.write_all(tail.as_bytes()).unwrap();
+        }
+    }
+
+    impl Drop for TempJournal {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn replays_outstanding_timeouts() {
+        let journal = TempJournal::new("replay");
+        let clock = MockClock::new();
+        let mut handler = journal.build(&clock).unwrap();
+        handler.insert("a".to_owned(), Duration::from_secs(10));
+        handler.insert("b".to_owned(), Duration::from_secs(20));
+        handler.insert("c".to_owned(), Duration::from_secs(30));
+        handler.insert("d".to_owned(), Duration::from_secs(60));
+        handler.remove("b");
+        handler.reset("c", Duration::from_secs(50));
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(handler.try_next().as_deref(), Some("a"));
+        handler.sync_journal().unwrap();
+        drop(handler);
+
+        // The time spent down is counted against the timeouts
+        clock.advance(Duration::from_secs(15));
+        let handler = journal.build(&clock).unwrap();
+        assert_eq!(handler.len(), 2);
+        assert!(!handler.contains("a") && !handler.contains("b"));
+        assert_eq!(handler.time_remaining("c"), Some(Duration::from_secs(25)));
+        assert_eq!(handler.time_remaining("d"), Some(Duration::from_secs(35)));
+    }
+
+    #[test]
+    fn recovers_with_the_configuration() {
+        let journal = TempJournal::new("recover");
+        let clock = MockClock::new();
+        let mut handler = journal.build(&clock).unwrap();
+        handler.insert("a".to_owned(), Duration::from_secs(10));
+        handler.insert("b".to_owned(), Duration::from_secs(20));
+        drop(handler);
+
+        clock.advance(Duration::from_secs(5));
+        let mut handler: DelayHandler<String> = DelayHandler::builder()
+            .clock(clock.clone())
+            .backend(Backend::TimingWheel)
+            .max_entries(1, Overflow::Reject)
+            .recover(&journal.0)
+            .unwrap();
+        assert_eq!(handler.len(), 1);
+        assert_eq!(handler.time_remaining("a"), Some(Duration::from_secs(5)));
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(handler.try_next().as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn reopens_persistent_handlers() {
+        let journal = TempJournal::new("persistent");
+        let clock = MockClock::new();
+        let mut handler = journal.open(&clock).unwrap();
+        assert!(handler
+            .insert("a".to_owned(), Duration::from_secs(10))
+            .unwrap());
+        assert!(!handler
+            .insert("a".to_owned(), Duration::from_secs(10))
+            .unwrap());
+        handler
+            .insert("b".to_owned(), Duration::from_secs(20))
+            .unwrap();
+        handler
+            .insert("c".to_owned(), Duration::from_secs(30))
+            .unwrap();
+        handler.reset("b", Duration::from_secs(40)).unwrap();
+        handler.remove("c").unwrap();
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(handler.try_next().unwrap().unwrap(), "a");
+        // Records are flushed as they are written, so that dropping is as good as crashing
+        drop(handler);
+
+        clock.advance(Duration::from_secs(10));
+        let mut handler = journal.open(&clock).unwrap();
+        assert_eq!(handler.len(), 1);
+        assert!(handler.contains("b") && !handler.contains("c"));
+        clock.advance(Duration::from_secs(19));
+        assert!(handler.try_next().is_none());
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(handler.try_next().unwrap().unwrap(), "b");
+        drop(handler);
+
+        assert!(journal.open(&clock).unwrap().is_empty());
+    }
+
+    #[test]
+    fn tolerates_only_a_torn_last_record() {
+        let journal = TempJournal::new("torn");
+        let clock = MockClock::new();
+        let deadline = unix_time(clock.system_now()) + Duration::from_secs(60);
+        let records = [
+            Record::Schedule("a", deadline),
+            Record::Schedule("b", deadline),
+            Record::Expire("a"),
+        ];
+        journal.write(&records, r#"{"Remove":["b"#);
+
+        let handler = journal.build(&clock).unwrap();
+        assert_eq!(handler.len(), 1);
+        assert!(handler.contains("b"));
+        drop(handler);
+
+        // A torn record followed by others wasn't the last write, so the journal is corrupt
+        journal.write(&records, "");
+        let mut contents = fs::read_to_string(&journal.0).unwrap();
+        contents.insert_str(0, "{\n");
+        fs::write(&journal.0, contents).unwrap();
+        let e = journal.build(&clock).map(drop).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn compacts_to_the_outstanding_timeouts() {
+        let journal = TempJournal::new("compact");
+        let clock = MockClock::new();
+        let mut handler = journal.build(&clock).unwrap();
+        for i in 0..10 {
+            handler.insert(i.to_string(), Duration::from_secs(i + 1));
+            handler.reset(&i.to_string(), Duration::from_secs(60));
+        }
+        for i in 0..5 {
+            handler.remove(&i.to_string());
+        }
+        let lines = |journal: &TempJournal| fs::read_to_string(&journal.0).unwrap().lines().count();
+        assert_eq!(lines(&journal), 25);
+
+        handler.compact_journal().unwrap();
+        assert_eq!(lines(&journal), 5);
+        handler.insert("a".to_owned(), Duration::from_secs(1));
+        assert_eq!(lines(&journal), 6);
+    }
+}
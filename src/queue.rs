@@ -0,0 +1,280 @@
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "tokio")]
+use tokio_util::time::{delay_queue, DelayQueue};
+
+#[cfg(not(feature = "tokio"))]
+use crate::clock::fallback;
+use crate::clock::Clock;
+#[cfg(feature = "tokio")]
+use crate::clock::TokioClock;
+use crate::timer::{Timer, TimerKey};
+use crate::wheel::{Wheel, WheelKey};
+use crate::Backend;
+
+/// Length of a tick of the timing wheel if no resolution is configured, matching the resolution
+/// of the [`DelayQueue`].
+const TICK: Duration = Duration::from_millis(1);
+
+/// Furthest ahead that deadlines are scheduled on the [`DelayQueue`], which panics beyond about two
+/// years. Values with later deadlines are yielded early, once this far out, to be re-armed.
+#[cfg(feature = "tokio")]
+const MAX_DELAY: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// The queue that a delay-map schedules its timeouts on, rounding their deadlines up to the
+/// configured resolution, if any.
+pub(crate) struct Queue<T> {
+    inner: Inner<T>,
+    /// Deadlines are rounded up to multiples of the resolution, counting from the origin.
+    resolution: Option<(Instant, Duration)>,
+}
+
+/// A [`DelayQueue`] unless configured with a custom [`Clock`], which only the [`Timer`] can follow.
+/// Without tokio, it is always a [`Timer`], following the default clock of the enabled backend.
+enum Inner<T> {
+    #[cfg(feature = "tokio")]
+    Tokio(DelayQueue<T>),
+    Clocked(Timer<T>),
+    Wheel(Box<Wheel<T>>),
+}
+
+/// Refers to a value in the [`Queue`] it was inserted into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum QueueKey {
+    #[cfg(feature = "tokio")]
+    Tokio(delay_queue::Key),
+    Clocked(TimerKey),
+    Wheel(WheelKey),
+}
+
+/// Clock that the timing wheel follows, when not configured with one.
+fn default_clock() -> Arc<dyn Clock> {
+    #[cfg(feature = "tokio")]
+    return Arc::new(TokioClock);
+    #[cfg(not(feature = "tokio"))]
+    return fallback();
+}
+
+impl<T> Queue<T> {
+    pub(crate) fn new(
+        backend: Backend,
+        clock: Option<Arc<dyn Clock>>,
+        resolution: Option<Duration>,
+        capacity: usize,
+    ) -> Self {
+        let resolution = resolution.filter(|resolution| !resolution.is_zero());
+        let inner = match (backend, clock) {
+            (Backend::TimingWheel, clock) => {
+                let clock = clock.unwrap_or_else(default_clock);
+                let tick = resolution.unwrap_or(TICK);
+                Inner::Wheel(Box::new(Wheel::with_capacity(clock, tick, capacity)))
+            }
+            (Backend::Queue, Some(clock)) => Inner::Clocked(Timer::with_capacity(clock, capacity)),
+            #[cfg(feature = "tokio")]
+            (Backend::Queue, _) => Inner::Tokio(DelayQueue::with_capacity(capacity)),
+            #[cfg(not(feature = "tokio"))]
+            (Backend::Queue, _) => Inner::Clocked(Timer::with_capacity(fallback(), capacity)),
+        };
+
+        // Rounding from where the wheel counts its ticks, so that deadlines fall on their boundaries
+        let origin = match &inner {
+            Inner::Wheel(wheel) => wheel.start(),
+            _ => inner.clock().now(),
+        };
+
+        Self {
+            inner,
+            resolution: resolution.map(|resolution| (origin, resolution)),
+        }
+    }
+
+    pub(crate) fn clock(&self) -> &dyn Clock {
+        self.inner.clock()
+    }
+
+    /// Rounds `deadline` up to the next multiple of the resolution.
+    fn round(&self, deadline: Instant) -> Instant {
+        let (origin, resolution) = match self.resolution {
+            Some(resolution) => resolution,
+            _ => return deadline,
+        };
+
+        let offset = deadline.saturating_duration_since(origin).as_nanos();
+        let resolution = resolution.as_nanos();
+        let rounded = offset.div_ceil(resolution) * resolution;
+
+        u64::try_from(rounded)
+            .ok()
+            .and_then(|rounded| origin.checked_add(Duration::from_nanos(rounded)))
+            .unwrap_or(deadline)
+            .max(deadline)
+    }
+
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        match &mut self.inner {
+            #[cfg(feature = "tokio")]
+            Inner::Tokio(queue) => queue.reserve(additional),
+            Inner::Clocked(timer) => timer.reserve(additional),
+            Inner::Wheel(wheel) => wheel.reserve(additional),
+        }
+    }
+
+    pub(crate) fn compact(&mut self) {
+        match &mut self.inner {
+            #[cfg(feature = "tokio")]
+            Inner::Tokio(queue) => queue.compact(),
+            Inner::Clocked(timer) => timer.compact(),
+            Inner::Wheel(wheel) => wheel.compact(),
+        }
+    }
+
+    /// # Panics
+    /// If the queue is a [`DelayQueue`] and called outside the context of a tokio runtime.
+    pub(crate) fn insert_at(&mut self, value: T, deadline: Instant) -> QueueKey {
+        let deadline = self.round(deadline);
+        match &mut self.inner {
+            #[cfg(feature = "tokio")]
+            Inner::Tokio(queue) => {
+                let deadline = deadline.min(TokioClock.now() + MAX_DELAY);
+                QueueKey::Tokio(queue.insert_at(value, deadline.into()))
+            }
+            Inner::Clocked(timer) => QueueKey::Clocked(timer.insert_at(value, deadline)),
+            Inner::Wheel(wheel) => QueueKey::Wheel(wheel.insert_at(value, deadline)),
+        }
+    }
+
+    pub(crate) fn reset_at(&mut self, key: &QueueKey, deadline: Instant) {
+        let deadline = self.round(deadline);
+        match (&mut self.inner, key) {
+            #[cfg(feature = "tokio")]
+            (Inner::Tokio(queue), QueueKey::Tokio(key)) => {
+                let deadline = deadline.min(TokioClock.now() + MAX_DELAY);
+                queue.reset_at(key, deadline.into())
+            }
+            (Inner::Clocked(timer), QueueKey::Clocked(key)) => timer.reset_at(key, deadline),
+            (Inner::Wheel(wheel), QueueKey::Wheel(key)) => wheel.reset_at(key, deadline),
+            _ => unreachable!("key from another queue"),
+        }
+    }
+
+    pub(crate) fn remove(&mut self, key: &QueueKey) -> T {
+        match (&mut self.inner, key) {
+            #[cfg(feature = "tokio")]
+            (Inner::Tokio(queue), QueueKey::Tokio(key)) => queue.remove(key).into_inner(),
+            (Inner::Clocked(timer), QueueKey::Clocked(key)) => timer.remove(key),
+            (Inner::Wheel(wheel), QueueKey::Wheel(key)) => wheel.remove(key),
+            _ => unreachable!("key from another queue"),
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        match &mut self.inner {
+            #[cfg(feature = "tokio")]
+            Inner::Tokio(queue) => queue.clear(),
+            Inner::Clocked(timer) => timer.clear(),
+            Inner::Wheel(wheel) => wheel.clear(),
+        }
+    }
+
+    /// Check if a value with `deadline` was yielded before it was reached, as its deadline was
+    /// further out than the queue schedules, rather than just within a tick of it.
+    pub(crate) fn is_early(&self, deadline: Instant) -> bool {
+        deadline > self.clock().now() + TICK
+    }
+
+    pub(crate) fn poll_expired(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        match &mut self.inner {
+            #[cfg(feature = "tokio")]
+            Inner::Tokio(queue) => queue
+                .poll_expired(cx)
+                .map(|expired| expired.map(|expired| expired.into_inner())),
+            Inner::Clocked(timer) => timer.poll_expired(cx),
+            Inner::Wheel(wheel) => wheel.poll_expired(cx),
+        }
+    }
+}
+
+impl<T> Inner<T> {
+    fn clock(&self) -> &dyn Clock {
+        match self {
+            #[cfg(feature = "tokio")]
+            Inner::Tokio(_) => &TokioClock,
+            Inner::Clocked(timer) => timer.clock(),
+            Inner::Wheel(wheel) => wheel.clock(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::{poll_fn, Future};
+    use std::sync::Arc;
+    use std::task::Poll;
+    use std::time::Duration;
+
+    use super::{Queue, TICK};
+    use crate::{Backend, MockClock};
+
+    /// Inserts values with scrambled delays, then steps time forward a tick at a time, asserting
+    /// that each value is yielded within a tick of its deadline and that they are all yielded in
+    /// order of their deadlines.
+    async fn assert_expires_in_order<A, F>(mut queue: Queue<u64>, mut advance: A)
+    where
+        A: FnMut(Duration) -> F,
+        F: Future<Output = ()>,
+    {
+        let start = queue.clock().now();
+        let delays: Vec<u64> = (0..1000).map(|i| i * 2_919 % 3_000 + 1).collect();
+        for (value, &delay) in delays.iter().enumerate() {
+            queue.insert_at(value as u64, start + Duration::from_millis(delay));
+        }
+
+        let mut expired = vec![];
+        for _ in 0..=3_000 {
+            advance(TICK).await;
+            let now = queue.clock().now();
+            while let Poll::Ready(Some(value)) =
+                poll_fn(|cx| Poll::Ready(queue.poll_expired(cx))).await
+            {
+                let deadline = start + Duration::from_millis(delays[value as usize]);
+                assert!(deadline <= now, "{value} expired early");
+                assert!(now - deadline <= TICK, "{value} expired late");
+                expired.push(value);
+            }
+        }
+
+        let mut sorted = expired.clone();
+        sorted.sort_by_key(|&value| delays[value as usize]);
+        assert_eq!(expired, sorted);
+        assert_eq!(expired.len(), delays.len());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(start_paused = true)]
+    async fn delay_queue_expires_in_order() {
+        let queue = Queue::new(Backend::Queue, None, None, 0);
+        assert_expires_in_order(queue, tokio::time::advance).await;
+    }
+
+    #[test]
+    fn timer_expires_in_order() {
+        let clock = MockClock::new();
+        let queue = Queue::new(Backend::Queue, Some(Arc::new(clock.clone())), None, 0);
+        futures::executor::block_on(assert_expires_in_order(queue, |duration| {
+            clock.advance(duration);
+            async {}
+        }));
+    }
+
+    #[test]
+    fn wheel_expires_in_order() {
+        let clock = MockClock::new();
+        let queue = Queue::new(Backend::TimingWheel, Some(Arc::new(clock.clone())), None, 0);
+        futures::executor::block_on(assert_expires_in_order(queue, |duration| {
+            clock.advance(duration);
+            async {}
+        }));
+    }
+}
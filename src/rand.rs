@@ -0,0 +1,30 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::time::Duration;
+
+/// A small xorshift generator, good enough to spread out deadlines without pulling in a dependency.
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new() -> Self {
+        // Seeded from the randomized keys std uses for hashing, `| 1` as the state mustn't be zero
+        Self(RandomState::new().hash_one(0u64) | 1)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+
+        self.0
+    }
+
+    /// Returns a duration picked uniformly between zero and `max`, inclusive.
+    pub(crate) fn duration(&mut self, max: Duration) -> Duration {
+        let max = max.as_nanos().min(u64::MAX as u128) as u64;
+        match max.checked_add(1) {
+            Some(range) => Duration::from_nanos(self.next_u64() % range),
+            None => Duration::from_nanos(self.next_u64()),
+        }
+    }
+}
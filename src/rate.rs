@@ -0,0 +1,205 @@
+use std::future::Future;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use crate::clock::{saturating_add, Sleep};
+use crate::DelayMap;
+
+/// Limits how often each key can act, allowing bursts of upto a number of acquires that are then
+/// refilled at a steady rate, as with a token bucket.
+///
+/// Every key is tracked by the instant its bucket will be full again, as the deadline of a timeout,
+/// so that keys which have been idle long enough to refill are forgotten, without a background task.
+///
+/// ### Examples
+/// Allow each user a burst of 10 requests, refilling 1 every 100ms
+/// ```
+/// # use delay_handler::RateLimiter;
+/// # use std::time::Duration;
+/// # #[tokio::main(flavor = "current_thread", start_paused = true)]
+/// # async fn main() {
+/// let mut limiter = RateLimiter::new(10, Duration::from_millis(100));
+/// for _ in 0..20 {
+///     // Resolves immediately for the first 10, after which each waits ~100ms
+///     limiter.acquire("alice").await;
+/// }
+///
+/// // Other keys aren't affected, while "alice" has to wait.
+/// assert!(limiter.try_acquire("bob").is_ok());
+/// assert!(limiter.try_acquire("alice").is_err());
+/// # }
+/// ```
+pub struct RateLimiter<K> {
+    map: DelayMap<K, ()>,
+    burst: u32,
+    interval: Duration,
+}
+
+/// A future that resolves once an acquire from a [`RateLimiter`] is allowed to proceed, returned
+/// by [`acquire()`](RateLimiter::acquire).
+pub struct Acquire {
+    sleep: Option<Sleep>,
+}
+
+impl<K> RateLimiter<K>
+where
+    K: Eq + Hash,
+{
+    /// Creates a rate limiter that allows each key bursts of upto `burst` acquires, refilling one
+    /// every `interval`. A burst of zero is treated as one.
+    pub fn new(burst: u32, interval: Duration) -> Self {
+        Self {
+            map: DelayMap::default(),
+            burst: burst.max(1),
+            interval,
+        }
+    }
+
+    /// Acquire a permit for `key`, returning a future that resolves once it is allowed to proceed.
+    /// The permit is reserved on calling, so that acquires proceed in the order they were made,
+    /// dropping the future without awaiting it doesn't give it back.
+    pub fn acquire(&mut self, key: K) -> Acquire {
+        let (now, full, allowed) = self.state(&key);
+        self.consume(key, now, full);
+
+        Acquire {
+            sleep: (allowed > now).then(|| self.map.clock().sleep_until(allowed)),
+        }
+    }
+
+    /// Acquire a permit for `key` if it is allowed to proceed right away, otherwise returns how
+    /// long until it will be, without reserving one.
+    pub fn try_acquire(&mut self, key: K) -> Result<(), Duration> {
+        let (now, full, allowed) = self.state(&key);
+        if allowed > now {
+            return Err(allowed - now);
+        }
+        self.consume(key, now, full);
+
+        Ok(())
+    }
+
+    /// Returns the current instant, the instant at which the bucket of `key` will be full and the
+    /// instant at which it next holds a permit, forgetting keys whose bucket is full already.
+    fn state(&mut self, key: &K) -> (Instant, Instant, Instant) {
+        self.map.drain_expired();
+        let now = self.map.now();
+        let full = self.map.deadline(key).map_or(now, |full| full.max(now));
+        let tolerance = self.interval.saturating_mul(self.burst - 1);
+        let allowed = full
+            .checked_sub(tolerance)
+            .map_or(now, |allowed| allowed.max(now));
+
+        (now, full, allowed)
+    }
+
+    /// Takes a permit from the bucket of `key`, pushing back the instant it will be full by an interval.
+    fn consume(&mut self, key: K, now: Instant, full: Instant) {
+        let full = saturating_add(full, self.interval);
+        if !self.map.reset(&key, full - now) {
+            self.map.insert_at(key, (), full);
+        }
+    }
+
+    /// Forget all keys, so that they can burst again.
+    pub fn clear(&mut self) {
+        self.map.clear()
+    }
+
+    /// Check if no keys are being limited.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Number of keys being limited, whose bucket isn't full.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+}
+
+impl Future for Acquire {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        match &mut self.sleep {
+            Some(sleep) => sleep.as_mut().poll(cx),
+            _ => Poll::Ready(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::pin;
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+
+    use futures::task::noop_waker_ref;
+
+    use super::RateLimiter;
+    use crate::{DelayMap, MockClock};
+
+    fn limiter(clock: &MockClock) -> RateLimiter<&'static str> {
+        let mut limiter = RateLimiter::new(3, Duration::from_millis(100));
+        limiter.map = DelayMap::builder().clock(clock.clone()).build_map();
+
+        limiter
+    }
+
+    #[test]
+    fn refills_bursts_at_a_steady_rate() {
+        let clock = MockClock::new();
+        let mut limiter = limiter(&clock);
+        for _ in 0..3 {
+            assert_eq!(limiter.try_acquire("a"), Ok(()));
+        }
+        assert_eq!(limiter.try_acquire("a"), Err(Duration::from_millis(100)));
+        assert_eq!(limiter.try_acquire("b"), Ok(()));
+
+        clock.advance(Duration::from_millis(60));
+        assert_eq!(limiter.try_acquire("a"), Err(Duration::from_millis(40)));
+        clock.advance(Duration::from_millis(40));
+        assert_eq!(limiter.try_acquire("a"), Ok(()));
+        assert!(limiter.try_acquire("a").is_err());
+    }
+
+    #[test]
+    fn acquires_wait_for_a_permit() {
+        let clock = MockClock::new();
+        let mut limiter = limiter(&clock);
+        let mut cx = Context::from_waker(noop_waker_ref());
+        for _ in 0..3 {
+            assert!(pin!(limiter.acquire("a")).poll(&mut cx).is_ready());
+        }
+
+        // Reserved on calling, so that the next acquire waits for another interval
+        let mut first = pin!(limiter.acquire("a"));
+        let mut second = pin!(limiter.acquire("a"));
+        clock.advance(Duration::from_millis(99));
+        assert_eq!(first.as_mut().poll(&mut cx), Poll::Pending);
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(first.as_mut().poll(&mut cx), Poll::Ready(()));
+        assert_eq!(second.as_mut().poll(&mut cx), Poll::Pending);
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(second.as_mut().poll(&mut cx), Poll::Ready(()));
+    }
+
+    #[test]
+    fn forgets_keys_once_refilled() {
+        let clock = MockClock::new();
+        let mut limiter = limiter(&clock);
+        limiter.try_acquire("a").unwrap();
+        limiter.try_acquire("a").unwrap();
+        assert_eq!(limiter.len(), 1);
+
+        clock.advance(Duration::from_millis(200));
+        limiter.try_acquire("b").unwrap();
+        assert_eq!(limiter.len(), 1);
+        for _ in 0..3 {
+            assert_eq!(limiter.try_acquire("a"), Ok(()));
+        }
+    }
+}
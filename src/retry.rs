@@ -0,0 +1,300 @@
+use std::future::poll_fn;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_core::{FusedStream, Stream};
+#[cfg(feature = "channels")]
+use tokio::sync::mpsc;
+
+use crate::rand::Rng;
+use crate::{Backoff, DelayHandle, HandleQueue};
+
+/// How long a [`RetryQueue`] waits before each attempt.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RetryPolicy {
+    /// Wait the same delay before every attempt.
+    Fixed(Duration),
+    /// Wait a delay that grows after every attempt, as per the backoff.
+    Exponential(Backoff),
+    /// Wait a delay picked at random between zero and that of the backoff, so that items which
+    /// failed together don't all retry together.
+    JitteredExponential(Backoff),
+}
+
+impl RetryPolicy {
+    /// Returns the longest delay before the given `attempt`, counting from 1. Jittered delays are
+    /// at most this long.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        match self {
+            RetryPolicy::Fixed(delay) => *delay,
+            RetryPolicy::Exponential(backoff) | RetryPolicy::JitteredExponential(backoff) => {
+                backoff.delay(attempt)
+            }
+        }
+    }
+
+    /// Returns true if there should be another attempt, after the given `attempt`. Only backoff
+    /// policies with [`max_attempts()`](Backoff::max_attempts) ever run out of attempts.
+    pub fn should_retry(&self, attempt: u32) -> bool {
+        match self {
+            RetryPolicy::Fixed(_) => true,
+            RetryPolicy::Exponential(backoff) | RetryPolicy::JitteredExponential(backoff) => {
+                backoff.should_retry(attempt)
+            }
+        }
+    }
+}
+
+/// Schedules retries of items that have failed, waiting longer before each attempt as per a
+/// [`RetryPolicy`], until they run out of attempts.
+///
+/// Items are yielded along with their attempt count, counting from 1, once they are due for a
+/// retry. Should the attempt fail again, hand the item back with [`retry()`](RetryQueue::retry) to
+/// schedule the next one, or give up on it, if it has run out of attempts. Items that are given up
+/// on are sent to the channel returned by [`gave_up()`](RetryQueue::gave_up), if any.
+///
+/// ### Examples
+/// Retry sending messages upto 5 times, starting with a delay of 1s that doubles after every attempt
+/// ```no_run
+/// # use delay_handler::{Backoff, RetryPolicy, RetryQueue};
+/// # use std::time::Duration;
+/// # fn send(message: &str) -> Result<(), ()> { Ok(()) }
+/// # async fn run() {
+/// let policy = RetryPolicy::JitteredExponential(Backoff::new(Duration::from_secs(1)));
+/// let mut retries = RetryQueue::new(policy).max_attempts(5);
+/// # #[cfg(feature = "channels")]
+/// let mut gave_up = retries.gave_up();
+///
+/// if send("hello").is_err() {
+///     retries.insert("hello");
+/// }
+///
+/// while let Some((message, attempt)) = retries.next().await {
+///     if send(message).is_err() {
+///         retries.retry(message, attempt);
+///     }
+/// }
+///
+/// # #[cfg(feature = "channels")]
+/// while let Ok((message, attempts)) = gave_up.try_recv() {
+///     println!("gave up on {message} after {attempts} attempts");
+/// }
+/// # }
+/// ```
+pub struct RetryQueue<T> {
+    queue: HandleQueue<(T, u32)>,
+    policy: RetryPolicy,
+    max_attempts: Option<u32>,
+    #[cfg(feature = "channels")]
+    gave_up: Option<mpsc::UnboundedSender<(T, u32)>>,
+    rng: Rng,
+}
+
+impl<T> RetryQueue<T> {
+    /// Creates an empty queue, that retries items as per the `policy` without limiting the number
+    /// of attempts.
+    pub fn new(policy: RetryPolicy) -> Self {
+        Self {
+            queue: HandleQueue::default(),
+            policy,
+            max_attempts: None,
+            #[cfg(feature = "channels")]
+            gave_up: None,
+            rng: Rng::new(),
+        }
+    }
+
+    /// Sets the number of attempts after which items are given up on, in addition to any limit
+    /// of the policy's backoff.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+
+    /// Returns the policy that retries are scheduled with.
+    pub fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+
+    /// Returns a channel that receives every item that is given up on, along with the number of
+    /// attempts it was retried for. Replaces any channel returned previously, while no channel is
+    /// open, items that are given up on are dropped.
+    #[cfg(feature = "channels")]
+    pub fn gave_up(&mut self) -> mpsc::UnboundedReceiver<(T, u32)> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.gave_up = Some(sender);
+
+        receiver
+    }
+
+    /// Schedule the first retry of an item that has failed, returning a handle that refers to it.
+    pub fn insert(&mut self, item: T) -> DelayHandle {
+        self.schedule(item, 1)
+    }
+
+    /// Schedule the next retry of an item whose `attempt` has failed, as it was yielded along with.
+    /// Returns `None` if the item has run out of attempts and is given up on instead.
+    pub fn retry(&mut self, item: T, attempt: u32) -> Option<DelayHandle> {
+        let exhausted = self.max_attempts.is_some_and(|max| attempt >= max);
+        if exhausted || !self.policy.should_retry(attempt) {
+            #[cfg(feature = "channels")]
+            if let Some(gave_up) = &self.gave_up {
+                let _ = gave_up.send((item, attempt));
+            }
+            return None;
+        }
+
+        Some(self.schedule(item, attempt + 1))
+    }
+
+    fn schedule(&mut self, item: T, attempt: u32) -> DelayHandle {
+        let delay = match self.policy {
+            RetryPolicy::JitteredExponential(backoff) => self.rng.duration(backoff.delay(attempt)),
+            policy => policy.delay(attempt),
+        };
+
+        self.queue.insert((item, attempt), delay)
+    }
+
+    /// Cancels the pending retry referred to by `handle`, returning the item if it wasn't already due.
+    pub fn remove(&mut self, handle: &DelayHandle) -> Option<T> {
+        let (item, _) = self.queue.remove(handle)?;
+
+        Some(item)
+    }
+
+    /// Remove an item once it is due for a retry and return it, along with its attempt count.
+    pub async fn next(&mut self) -> Option<(T, u32)> {
+        poll_fn(|cx| self.poll_expired(cx)).await
+    }
+
+    /// Polls for an item that is due for a retry, registering the current task for wakeup if none
+    /// is yet.
+    pub fn poll_expired(&mut self, cx: &mut Context<'_>) -> Poll<Option<(T, u32)>> {
+        self.queue.poll_expired(cx)
+    }
+
+    /// Remove an item if it is already due for a retry and return it, along with its attempt
+    /// count, without waiting.
+    pub fn try_next(&mut self) -> Option<(T, u32)> {
+        self.queue.try_next()
+    }
+
+    /// Cancel all pending retries, dropping their items.
+    pub fn clear(&mut self) {
+        self.queue.clear()
+    }
+
+    /// Check if no retries are pending.
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Check if the retry referred to by `handle` is still pending.
+    pub fn contains(&self, handle: &DelayHandle) -> bool {
+        self.queue.contains(handle)
+    }
+
+    /// Number of retries currently pending.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+}
+
+impl<T> Stream for RetryQueue<T> {
+    type Item = (T, u32);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_expired(cx)
+    }
+}
+
+impl<T> FusedStream for RetryQueue<T> {
+    fn is_terminated(&self) -> bool {
+        self.queue.is_terminated()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{RetryPolicy, RetryQueue};
+    use crate::{Backoff, DelayHandlerBuilder, HandleQueue, MockClock};
+
+    fn retries(clock: &MockClock, policy: RetryPolicy) -> RetryQueue<&'static str> {
+        let mut retries = RetryQueue::new(policy);
+        retries.queue = HandleQueue::from_builder(DelayHandlerBuilder::new().clock(clock.clone()));
+
+        retries
+    }
+
+    #[test]
+    fn waits_longer_before_each_attempt() {
+        let clock = MockClock::new();
+        let backoff = Backoff::new(Duration::from_secs(1));
+        let mut retries = retries(&clock, RetryPolicy::Exponential(backoff));
+        retries.insert("job");
+
+        for (attempt, delay) in [(1, 1), (2, 2), (3, 4), (4, 8)] {
+            clock.advance(Duration::from_secs(delay) - Duration::from_millis(1));
+            assert_eq!(retries.try_next(), None, "attempt {attempt} was early");
+            clock.advance(Duration::from_millis(1));
+            assert_eq!(retries.try_next(), Some(("job", attempt)));
+            retries.retry("job", attempt);
+        }
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let clock = MockClock::new();
+        let mut retries =
+            retries(&clock, RetryPolicy::Fixed(Duration::from_secs(1))).max_attempts(2);
+        #[cfg(feature = "channels")]
+        let mut gave_up = retries.gave_up();
+        retries.insert("job");
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(retries.try_next(), Some(("job", 1)));
+        assert!(retries.retry("job", 1).is_some());
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(retries.try_next(), Some(("job", 2)));
+        assert!(retries.retry("job", 2).is_none());
+        assert!(retries.is_empty());
+        #[cfg(feature = "channels")]
+        assert_eq!(gave_up.try_recv(), Ok(("job", 2)));
+    }
+
+    #[test]
+    fn jitters_delays_within_the_backoff() {
+        let clock = MockClock::new();
+        let backoff = Backoff::new(Duration::from_secs(10));
+        let mut retries = retries(&clock, RetryPolicy::JitteredExponential(backoff));
+        for _ in 0..100 {
+            retries.insert("job");
+        }
+
+        // Spread across the whole delay, rather than all being due at its end
+        clock.advance(Duration::from_secs(5));
+        let early = std::iter::from_fn(|| retries.try_next()).count();
+        assert!(early > 0 && early < 100, "{early} of 100 were due halfway");
+        clock.advance(Duration::from_secs(5));
+        let late = std::iter::from_fn(|| retries.try_next()).count();
+        assert_eq!(early + late, 100);
+    }
+
+    #[test]
+    fn removes_pending_retries() {
+        let clock = MockClock::new();
+        let mut retries = retries(&clock, RetryPolicy::Fixed(Duration::from_secs(1)));
+        let handle = retries.insert("a");
+        retries.insert("b");
+
+        assert_eq!(retries.remove(&handle), Some("a"));
+        assert_eq!(retries.remove(&handle), None);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(retries.try_next(), Some(("b", 1)));
+        assert_eq!(retries.try_next(), None);
+    }
+}
@@ -0,0 +1,128 @@
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+
+use serde::de::{Deserialize, Deserializer, SeqAccess, Visitor};
+use serde::ser::{Serialize, Serializer};
+
+use crate::{DelayHandler, DelayMap};
+
+/// Serializes every outstanding timeout as a sequence of `(key, value, remaining)`, timeouts that
+/// have already expired are stored with no time remaining.
+/// Frozen timeouts are stored with the time they had remaining, but are restored unfrozen.
+impl<K, V, H> Serialize for DelayMap<K, V, H>
+where
+    K: Serialize + Eq + Hash,
+    V: Serialize,
+    H: BuildHasher,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.pending())
+    }
+}
+
+/// Restores timeouts from a sequence of `(key, value, remaining)`, to timeout after `remaining` from
+/// now, skipping keys that are repeated.
+impl<'de, K, V> Deserialize<'de> for DelayMap<K, V>
+where
+    K: Deserialize<'de> + Eq + Hash,
+    V: Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(Timeouts(PhantomData))
+    }
+}
+
+/// Serializes every outstanding timeout as a sequence of `(item, remaining)`, timeouts that have
+/// already expired are stored with no time remaining.
+/// Frozen timeouts are stored with the time they had remaining, but are restored unfrozen.
+impl<T, H> Serialize for DelayHandler<T, H>
+where
+    T: Serialize + Eq + Hash,
+    H: BuildHasher,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(
+            self.map
+                .pending()
+                .map(|(item, _, remaining)| (item, remaining)),
+        )
+    }
+}
+
+/// Restores timeouts from a sequence of `(item, remaining)`, to timeout after `remaining` from now,
+/// skipping items that are repeated.
+impl<'de, T> Deserialize<'de> for DelayHandler<T>
+where
+    T: Deserialize<'de> + Eq + Hash,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(Timeouts(PhantomData))
+    }
+}
+
+/// Collects a sequence of timeouts into any container of them, as they are read.
+struct Timeouts<C, E>(PhantomData<(C, E)>);
+
+impl<'de, C, E> Visitor<'de> for Timeouts<C, E>
+where
+    C: Default + Extend<E>,
+    E: Deserialize<'de>,
+{
+    type Value = C;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a sequence of timeouts with their remaining durations")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<C, A::Error> {
+        let mut timeouts = C::default();
+        while let Some(timeout) = seq.next_element()? {
+            timeouts.extend(Some(timeout));
+        }
+
+        Ok(timeouts)
+    }
+}
+
+#[cfg(all(test, feature = "tokio"))]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{DelayHandler, DelayMap};
+
+    #[tokio::test(start_paused = true)]
+    async fn round_trips_remaining_times() {
+        let mut map = DelayMap::default();
+        map.insert("a", 1, Duration::from_secs(10));
+        map.insert("b", 2, Duration::from_secs(20));
+        map.insert("c", 3, Duration::from_secs(30));
+        map.freeze("c");
+        tokio::time::advance(Duration::from_secs(5)).await;
+
+        let json = serde_json::to_string(&map).unwrap();
+        tokio::time::advance(Duration::from_secs(5)).await;
+        let restored: DelayMap<String, u32> = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.get("b"), Some(&2));
+        assert_eq!(restored.time_remaining("a"), Some(Duration::from_secs(5)));
+        assert_eq!(restored.time_remaining("b"), Some(Duration::from_secs(15)));
+        assert_eq!(restored.time_remaining("c"), Some(Duration::from_secs(30)));
+        assert!(!restored.is_frozen("c"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn skips_repeated_items() {
+        let mut handler = DelayHandler::default();
+        handler.insert(1, Duration::from_secs(10));
+        handler.insert(2, Duration::from_secs(20));
+        let json = serde_json::to_string(&handler).unwrap();
+
+        let repeated = format!(
+            "[{}, {}]",
+            &json[1..json.len() - 1],
+            &json[1..json.len() - 1]
+        );
+        let restored: DelayHandler<u32> = serde_json::from_str(&repeated).unwrap();
+        assert_eq!(restored.len(), 2);
+        assert_eq!(restored.time_remaining(&2), Some(Duration::from_secs(20)));
+    }
+}
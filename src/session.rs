@@ -0,0 +1,278 @@
+use std::borrow::Borrow;
+use std::future::poll_fn;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures_core::{FusedStream, Stream};
+
+use crate::clock::saturating_add;
+use crate::DelayMap;
+
+/// Data of a session, along with the instant its lifetime runs out at, regardless of activity.
+struct Session<V> {
+    data: V,
+    ends: Option<Instant>,
+}
+
+/// Expires sessions that have been idle for too long, with a deadline that slides forward on every
+/// [`touch()`](SessionExpiry::touch).
+///
+/// Sessions can also be given a [max lifetime](SessionExpiry::max_lifetime), beyond which they
+/// expire even if they are kept active. Expired sessions are yielded by
+/// [`next()`](SessionExpiry::next) along with their data.
+///
+/// ### Examples
+/// Expire sessions idle for 15 minutes, or 12 hours after they were started
+/// ```no_run
+/// # use delay_handler::SessionExpiry;
+/// # use std::time::Duration;
+/// # async fn run() {
+/// let mut sessions = SessionExpiry::new(Duration::from_secs(15 * 60))
+///     .max_lifetime(Duration::from_secs(12 * 60 * 60));
+/// sessions.insert("token", "alice");
+///
+/// // Every request in the session keeps it alive for another 15 minutes.
+/// sessions.touch("token");
+///
+/// while let Some((token, user)) = sessions.next().await {
+///     println!("session {token} of {user} expired");
+/// }
+/// # }
+/// ```
+pub struct SessionExpiry<K, V> {
+    map: DelayMap<K, Session<V>>,
+    idle: Duration,
+    max_lifetime: Option<Duration>,
+}
+
+impl<K, V> SessionExpiry<K, V>
+where
+    K: Eq + Hash,
+{
+    /// Creates an empty set of sessions, that expire after being idle for `idle`.
+    pub fn new(idle: Duration) -> Self {
+        Self {
+            map: DelayMap::default(),
+            idle,
+            max_lifetime: None,
+        }
+    }
+
+    /// Sets how long sessions can last since they were inserted, no matter how active they are.
+    pub fn max_lifetime(mut self, max_lifetime: Duration) -> Self {
+        self.max_lifetime = Some(max_lifetime);
+        self
+    }
+
+    /// Returns the period of inactivity after which sessions expire.
+    pub fn idle_timeout(&self) -> Duration {
+        self.idle
+    }
+
+    /// Insert a new session with its data if it doesn't already exist. If one already exists,
+    /// don't insert and drop the provided data, returning false.
+    pub fn insert(&mut self, key: K, data: V) -> bool {
+        let now = self.map.now();
+        let ends = self
+            .max_lifetime
+            .and_then(|max_lifetime| now.checked_add(max_lifetime));
+        let deadline = self.deadline(now, ends);
+
+        self.map.insert_at(key, Session { data, ends }, deadline)
+    }
+
+    /// Renews a session, so that it expires only after being idle for another idle timeout from
+    /// now, or at the end of its lifetime, if sooner. If it didn't exist returns false.
+    pub fn touch<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let ends = match self.map.get(key) {
+            Some(session) => session.ends,
+            _ => return false,
+        };
+        let now = self.map.now();
+        let period = self.deadline(now, ends).saturating_duration_since(now);
+
+        self.map.reset(key, period)
+    }
+
+    /// Deadline of a session renewed at `now`, that can't go past the end of its lifetime.
+    fn deadline(&self, now: Instant, ends: Option<Instant>) -> Instant {
+        let idle = saturating_add(now, self.idle);
+
+        ends.map_or(idle, |ends| idle.min(ends))
+    }
+
+    /// Returns the data of a session, without renewing it.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.get(key).map(|session| &session.data)
+    }
+
+    /// Returns the data of a session for modification, without renewing it.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.get_mut(key).map(|session| &mut session.data)
+    }
+
+    /// Ends a session prematurely, such as on logout, returning its data if it hadn't already expired.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.remove(key).map(|session| session.data)
+    }
+
+    /// Returns how long until a session expires, unless renewed, zero if it is already due.
+    pub fn time_remaining<Q>(&self, key: &Q) -> Option<Duration>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.time_remaining(key)
+    }
+
+    /// Remove a session once it has expired and return it, along with its data.
+    pub async fn next(&mut self) -> Option<(K, V)> {
+        poll_fn(|cx| self.poll_expired(cx)).await
+    }
+
+    /// Polls for a session that has expired, registering the current task for wakeup if none has yet.
+    pub fn poll_expired(&mut self, cx: &mut Context<'_>) -> Poll<Option<(K, V)>> {
+        self.map
+            .poll_expired(cx)
+            .map(|expired| expired.map(|(key, session)| (key, session.data)))
+    }
+
+    /// Remove a session if it has already expired and return it along with its data, without waiting.
+    pub fn try_next(&mut self) -> Option<(K, V)> {
+        let (key, session) = self.map.try_next()?;
+
+        Some((key, session.data))
+    }
+
+    /// End all sessions, dropping their data.
+    pub fn clear(&mut self) {
+        self.map.clear()
+    }
+
+    /// Check if there are no sessions.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Check if a session exists, i.e. hasn't expired.
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.contains(key)
+    }
+
+    /// Number of sessions that haven't expired.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+}
+
+impl<K, V> Stream for SessionExpiry<K, V>
+where
+    K: Eq + Hash,
+{
+    type Item = (K, V);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_expired(cx)
+    }
+}
+
+impl<K, V> FusedStream for SessionExpiry<K, V>
+where
+    K: Eq + Hash,
+{
+    fn is_terminated(&self) -> bool {
+        self.map.is_terminated()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::SessionExpiry;
+    use crate::{DelayMap, MockClock};
+
+    fn sessions(clock: &MockClock, idle: u64) -> SessionExpiry<&'static str, u32> {
+        let mut sessions = SessionExpiry::new(Duration::from_secs(idle));
+        sessions.map = DelayMap::builder().clock(clock.clone()).build_map();
+
+        sessions
+    }
+
+    #[test]
+    fn expires_sessions_once_idle() {
+        let clock = MockClock::new();
+        let mut sessions = sessions(&clock, 10);
+        assert!(sessions.insert("a", 1));
+        assert!(!sessions.insert("a", 2));
+
+        for _ in 0..3 {
+            clock.advance(Duration::from_secs(8));
+            assert!(sessions.touch("a"));
+            assert_eq!(sessions.try_next(), None);
+        }
+        assert_eq!(sessions.time_remaining("a"), Some(Duration::from_secs(10)));
+
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(sessions.try_next(), Some(("a", 1)));
+        assert!(!sessions.touch("a"));
+    }
+
+    #[test]
+    fn caps_sessions_at_their_max_lifetime() {
+        let clock = MockClock::new();
+        let mut sessions = sessions(&clock, 10).max_lifetime(Duration::from_secs(25));
+        sessions.insert("a", 1);
+
+        for _ in 0..3 {
+            clock.advance(Duration::from_secs(8));
+            assert!(sessions.touch("a"));
+        }
+        assert_eq!(sessions.time_remaining("a"), Some(Duration::from_secs(1)));
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(sessions.try_next(), Some(("a", 1)));
+    }
+
+    #[test]
+    fn reads_and_removes_session_data() {
+        let clock = MockClock::new();
+        let mut sessions = sessions(&clock, 10);
+        sessions.insert("a", 1);
+        sessions.insert("b", 2);
+
+        clock.advance(Duration::from_secs(5));
+        *sessions.get_mut("a").unwrap() += 1;
+        assert_eq!(sessions.get("a"), Some(&2));
+        // Reading a session doesn't renew it
+        assert_eq!(sessions.time_remaining("a"), Some(Duration::from_secs(5)));
+        assert_eq!(sessions.remove("b"), Some(2));
+        assert_eq!(sessions.remove("b"), None);
+
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(sessions.try_next(), Some(("a", 2)));
+        assert_eq!(sessions.try_next(), None);
+    }
+}
@@ -0,0 +1,235 @@
+use std::borrow::Borrow;
+use std::convert::Infallible;
+use std::future::poll_fn;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use futures_sink::Sink;
+
+use crate::DelayHandler;
+
+/// A clonable, thread-safe handle to a [`DelayHandler`], so that timeouts can be inserted and removed
+/// from many tasks, while being consumed from one place.
+///
+/// Unlike the handler itself, [`next()`](SharedDelayHandler::next) waits for new inserts from other
+/// handles while the handler is empty, returning `None` only once every other handle has been dropped.
+/// If more than one task waits on expiries, only the last one to poll is woken up. It also implements
+/// [`Sink`], so that a stream of `(item, period)` pairs can be forwarded into it.
+///
+/// ### Examples
+/// ```no_run
+/// # use delay_handler::SharedDelayHandler;
+/// # use std::time::Duration;
+/// # async fn run() {
+/// let handler = SharedDelayHandler::default();
+/// let producer = handler.clone();
+/// tokio::spawn(async move {
+///     producer.insert(1, Duration::from_secs(5));
+/// });
+///
+/// // Prints 1 after ~5s, then ends as the producer has been dropped.
+/// while let Some(expired) = handler.next().await {
+///     println!("{}", expired);
+/// }
+/// # }
+/// ```
+pub struct SharedDelayHandler<T> {
+    inner: Arc<Mutex<Shared<T>>>,
+}
+
+struct Shared<T> {
+    handler: DelayHandler<T>,
+    /// Task waiting for expiries, to be woken up whenever the next deadline may have changed.
+    waker: Option<Waker>,
+    /// Number of handles that are yet to be dropped.
+    handles: usize,
+}
+
+impl<T> Shared<T> {
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> SharedDelayHandler<T>
+where
+    T: Eq + Hash,
+{
+    /// Share an existing handler between handles.
+    pub fn new(handler: DelayHandler<T>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Shared {
+                handler,
+                waker: None,
+                handles: 1,
+            })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Shared<T>> {
+        self.inner.lock().unwrap()
+    }
+
+    /// Runs `f` with exclusive access to the underlying handler, for operations not exposed on the
+    /// shared handle. The task waiting on expiries is woken up afterwards, in case `f` changed the
+    /// next deadline.
+    pub fn with<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut DelayHandler<T>) -> R,
+    {
+        let mut shared = self.lock();
+        let ret = f(&mut shared.handler);
+        shared.wake();
+
+        ret
+    }
+
+    /// Insert new timeout into the map and queue if it doesn't already exist.
+    /// If one already exists, don't insert, returning false.
+    pub fn insert(&self, item: T, period: Duration) -> bool {
+        self.with(|handler| handler.insert(item, period))
+    }
+
+    /// Insert new timeout, to timeout at the given `deadline`, if it doesn't already exist.
+    /// If one already exists, don't insert, returning false.
+    pub fn insert_at(&self, item: T, deadline: Instant) -> bool {
+        self.with(|handler| handler.insert_at(item, deadline))
+    }
+
+    /// Prematurely removes timeout from delay-map, returning false if it didn't already exist.
+    pub fn remove<Q>(&self, item: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.with(|handler| handler.remove(item))
+    }
+
+    /// Updates the deadline of an existing timeout in place, to timeout after `period` from now.
+    /// If it didn't already exist returns false.
+    pub fn reset<Q>(&self, item: &Q, period: Duration) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.with(|handler| handler.reset(item, period))
+    }
+
+    /// Remove an item from map if it has timedout and return it, waiting for new inserts while the
+    /// handler is empty. Returns `None` once the handler is empty and every other handle is dropped.
+    pub async fn next(&self) -> Option<T> {
+        poll_fn(|cx| self.poll_expired(cx)).await
+    }
+
+    /// Polls for an item that has timedout, registering the current task for wakeup if none has yet.
+    pub fn poll_expired(&self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut shared = self.lock();
+        let poll = match shared.handler.poll_expired(cx) {
+            Poll::Ready(None) if shared.handles > 1 => Poll::Pending,
+            poll => poll,
+        };
+        if poll.is_pending() {
+            shared.waker = Some(cx.waker().clone());
+        }
+
+        poll
+    }
+
+    /// Remove an item from map if it has already timedout and return it, without waiting.
+    pub fn try_next(&self) -> Option<T> {
+        self.lock().handler.try_next()
+    }
+
+    /// Remove all pending timeouts from the delay-map.
+    pub fn clear(&self) {
+        self.with(|handler| handler.clear())
+    }
+
+    /// Check if queue is empty.
+    pub fn is_empty(&self) -> bool {
+        self.lock().handler.is_empty()
+    }
+
+    /// Check if a timeout is currently scheduled for `item`.
+    pub fn contains<Q>(&self, item: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.lock().handler.contains(item)
+    }
+
+    /// Number of timeouts currently pending in the delay-map.
+    pub fn len(&self) -> usize {
+        self.lock().handler.len()
+    }
+}
+
+/// Schedules timeouts forwarded from a producer pipeline, timeouts that already exist are ignored.
+impl<T> Sink<(T, Duration)> for SharedDelayHandler<T>
+where
+    T: Eq + Hash,
+{
+    type Error = Infallible;
+
+    fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, (item, period): (T, Duration)) -> Result<(), Self::Error> {
+        self.insert(item, period);
+
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T> Clone for SharedDelayHandler<T> {
+    fn clone(&self) -> Self {
+        self.inner.lock().unwrap().handles += 1;
+
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Default for SharedDelayHandler<T>
+where
+    T: Eq + Hash,
+{
+    fn default() -> Self {
+        Self::new(DelayHandler::default())
+    }
+}
+
+impl<T> From<DelayHandler<T>> for SharedDelayHandler<T>
+where
+    T: Eq + Hash,
+{
+    fn from(handler: DelayHandler<T>) -> Self {
+        Self::new(handler)
+    }
+}
+
+impl<T> Drop for SharedDelayHandler<T> {
+    fn drop(&mut self) {
+        // Let the task waiting on expiries learn that this handle is gone
+        if let Ok(mut shared) = self.inner.lock() {
+            shared.handles -= 1;
+            shared.wake();
+        }
+    }
+}
@@ -0,0 +1,36 @@
+/// Counters and gauges of a delay-map's activity since it was created, as returned by
+/// [`stats()`](crate::DelayHandler::stats).
+///
+/// ### Examples
+/// ```
+/// # use delay_handler::DelayHandler;
+/// # use std::time::Duration;
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let mut handler = DelayHandler::default();
+/// handler.insert(1, Duration::from_secs(1));
+/// handler.insert(1, Duration::from_secs(1));
+/// handler.insert(2, Duration::from_secs(1));
+/// handler.remove(&2);
+///
+/// let stats = handler.stats();
+/// assert_eq!((stats.inserted, stats.duplicates, stats.removed), (2, 1, 1));
+/// assert_eq!((stats.depth, stats.peak_depth), (1, 2));
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Number of timeouts inserted.
+    pub inserted: u64,
+    /// Number of inserts rejected, as a timeout already existed for the key.
+    pub duplicates: u64,
+    /// Number of timeouts removed before expiring, including those that were cleared, drained,
+    /// cancelled or evicted to make room.
+    pub removed: u64,
+    /// Number of expiries, counting every expiry of periodic timeouts and ones with a backoff policy.
+    pub expired: u64,
+    /// Number of timeouts currently pending.
+    pub depth: usize,
+    /// Highest number of timeouts that have been pending at once.
+    pub peak_depth: usize,
+}
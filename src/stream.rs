@@ -0,0 +1,56 @@
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use futures_core::{FusedStream, Stream};
+
+/// An owned stream of timedout items, returned by [`DelayHandler::into_stream()`](crate::DelayHandler::into_stream)
+/// and [`DelayMap::into_stream()`](crate::DelayMap::into_stream).
+///
+/// Unlike the handler itself, which yields items again after new inserts, this stream is fused:
+/// once the delay-map has been drained and `None` is returned, it will keep returning `None`.
+pub struct IntoStream<S> {
+    inner: S,
+    terminated: bool,
+}
+
+impl<S> IntoStream<S> {
+    pub(crate) fn new(inner: S) -> Self {
+        Self {
+            inner,
+            terminated: false,
+        }
+    }
+
+    /// Consumes the stream, returning the underlying handler.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S> Stream for IntoStream<S>
+where
+    S: Stream + Unpin,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.terminated {
+            return Poll::Ready(None);
+        }
+
+        let item = ready!(Pin::new(&mut this.inner).poll_next(cx));
+        this.terminated = item.is_none();
+
+        Poll::Ready(item)
+    }
+}
+
+impl<S> FusedStream for IntoStream<S>
+where
+    S: Stream + Unpin,
+{
+    fn is_terminated(&self) -> bool {
+        self.terminated
+    }
+}
@@ -0,0 +1,86 @@
+//! Helpers for fast-forwarding through the schedule of a handler in tests, with the tokio clock
+//! paused through `tokio::time::pause()` or `#[tokio::test(start_paused = true)]`.
+//!
+//! The handler follows the paused clock, so after it is advanced, every timeout whose deadline was
+//! skipped over is due at once and is returned in order of expiry, including every run of
+//! recurring timeouts. The wall-clock time told by [`TokioClock`](crate::TokioClock) follows the
+//! paused clock too, so that timeouts with a wall-clock deadline also expire. Handlers configured
+//! with a [`MockClock`](crate::MockClock) are instead advanced through the clock itself.
+
+use std::hash::{BuildHasher, Hash};
+use std::time::Duration;
+
+use crate::{DelayHandler, DelayMap};
+
+/// Advances the paused tokio clock by `duration`, returning every item whose deadline was skipped
+/// over, in order of expiry.
+///
+/// ### Examples
+/// ```
+/// # use delay_handler::{testing, DelayHandler};
+/// # use std::time::Duration;
+/// # #[tokio::main(flavor = "current_thread", start_paused = true)]
+/// # async fn main() {
+/// let mut handler = DelayHandler::default();
+/// handler.insert(1, Duration::from_secs(3600));
+/// handler.insert(2, Duration::from_secs(60));
+///
+/// let expired = testing::advance(&mut handler, Duration::from_secs(2 * 3600)).await;
+/// assert_eq!(expired, vec![2, 1]);
+/// # }
+/// ```
+///
+/// # Panics
+/// If the tokio clock isn't paused, or if called outside the context of a tokio runtime.
+pub async fn advance<T, S>(handler: &mut DelayHandler<T, S>, duration: Duration) -> Vec<T>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
+{
+    tokio::time::advance(duration).await;
+    handler.drain_expired()
+}
+
+/// Advances the paused tokio clock by `duration`, returning every key whose deadline was skipped
+/// over along with its associated payload, in order of expiry.
+///
+/// # Panics
+/// If the tokio clock isn't paused, or if called outside the context of a tokio runtime.
+pub async fn advance_map<K, V, S>(map: &mut DelayMap<K, V, S>, duration: Duration) -> Vec<(K, V)>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    tokio::time::advance(duration).await;
+    map.drain_expired()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{testing, DelayHandler, Periodic};
+
+    #[tokio::test(start_paused = true)]
+    async fn returns_every_deadline_skipped_over() {
+        let minute = Duration::from_secs(60);
+        let mut handler = DelayHandler::default();
+        let mut expected = vec![];
+        for item in 0..100 {
+            let delay = minute * (item * 37 % 100 + 1);
+            handler.insert(item, delay);
+            expected.push((delay, item));
+        }
+        // Runs of a recurring timeout that are skipped over are all returned
+        let interval = minute * 51 / 2;
+        handler.insert_periodic(1000, interval, Periodic::FixedRate);
+        expected.extend((1..4).map(|run| (interval * run, 1000)));
+        expected.sort();
+
+        let expired = testing::advance(&mut handler, minute * 100).await;
+        let expected: Vec<_> = expected.into_iter().map(|(_, item)| item).collect();
+        assert_eq!(expired, expected);
+        assert_eq!(handler.len(), 1);
+        assert_eq!(handler.time_remaining(&1000), Some(minute * 2));
+    }
+}
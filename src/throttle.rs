@@ -0,0 +1,279 @@
+use std::borrow::Borrow;
+use std::future::poll_fn;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures_core::{FusedStream, Stream};
+
+use crate::clock::saturating_add;
+use crate::DelayMap;
+
+/// Describes when a [`Throttle`] emits an item, within the period opened by its first insert.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    /// Emit immediately on the first insert, suppressing every other insert until the period ends.
+    Leading,
+    /// Emit once the period ends, coalescing every insert within it.
+    Trailing,
+}
+
+/// Counts the inserts of an item within its period.
+struct Window {
+    count: u32,
+    /// False once the item has been emitted on the leading edge, with the rest of the period left
+    /// to run out.
+    emitting: bool,
+}
+
+/// Emits each item at most once per period, no matter how often it is inserted, to rate-limit
+/// noisy event sources.
+///
+/// The first insert of an item opens a period, until the end of which every other insert of it is
+/// either suppressed or coalesced, depending on the [`Edge`] it is emitted on. Items are yielded by
+/// [`next()`](Throttle::next) along with the number of inserts they stand for.
+///
+/// ### Examples
+/// ```
+/// # use delay_handler::{Edge, Throttle};
+/// # use std::time::Duration;
+/// # #[tokio::main(flavor = "current_thread", start_paused = true)]
+/// # async fn main() {
+/// let mut throttle = Throttle::new(Duration::from_secs(1), Edge::Trailing);
+/// throttle.insert("disk full");
+/// throttle.insert("disk full");
+/// throttle.insert("disk full");
+///
+/// // Expect a delay of ~1s, after which "disk full" is yielded just once, standing for 3 inserts.
+/// assert_eq!(throttle.next().await, Some(("disk full", 3)));
+/// # }
+/// ```
+pub struct Throttle<T> {
+    map: DelayMap<T, Window>,
+    period: Duration,
+    edge: Edge,
+}
+
+impl<T> Throttle<T>
+where
+    T: Eq + Hash + Clone,
+{
+    /// Creates an empty throttle, that emits each item at most once every `period`, on the given `edge`.
+    pub fn new(period: Duration, edge: Edge) -> Self {
+        Self {
+            map: DelayMap::default(),
+            period,
+            edge,
+        }
+    }
+
+    /// Returns the period within which each item is emitted at most once.
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Returns the edge of the period that items are emitted on.
+    pub fn edge(&self) -> Edge {
+        self.edge
+    }
+
+    /// Insert an item, opening a new period if it doesn't already have one running, in which case
+    /// returns true. Otherwise the insert is counted towards that period and the provided item is
+    /// dropped, returning false.
+    pub fn insert(&mut self, item: T) -> bool {
+        if let Some(window) = self.map.get_mut(&item) {
+            window.count = window.count.saturating_add(1);
+            return false;
+        }
+
+        let window = Window {
+            count: 1,
+            emitting: true,
+        };
+        let delay = match self.edge {
+            Edge::Leading => Duration::ZERO,
+            Edge::Trailing => self.period,
+        };
+
+        self.map.insert(item, window, delay)
+    }
+
+    /// Ends the period of an item prematurely, so that it isn't emitted if it was yet to be and is
+    /// emitted again on the next insert. If it had no period running returns false.
+    pub fn remove<Q>(&mut self, item: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.remove(item).is_some()
+    }
+
+    /// Remove an item once it is emitted and return it, along with the number of inserts it stands for.
+    pub async fn next(&mut self) -> Option<(T, u32)> {
+        poll_fn(|cx| self.poll_expired(cx)).await
+    }
+
+    /// Polls for an item to be emitted, registering the current task for wakeup if none is yet.
+    pub fn poll_expired(&mut self, cx: &mut Context<'_>) -> Poll<Option<(T, u32)>> {
+        loop {
+            let expired = match ready!(self.map.poll_next_expired(cx)) {
+                Some(expired) => expired,
+                _ => return Poll::Ready(None),
+            };
+            let deadline = expired.deadline();
+            let (item, window) = expired.into_inner();
+            if let Some(emitted) = self.emit(item, window, deadline) {
+                return Poll::Ready(Some(emitted));
+            }
+        }
+    }
+
+    /// Remove an item if it is already due to be emitted and return it, along with the number of
+    /// inserts it stands for, without waiting.
+    pub fn try_next(&mut self) -> Option<(T, u32)> {
+        while let Some(expired) = self.map.try_next_expired() {
+            let deadline = expired.deadline();
+            let (item, window) = expired.into_inner();
+            if let Some(emitted) = self.emit(item, window, deadline) {
+                return Some(emitted);
+            }
+        }
+
+        None
+    }
+
+    /// Emits an item whose window timedout at `deadline`, unless it was only left to run out.
+    fn emit(&mut self, item: T, window: Window, deadline: Instant) -> Option<(T, u32)> {
+        if !window.emitting {
+            return None;
+        }
+
+        // Suppress inserts for the rest of the period, which started as the item was inserted
+        if self.edge == Edge::Leading {
+            let rest = Window {
+                count: 0,
+                emitting: false,
+            };
+            self.map
+                .insert_at(item.clone(), rest, saturating_add(deadline, self.period));
+        }
+
+        Some((item, window.count))
+    }
+
+    /// End the periods of all items, so that none of them are emitted.
+    pub fn clear(&mut self) {
+        self.map.clear()
+    }
+
+    /// Check if no items have a period running.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Check if an item has a period running, so that inserting it won't emit it again.
+    pub fn contains<Q>(&self, item: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.contains(item)
+    }
+
+    /// Number of items that currently have a period running.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+}
+
+impl<T> Stream for Throttle<T>
+where
+    T: Eq + Hash + Clone,
+{
+    type Item = (T, u32);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_expired(cx)
+    }
+}
+
+impl<T> FusedStream for Throttle<T>
+where
+    T: Eq + Hash + Clone,
+{
+    fn is_terminated(&self) -> bool {
+        self.map.is_terminated()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Edge, Throttle};
+    use crate::{DelayMap, MockClock};
+
+    fn throttle(clock: &MockClock, edge: Edge) -> Throttle<&'static str> {
+        let mut throttle = Throttle::new(Duration::from_secs(1), edge);
+        throttle.map = DelayMap::builder().clock(clock.clone()).build_map();
+
+        throttle
+    }
+
+    #[test]
+    fn emits_on_the_leading_edge() {
+        let clock = MockClock::new();
+        let mut throttle = throttle(&clock, Edge::Leading);
+        assert!(throttle.insert("a"));
+        assert_eq!(throttle.try_next(), Some(("a", 1)));
+
+        // Suppressed for the rest of the period
+        assert!(!throttle.insert("a"));
+        assert!(!throttle.insert("a"));
+        clock.advance(Duration::from_millis(999));
+        assert_eq!(throttle.try_next(), None);
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(throttle.try_next(), None);
+        assert!(!throttle.contains("a"));
+
+        assert!(throttle.insert("a"));
+        assert_eq!(throttle.try_next(), Some(("a", 1)));
+    }
+
+    #[test]
+    fn anchors_the_period_at_the_first_insert() {
+        let clock = MockClock::new();
+        let mut throttle = throttle(&clock, Edge::Leading);
+        throttle.insert("a");
+
+        // Emitted late, which doesn't push back the end of the period
+        clock.advance(Duration::from_millis(300));
+        assert_eq!(throttle.try_next(), Some(("a", 1)));
+        clock.advance(Duration::from_millis(699));
+        throttle.try_next();
+        assert!(throttle.contains("a"));
+        clock.advance(Duration::from_millis(1));
+        throttle.try_next();
+        assert!(!throttle.contains("a"));
+    }
+
+    #[test]
+    fn coalesces_on_the_trailing_edge() {
+        let clock = MockClock::new();
+        let mut throttle = throttle(&clock, Edge::Trailing);
+        assert!(throttle.insert("a"));
+        assert!(!throttle.insert("a"));
+        clock.advance(Duration::from_millis(500));
+        assert!(!throttle.insert("a"));
+        assert!(throttle.insert("b"));
+        assert_eq!(throttle.try_next(), None);
+
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(throttle.try_next(), Some(("a", 3)));
+        assert_eq!(throttle.try_next(), None);
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(throttle.try_next(), Some(("b", 1)));
+        assert!(throttle.is_empty());
+    }
+}
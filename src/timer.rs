@@ -0,0 +1,123 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::task::{ready, Context, Poll, Waker};
+use std::time::Instant;
+
+use crate::clock::{Clock, Sleep};
+
+/// Queue of values that are each yielded once their deadline passes, as told by a [`Clock`].
+/// Only a single sleep is ever pending, for the earliest deadline.
+pub(crate) struct Timer<T> {
+    clock: Arc<dyn Clock>,
+    entries: BTreeMap<(Instant, u64), T>,
+    deadlines: HashMap<u64, Instant>,
+    next_key: u64,
+    /// Sleep for the earliest deadline, along with that deadline.
+    sleep: Option<(Instant, Sleep)>,
+    /// Task that last polled, woken up when an earlier deadline is inserted.
+    waker: Option<Waker>,
+}
+
+/// Refers to a value in the [`Timer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TimerKey(u64);
+
+impl<T> Timer<T> {
+    pub(crate) fn with_capacity(clock: Arc<dyn Clock>, capacity: usize) -> Self {
+        Self {
+            clock,
+            entries: BTreeMap::new(),
+            deadlines: HashMap::with_capacity(capacity),
+            next_key: 0,
+            sleep: None,
+            waker: None,
+        }
+    }
+
+    pub(crate) fn clock(&self) -> &dyn Clock {
+        &*self.clock
+    }
+
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        self.deadlines.reserve(additional);
+    }
+
+    pub(crate) fn compact(&mut self) {
+        self.deadlines.shrink_to_fit();
+    }
+
+    pub(crate) fn insert_at(&mut self, value: T, deadline: Instant) -> TimerKey {
+        let key = self.next_key;
+        self.next_key += 1;
+        self.entries.insert((deadline, key), value);
+        self.deadlines.insert(key, deadline);
+        self.wake_if_earlier(deadline);
+
+        TimerKey(key)
+    }
+
+    /// # Panics
+    /// If `key` doesn't refer to a value in the timer.
+    pub(crate) fn reset_at(&mut self, key: &TimerKey, deadline: Instant) {
+        let previous = self.deadlines.insert(key.0, deadline).unwrap();
+        let value = self.entries.remove(&(previous, key.0)).unwrap();
+        self.entries.insert((deadline, key.0), value);
+        self.wake_if_earlier(deadline);
+    }
+
+    /// # Panics
+    /// If `key` doesn't refer to a value in the timer.
+    pub(crate) fn remove(&mut self, key: &TimerKey) -> T {
+        let deadline = self.deadlines.remove(&key.0).unwrap();
+
+        self.entries.remove(&(deadline, key.0)).unwrap()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.deadlines.clear();
+        self.sleep = None;
+    }
+
+    /// Polls for the value with the earliest deadline to have expired, returning `None` when empty.
+    pub(crate) fn poll_expired(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        if !self.waker.as_ref().is_some_and(|w| w.will_wake(cx.waker())) {
+            self.waker = Some(cx.waker().clone());
+        }
+
+        let deadline = match self.entries.first_key_value() {
+            Some((&(deadline, _), _)) => deadline,
+            _ => {
+                self.sleep = None;
+                return Poll::Ready(None);
+            }
+        };
+
+        if deadline > self.clock.now() {
+            if self.sleep.as_ref().map(|(armed, _)| *armed) != Some(deadline) {
+                self.sleep = Some((deadline, self.clock.sleep_until(deadline)));
+            }
+
+            let (_, sleep) = self.sleep.as_mut().unwrap();
+            ready!(sleep.as_mut().poll(cx));
+            self.sleep = None;
+        }
+
+        let ((_, key), value) = self.entries.pop_first().unwrap();
+        self.deadlines.remove(&key);
+
+        Poll::Ready(Some(value))
+    }
+
+    fn wake_if_earlier(&mut self, deadline: Instant) {
+        let earlier = match &self.sleep {
+            Some((armed, _)) => deadline < *armed,
+            _ => true,
+        };
+        if earlier {
+            if let Some(waker) = self.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
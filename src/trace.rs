@@ -0,0 +1,17 @@
+use std::fmt::{self, Debug, Formatter};
+
+/// Formats a key in tracing events, as configured with
+/// [`trace_keys()`](crate::DelayMap::trace_keys).
+pub(crate) type FmtKey<K> = fn(&K, &mut Formatter<'_>) -> fmt::Result;
+
+/// A key as recorded in tracing events, elided unless keys are traced.
+pub(crate) struct Key<'a, K>(pub(crate) &'a K, pub(crate) Option<FmtKey<K>>);
+
+impl<K> Debug for Key<'_, K> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.1 {
+            Some(fmt) => fmt(self.0, f),
+            _ => f.write_str(".."),
+        }
+    }
+}
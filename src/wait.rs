@@ -0,0 +1,74 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::sync::oneshot;
+
+/// Describes what became of a timeout that was waited on with [`wait_for()`](crate::DelayMap::wait_for).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fate {
+    /// The timeout expired.
+    Expired,
+    /// The timeout was removed before it could expire, such as by removal, eviction, clearing,
+    /// draining or dropping the delay-map.
+    Removed,
+}
+
+/// A future that resolves once a particular timeout has expired or been removed, returned by
+/// [`wait_for()`](crate::DelayMap::wait_for). It doesn't borrow the delay-map, so that it can be
+/// awaited by a task other than the one driving it.
+pub struct WaitFor {
+    receiver: oneshot::Receiver<()>,
+}
+
+impl WaitFor {
+    pub(crate) fn new(receiver: oneshot::Receiver<()>) -> Self {
+        Self { receiver }
+    }
+}
+
+impl Future for WaitFor {
+    type Output = Fate;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Fate> {
+        // The sender is only ever used on expiry, every other way of the timeout going away drops it
+        match Pin::new(&mut self.receiver).poll(cx) {
+            Poll::Ready(Ok(())) => Poll::Ready(Fate::Expired),
+            Poll::Ready(Err(_)) => Poll::Ready(Fate::Removed),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::executor::block_on;
+
+    use super::Fate;
+    use crate::{DelayMap, MockClock};
+
+    #[test]
+    fn tells_expired_from_removed() {
+        let clock = MockClock::new();
+        let mut map = DelayMap::builder().clock(clock.clone()).build_map();
+        map.insert("a", 1, Duration::from_secs(1));
+        map.insert("b", 2, Duration::from_secs(2));
+        map.insert("c", 3, Duration::from_secs(3));
+        assert!(map.wait_for("d").is_none());
+        let (a, again) = (map.wait_for("a").unwrap(), map.wait_for("a").unwrap());
+        let (b, c) = (map.wait_for("b").unwrap(), map.wait_for("c").unwrap());
+
+        map.remove("b");
+        assert_eq!(block_on(b), Fate::Removed);
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(map.try_next(), Some(("a", 1)));
+        assert_eq!(block_on(a), Fate::Expired);
+        assert_eq!(block_on(again), Fate::Expired);
+
+        drop(map);
+        assert_eq!(block_on(c), Fate::Removed);
+    }
+}
@@ -0,0 +1,511 @@
+use std::sync::Arc;
+use std::task::{ready, Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use crate::clock::{saturating_add, Clock, Sleep};
+
+/// Number of levels in the wheel, each with slots spanning 64 times as many ticks as the one below.
+const LEVELS: usize = 6;
+const SLOTS: usize = 64;
+/// Ticks spanned by the whole wheel, values further out are parked in its last level until they
+/// come within range.
+const MAX_TICKS: u64 = 1 << (6 * LEVELS);
+
+/// Hierarchical timing wheel of values that are each yielded once their deadline passes, as told
+/// by a [`Clock`]. Deadlines are rounded up to whole ticks, inserting and removing is constant time
+/// and only a single sleep is ever pending, for the next occupied slot.
+pub(crate) struct Wheel<T> {
+    clock: Arc<dyn Clock>,
+    /// Instant from which ticks are counted.
+    start: Instant,
+    tick: Duration,
+    /// Ticks that have been processed, values are placed in levels relative to it.
+    elapsed: u64,
+    entries: Vec<Entry<T>>,
+    /// Indices of vacant entries, to be reused.
+    vacant: Vec<usize>,
+    len: usize,
+    levels: [Level; LEVELS],
+    /// Values whose deadline has been reached, waiting to be yielded.
+    expired: List,
+    /// Sleep for the next occupied slot, along with the tick it starts at.
+    sleep: Option<(u64, Sleep)>,
+    /// Task that last polled, woken up when an earlier deadline is inserted.
+    waker: Option<Waker>,
+}
+
+/// Refers to a value in the [`Wheel`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct WheelKey(usize);
+
+struct Level {
+    /// Bitmap of slots that hold at least one value.
+    occupied: u64,
+    slots: [List; SLOTS],
+}
+
+/// Intrusive doubly-linked list of entries.
+#[derive(Clone, Copy, Default)]
+struct List {
+    head: Option<usize>,
+    tail: Option<usize>,
+}
+
+struct Entry<T> {
+    value: Option<T>,
+    /// Tick at which the value expires.
+    when: u64,
+    prev: Option<usize>,
+    next: Option<usize>,
+    place: Place,
+}
+
+#[derive(Clone, Copy)]
+enum Place {
+    Expired,
+    Slot(usize, usize),
+    Vacant,
+}
+
+impl<T> Wheel<T> {
+    pub(crate) fn with_capacity(clock: Arc<dyn Clock>, tick: Duration, capacity: usize) -> Self {
+        Self {
+            start: clock.now(),
+            clock,
+            tick: tick.max(Duration::from_nanos(1)),
+            elapsed: 0,
+            entries: Vec::with_capacity(capacity),
+            vacant: vec![],
+            len: 0,
+            levels: std::array::from_fn(|_| Level {
+                occupied: 0,
+                slots: [List::default(); SLOTS],
+            }),
+            expired: List::default(),
+            sleep: None,
+            waker: None,
+        }
+    }
+
+    pub(crate) fn clock(&self) -> &dyn Clock {
+        &*self.clock
+    }
+
+    pub(crate) fn start(&self) -> Instant {
+        self.start
+    }
+
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        self.entries
+            .reserve(additional.saturating_sub(self.vacant.len()));
+    }
+
+    pub(crate) fn compact(&mut self) {
+        // Only trailing vacant entries can be dropped without moving others
+        while let Some(Entry {
+            place: Place::Vacant,
+            ..
+        }) = self.entries.last()
+        {
+            self.entries.pop();
+        }
+        let len = self.entries.len();
+        self.vacant.retain(|&index| index < len);
+        self.entries.shrink_to_fit();
+        self.vacant.shrink_to_fit();
+    }
+
+    pub(crate) fn insert_at(&mut self, value: T, deadline: Instant) -> WheelKey {
+        let entry = Entry {
+            value: Some(value),
+            when: self.ticks(deadline),
+            prev: None,
+            next: None,
+            place: Place::Vacant,
+        };
+        let index = match self.vacant.pop() {
+            Some(index) => {
+                self.entries[index] = entry;
+                index
+            }
+            _ => {
+                self.entries.push(entry);
+                self.entries.len() - 1
+            }
+        };
+        self.len += 1;
+        self.place(index);
+        self.wake_if_earlier(index);
+
+        WheelKey(index)
+    }
+
+    /// # Panics
+    /// If `key` doesn't refer to a value in the wheel.
+    pub(crate) fn reset_at(&mut self, key: &WheelKey, deadline: Instant) {
+        self.unlink(key.0);
+        self.entries[key.0].when = self.ticks(deadline);
+        self.place(key.0);
+        self.wake_if_earlier(key.0);
+    }
+
+    /// # Panics
+    /// If `key` doesn't refer to a value in the wheel.
+    pub(crate) fn remove(&mut self, key: &WheelKey) -> T {
+        self.unlink(key.0);
+        self.vacate(key.0)
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.vacant.clear();
+        self.len = 0;
+        for level in &mut self.levels {
+            level.occupied = 0;
+            level.slots = [List::default(); SLOTS];
+        }
+        self.expired = List::default();
+        self.sleep = None;
+    }
+
+    /// Polls for a value whose deadline has passed, returning `None` when empty.
+    pub(crate) fn poll_expired(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        if !self.waker.as_ref().is_some_and(|w| w.will_wake(cx.waker())) {
+            self.waker = Some(cx.waker().clone());
+        }
+
+        loop {
+            if let Some(index) = self.expired.head {
+                self.unlink(index);
+                return Poll::Ready(Some(self.vacate(index)));
+            }
+
+            let (level, slot, tick) = match self.next_expiration() {
+                Some(next) => next,
+                _ => {
+                    self.sleep = None;
+                    return Poll::Ready(None);
+                }
+            };
+
+            if tick > self.ticks_elapsed(self.clock.now()) {
+                if self.sleep.as_ref().map(|(armed, _)| *armed) != Some(tick) {
+                    let deadline = self.instant(tick);
+                    self.sleep = Some((tick, self.clock.sleep_until(deadline)));
+                }
+
+                let (_, sleep) = self.sleep.as_mut().unwrap();
+                ready!(sleep.as_mut().poll(cx));
+                self.sleep = None;
+            }
+
+            self.process(level, slot, tick);
+        }
+    }
+
+    /// Finds the next occupied slot, returning its level, index and the tick it starts at. All
+    /// values in a level expire before any in the levels above it.
+    fn next_expiration(&self) -> Option<(usize, usize, u64)> {
+        self.levels
+            .iter()
+            .enumerate()
+            .find(|(_, level)| level.occupied != 0)
+            .map(|(index, level)| {
+                let shift = 6 * index as u32;
+                // The slot at the current position is only ever occupied by values parked in the
+                // last level, that are a whole rotation away, so it is searched last
+                let position = (self.elapsed >> shift) as u32 % SLOTS as u32 + 1;
+                let slot = (position + level.occupied.rotate_right(position).trailing_zeros())
+                    as usize
+                    % SLOTS;
+
+                let slot_range = 1u64 << shift;
+                let level_range = slot_range * SLOTS as u64;
+                let mut tick = (self.elapsed & !(level_range - 1)) + slot as u64 * slot_range;
+                if tick <= self.elapsed {
+                    tick += level_range;
+                }
+
+                (index, slot, tick)
+            })
+    }
+
+    /// Moves time forward to `tick`, cascading the values of the slot starting at it down the levels,
+    /// or into the expired list if their deadline has been reached.
+    fn process(&mut self, level: usize, slot: usize, tick: u64) {
+        self.elapsed = tick;
+
+        let mut next = self.levels[level].slots[slot].head;
+        self.levels[level].slots[slot] = List::default();
+        self.levels[level].occupied &= !(1 << slot);
+        while let Some(index) = next {
+            next = self.entries[index].next;
+            self.place(index);
+        }
+    }
+
+    /// Links an unlinked entry into the list it belongs in, as of the ticks that have elapsed.
+    fn place(&mut self, index: usize) {
+        let when = self.entries[index].when;
+        let place = if when <= self.elapsed {
+            Place::Expired
+        } else {
+            let when = when.min(self.elapsed + MAX_TICKS - 1);
+            let significant = 63 - ((self.elapsed ^ when) | (SLOTS as u64 - 1)).leading_zeros();
+            let level = (significant as usize / 6).min(LEVELS - 1);
+
+            Place::Slot(level, (when >> (6 * level)) as usize % SLOTS)
+        };
+
+        let list = self.list(place);
+        let tail = list.tail;
+        list.tail = Some(index);
+        if tail.is_none() {
+            list.head = Some(index);
+        }
+        if let Place::Slot(level, slot) = place {
+            self.levels[level].occupied |= 1 << slot;
+        }
+        if let Some(tail) = tail {
+            self.entries[tail].next = Some(index);
+        }
+
+        let entry = &mut self.entries[index];
+        entry.prev = tail;
+        entry.next = None;
+        entry.place = place;
+    }
+
+    fn unlink(&mut self, index: usize) {
+        let Entry {
+            prev, next, place, ..
+        } = self.entries[index];
+
+        match prev {
+            Some(prev) => self.entries[prev].next = next,
+            _ => self.list(place).head = next,
+        }
+        match next {
+            Some(next) => self.entries[next].prev = prev,
+            _ => self.list(place).tail = prev,
+        }
+        if let Place::Slot(level, slot) = place {
+            if self.levels[level].slots[slot].head.is_none() {
+                self.levels[level].occupied &= !(1 << slot);
+            }
+        }
+    }
+
+    fn vacate(&mut self, index: usize) -> T {
+        let entry = &mut self.entries[index];
+        entry.place = Place::Vacant;
+        self.vacant.push(index);
+        self.len -= 1;
+
+        entry.value.take().unwrap()
+    }
+
+    fn list(&mut self, place: Place) -> &mut List {
+        match place {
+            Place::Expired => &mut self.expired,
+            Place::Slot(level, slot) => &mut self.levels[level].slots[slot],
+            Place::Vacant => unreachable!("vacant entries aren't linked"),
+        }
+    }
+
+    /// Deadline as a number of ticks since the start, rounded up so that nothing expires early.
+    fn ticks(&self, deadline: Instant) -> u64 {
+        let nanos = deadline.saturating_duration_since(self.start).as_nanos();
+        let tick = self.tick.as_nanos();
+
+        u64::try_from(nanos.div_ceil(tick)).unwrap_or(u64::MAX)
+    }
+
+    /// Number of whole ticks that have passed by `now`.
+    fn ticks_elapsed(&self, now: Instant) -> u64 {
+        let nanos = now.saturating_duration_since(self.start).as_nanos();
+
+        u64::try_from(nanos / self.tick.as_nanos()).unwrap_or(u64::MAX)
+    }
+
+    fn instant(&self, tick: u64) -> Instant {
+        let nanos = tick as u128 * self.tick.as_nanos();
+
+        let offset = u64::try_from(nanos / 1_000_000_000)
+            .map(|secs| Duration::new(secs, (nanos % 1_000_000_000) as u32))
+            .unwrap_or(Duration::MAX);
+
+        saturating_add(self.start, offset)
+    }
+
+    fn wake_if_earlier(&mut self, index: usize) {
+        let when = self.entries[index].when;
+        let earlier = match &self.sleep {
+            Some((armed, _)) => when < *armed,
+            _ => true,
+        };
+        if earlier {
+            if let Some(waker) = self.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+
+    use futures::task::noop_waker_ref;
+
+    use super::{Wheel, MAX_TICKS};
+    use crate::MockClock;
+
+    const TICK: Duration = Duration::from_millis(1);
+
+    fn wheel(clock: &MockClock) -> Wheel<u64> {
+        Wheel::with_capacity(Arc::new(clock.clone()), TICK, 0)
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    /// Takes every value that has expired by now, in the order they are yielded.
+    fn expired(wheel: &mut Wheel<u64>) -> Vec<u64> {
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut expired = vec![];
+        while let Poll::Ready(Some(value)) = wheel.poll_expired(&mut cx) {
+            expired.push(value);
+        }
+
+        expired
+    }
+
+    #[test]
+    fn cascades_down_the_levels() {
+        let clock = MockClock::new();
+        let mut wheel = wheel(&clock);
+        let start = wheel.start();
+        // One deadline within the range of each of the first four levels
+        for (value, deadline) in [(1, 5), (2, 100), (3, 5_000), (4, 300_000)] {
+            wheel.insert_at(value, start + ms(deadline));
+        }
+
+        let mut now = 0;
+        for (value, deadline) in [(1, 5), (2, 100), (3, 5_000), (4, 300_000)] {
+            clock.advance(ms(deadline - 1 - now));
+            assert!(expired(&mut wheel).is_empty(), "{value} expired early");
+            clock.advance(ms(1));
+            assert_eq!(expired(&mut wheel), [value]);
+            now = deadline;
+        }
+        assert_eq!(wheel.len, 0);
+    }
+
+    #[test]
+    fn wraps_around_the_levels() {
+        let clock = MockClock::new();
+        let mut wheel = wheel(&clock);
+        let start = wheel.start();
+        let mut deadlines = vec![];
+        // Keep inserting as time moves, so that slots are reused across rotations of every level
+        for now in 0..10_000 {
+            if now % 7 == 0 {
+                let deadline = now + 1 + now * 31 % 5_000;
+                wheel.insert_at(deadlines.len() as u64, start + ms(deadline));
+                deadlines.push(deadline);
+            }
+
+            clock.advance(ms(1));
+            for value in expired(&mut wheel) {
+                assert_eq!(
+                    deadlines[value as usize],
+                    now + 1,
+                    "{value} expired off its tick"
+                );
+            }
+        }
+
+        clock.advance(ms(5_000));
+        let rest = expired(&mut wheel);
+        assert!(rest.iter().all(|&value| deadlines[value as usize] > 10_000));
+        assert_eq!(wheel.len, 0);
+    }
+
+    #[test]
+    fn parks_deadlines_beyond_the_wheel() {
+        let clock = MockClock::new();
+        let mut wheel = wheel(&clock);
+        let start = wheel.start();
+        wheel.insert_at(1, start + ms(10));
+        clock.advance(ms(10));
+        assert_eq!(expired(&mut wheel), [1]);
+
+        // Parked in the slot of the last level that time is in, which must be searched last
+        let later = 1 << 31;
+        let deadline = MAX_TICKS * 3 + later + 500;
+        wheel.insert_at(2, start + ms(deadline));
+        wheel.insert_at(3, start + ms(later));
+        clock.advance(ms(later - 10));
+        assert_eq!(expired(&mut wheel), [3]);
+
+        // Each rotation of the last level only moves the parked value along
+        for _ in 0..3 {
+            clock.advance(ms(MAX_TICKS));
+            assert!(expired(&mut wheel).is_empty());
+        }
+        clock.advance(ms(499));
+        assert!(expired(&mut wheel).is_empty());
+        clock.advance(ms(1));
+        assert_eq!(expired(&mut wheel), [2]);
+    }
+
+    #[test]
+    fn removes_and_resets_pending_values() {
+        let clock = MockClock::new();
+        let mut wheel = wheel(&clock);
+        let start = wheel.start();
+        let a = wheel.insert_at(1, start + ms(10));
+        let b = wheel.insert_at(2, start + ms(100));
+        wheel.insert_at(3, start + ms(30));
+
+        wheel.reset_at(&a, start + ms(40));
+        assert_eq!(wheel.remove(&b), 2);
+        let b = wheel.insert_at(4, start + ms(5_000));
+        wheel.reset_at(&b, start + ms(20));
+
+        clock.advance(ms(10));
+        assert!(expired(&mut wheel).is_empty());
+        clock.advance(ms(10));
+        assert_eq!(expired(&mut wheel), [4]);
+        clock.advance(ms(10));
+        assert_eq!(expired(&mut wheel), [3]);
+        clock.advance(ms(10));
+        assert_eq!(expired(&mut wheel), [1]);
+    }
+
+    #[test]
+    fn removes_and_resets_expired_values() {
+        let clock = MockClock::new();
+        let mut wheel = wheel(&clock);
+        let start = wheel.start();
+        wheel.insert_at(1, start + ms(10));
+        let b = wheel.insert_at(2, start + ms(10));
+        let c = wheel.insert_at(3, start + ms(10));
+        clock.advance(ms(10));
+
+        // Only the first is yielded, leaving the others in the list of expired values
+        let mut cx = Context::from_waker(noop_waker_ref());
+        assert_eq!(wheel.poll_expired(&mut cx), Poll::Ready(Some(1)));
+        assert_eq!(wheel.remove(&b), 2);
+        wheel.reset_at(&c, start + ms(20));
+        assert!(expired(&mut wheel).is_empty());
+
+        clock.advance(ms(10));
+        assert_eq!(expired(&mut wheel), [3]);
+        assert_eq!(wheel.poll_expired(&mut cx), Poll::Ready(None));
+    }
+}